@@ -18,20 +18,29 @@ use warp::Filter;
 
 // mod cached_stats; // Temporarily disabled - requires ocra
 mod metrics_object_store;
+mod partitioned;
 mod routes;
 mod tables;
+mod time_travel;
 
 /// Command-line arguments for the query server
 #[derive(Parser, Debug, Clone)]
 #[command(name = "plano-serv")]
 struct Args {
     /// One or more table-specs in the form
-    ///   name=path[:col1,col2,...]
+    ///   name=path[:col1,col2,...][#bucket_col/buckets]
     ///
     /// e.g. --table-spec events=/data/parquet/events:year,month,day
+    /// or, for a table synced with `--bucket-by name --buckets 64`,
+    /// --table-spec 'vessels=/data/parquet/vessels:year#name/64'
     #[arg(long, short, action = clap::ArgAction::Append, required=true)]
     table_spec: Vec<String>,
 
+    /// Only read the files a table directory's `_SUCCESS` marker lists, so that queries see
+    /// each plano-sync run all at once, when it commits; tables without a marker are refused
+    #[arg(long)]
+    require_success: bool,
+
     /// Address to bind the server to
     #[arg(long, default_value = "127.0.0.1:8080")]
     bind: String,
@@ -117,7 +126,8 @@ async fn main() -> anyhow::Result<()> {
     //
     // These specifications enable datafusion to dynamically create glob specs and lazily read
     // partitioned filesets into in-memory tables to satisfy newly arriving queries.
-    register_tables(&ctx, &table_specs).await?;
+    time_travel::enable(&ctx)?;
+    register_tables(&ctx, &table_specs, args.require_success).await?;
 
     let routes = configure_routes(ctx, cache);
 
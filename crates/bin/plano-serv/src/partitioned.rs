@@ -0,0 +1,1289 @@
+///
+/// A table over a directory of Hive-style partitioned Parquet files, as written by plano-sync
+///
+use async_trait::async_trait;
+use chrono::DateTime;
+use datafusion::arrow::array::{Array, ArrayRef, BooleanArray, RecordBatch};
+use datafusion::arrow::compute::SortOptions;
+use datafusion::arrow::datatypes::{DataType, Field, FieldRef, Schema, SchemaRef};
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::catalog::Session;
+use datafusion::common::{DFSchema, ScalarValue, project_schema};
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::listing::helpers::expr_applicable_for_cols;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::physical_plan::{FileGroup, FileScanConfigBuilder};
+use datafusion::datasource::table_schema::TableSchema;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::utils::conjunction;
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::parquet::arrow::async_reader::{AsyncFileReader, ParquetObjectReader};
+use datafusion::physical_expr::expressions::Column;
+use datafusion::physical_expr::{LexOrdering, PhysicalSortExpr};
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::physical_plan::empty::EmptyExec;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore, ObjectStoreExt};
+use plano_core::bucket::{bucket_of, file_bucket};
+use plano_core::commit::{COMMIT_MARKER, Manifest};
+use plano_core::delta::{self, Action, LOG_DIR, Snapshot};
+use plano_core::partition::decode_value;
+use std::any::Any;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::debug;
+
+use crate::time_travel::AsOf;
+
+/// Parquet files under `url`, in `column=value/` directories for each partition column.
+///
+/// Unlike a `ListingTable`, which casts directory values to the column type as they are,
+/// values are unescaped first and `__HIVE_DEFAULT_PARTITION__` is read as null, so they come
+/// back exactly as they were written.  A partition column takes its type from the column of the
+/// same name in the files (which plano-sync keeps there), and is a string otherwise, e.g. for
+/// `year`.  Files and directories whose names start with `_` or `.` are ignored.
+///
+/// When the files are split into `buckets`, `=` and `IN` filters on the bucket column only
+/// read the files of the buckets their values hash to.  When every file records the same sort
+/// order (plano-sync's `--sort-by`), the scan declares it, so that `DataFusion` can merge the
+/// files rather than sort them; the order is read from the files each scan reads, as runs
+/// with another order or none may have added some since the table was registered.
+///
+/// With `committed_only`, a directory's files are only read once the `_SUCCESS` marker
+/// plano-sync writes when it commits a run lists them, so that queries never see a run that is
+/// still being written or the files it replaces.
+///
+/// A directory with a Delta `_delta_log` (plano-sync's `--delta-log`) is read from the log
+/// instead, whatever `committed_only` says: the schema, partition columns and files are those
+/// of its latest version, which each scan brings up to date by reading only the new commits.
+#[derive(Debug)]
+pub struct PartitionedTable {
+    url: ListingTableUrl,
+    /// The columns stored in the files, without the partition columns.
+    file_schema: SchemaRef,
+    partition_cols: Vec<FieldRef>,
+    /// The file columns followed by the partition columns.
+    schema: SchemaRef,
+    buckets: Option<Buckets>,
+    committed_only: bool,
+    log: Option<DeltaLog>,
+}
+
+/// A table's `_delta_log`, and its latest version read so far, or, when `frozen`, the version
+/// the table is read as of.
+#[derive(Debug)]
+struct DeltaLog {
+    store: Arc<dyn ObjectStore>,
+    dir: Path,
+    snapshot: Mutex<Snapshot>,
+    frozen: bool,
+}
+
+impl DeltaLog {
+    /// The log of the table at `url`, or `None` if it has no commits.
+    async fn open(store: Arc<dyn ObjectStore>, url: &ListingTableUrl) -> Result<Option<Self>> {
+        if !url.is_collection() {
+            return Ok(None);
+        }
+        let log = Self {
+            store,
+            dir: url.prefix().clone().join(LOG_DIR),
+            snapshot: Mutex::new(Snapshot::default()),
+            frozen: false,
+        };
+        if log.update().await?.version.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(log))
+    }
+
+    /// Applies the commits made since the snapshot was last brought up to date, and returns it.
+    async fn update(&self) -> Result<MutexGuard<'_, Snapshot>> {
+        let mut snapshot = self.snapshot.lock().await;
+        if self.frozen {
+            return Ok(snapshot);
+        }
+        let objects: Vec<ObjectMeta> = match snapshot.version {
+            Some(version) => {
+                let offset = self.dir.clone().join(delta::commit_file(version));
+                self.store
+                    .list_with_offset(Some(&self.dir), &offset)
+                    .try_collect()
+                    .await?
+            }
+            None => self.store.list(Some(&self.dir)).try_collect().await?,
+        };
+        let mut versions: Vec<u64> = objects
+            .iter()
+            .filter_map(|object| object.location.filename().and_then(delta::commit_version))
+            .collect();
+        versions.sort_unstable();
+        for version in versions {
+            // a commit is applied only after the one before it
+            if version != snapshot.version.map_or(0, |last| last + 1) {
+                break;
+            }
+            snapshot.apply(version, self.read(version).await?);
+        }
+        self.check_readable(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// The log of the table at `url` frozen at the version that was current `at`.
+    async fn at(&self, url: &ListingTableUrl, at: &AsOf) -> Result<Self> {
+        let objects: Vec<ObjectMeta> = self.store.list(Some(&self.dir)).try_collect().await?;
+        let mut commits: Vec<(u64, i64)> = objects
+            .iter()
+            .filter_map(|object| {
+                let version = object.location.filename().and_then(delta::commit_version)?;
+                Some((version, object.last_modified.timestamp_millis()))
+            })
+            .collect();
+        commits.sort_unstable();
+        let mut snapshot = Snapshot::default();
+        let mut found = false;
+        for (version, modified) in commits {
+            if version != snapshot.version.map_or(0, |last| last + 1) {
+                break;
+            }
+            let actions = self.read(version).await?;
+            let info = actions
+                .iter()
+                .find_map(|action| action.commit_info.clone())
+                .unwrap_or_default();
+            match at {
+                AsOf::Version(last) if version > *last => break,
+                AsOf::Time(time) if info.timestamp.unwrap_or(modified) > *time => break,
+                _ => {}
+            }
+            snapshot.apply(version, actions);
+            if let AsOf::Run(run) = at
+                && info
+                    .txn_id
+                    .is_some_and(|id| id.replace('-', "").eq_ignore_ascii_case(run))
+            {
+                found = true;
+                break;
+            }
+        }
+        let found = match at {
+            AsOf::Version(version) => snapshot.version == Some(*version),
+            AsOf::Time(_) => snapshot.version.is_some(),
+            AsOf::Run(_) => found,
+        };
+        if !found {
+            return Err(DataFusionError::Plan(format!(
+                "{url} has no version as of {at}"
+            )));
+        }
+        self.check_readable(&snapshot)?;
+        Ok(Self {
+            store: self.store.clone(),
+            dir: self.dir.clone(),
+            snapshot: Mutex::new(snapshot),
+            frozen: true,
+        })
+    }
+
+    /// The actions of commit `version`.
+    async fn read(&self, version: u64) -> Result<Vec<Action>> {
+        let path = self.dir.clone().join(delta::commit_file(version));
+        let contents = self.store.get(&path).await?.bytes().await?;
+        delta::parse_commit(&contents).map_err(|e| DataFusionError::External(e.into()))
+    }
+
+    fn check_readable(&self, snapshot: &Snapshot) -> Result<()> {
+        snapshot
+            .check_readable()
+            .map_err(|e| DataFusionError::NotImplemented(format!("{}: {e}", self.dir)))
+    }
+
+    /// The schema of the table's columns, and which of them it is partitioned by, which must
+    /// be `partitions` unless that is empty.
+    async fn table_schema(
+        &self,
+        url: &ListingTableUrl,
+        partitions: &[String],
+    ) -> Result<(SchemaRef, Vec<String>)> {
+        let Some(metadata) = self.snapshot.lock().await.metadata.clone() else {
+            return Err(DataFusionError::Plan(format!(
+                "{} has no table metadata",
+                self.dir
+            )));
+        };
+        if !partitions.is_empty() && partitions != metadata.partition_columns {
+            return Err(DataFusionError::Plan(format!(
+                "{url} is partitioned by {} according to its {LOG_DIR}, not {}",
+                metadata.partition_columns.join(","),
+                partitions.join(",")
+            )));
+        }
+        let schema = delta::arrow_schema(&metadata.schema_string)
+            .map_err(|e| DataFusionError::External(e.into()))?;
+        Ok((Arc::new(schema), metadata.partition_columns))
+    }
+}
+
+/// The column plano-sync's `--bucket-by` split each partition's files by, and the number of
+/// `--buckets`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Buckets {
+    pub column: String,
+    pub count: u32,
+}
+
+impl Buckets {
+    /// Parses `column/count`, e.g. `name/64`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid buckets `{s}`; expected column/count");
+        let (column, count) = s.rsplit_once('/').ok_or_else(invalid)?;
+        let count = count
+            .parse::<u32>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(invalid)?;
+        if column.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            column: column.to_string(),
+            count,
+        })
+    }
+}
+
+impl PartitionedTable {
+    /// Reads the schema of the Parquet files under `url`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the files cannot be listed or their schemas read, or, with
+    /// `committed_only`, the directory has no commit marker.
+    pub async fn try_new(
+        state: &dyn Session,
+        url: ListingTableUrl,
+        partitions: &[String],
+        buckets: Option<Buckets>,
+        committed_only: bool,
+    ) -> Result<Self> {
+        let store = state.runtime_env().object_store(&url)?;
+        let log = DeltaLog::open(store.clone(), &url).await?;
+        let (stored, partitions) = if let Some(log) = &log {
+            log.table_schema(&url, partitions).await?
+        } else {
+            let objects = list_objects(state, &url, committed_only).await?;
+            let stored = ParquetFormat::default()
+                .infer_schema(state, &store, &objects)
+                .await?;
+            (stored, partitions.to_vec())
+        };
+        Self::with_schema(url, &stored, &partitions, buckets, committed_only, log)
+    }
+
+    /// The table as it was `at`, which is read from its log.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the table has no log, or the log has no version as of `at`.
+    pub async fn as_of(&self, at: &AsOf) -> Result<Self> {
+        let Some(log) = &self.log else {
+            return Err(DataFusionError::Plan(format!(
+                "{} has no {LOG_DIR} to read earlier versions from; sync it with --delta-log",
+                self.url
+            )));
+        };
+        let log = log.at(&self.url, at).await?;
+        let (stored, partitions) = log.table_schema(&self.url, &[]).await?;
+        Self::with_schema(
+            self.url.clone(),
+            &stored,
+            &partitions,
+            self.buckets.clone(),
+            false,
+            Some(log),
+        )
+    }
+
+    /// A table of the `stored` columns, of which `partitions` are read from the directory
+    /// names.
+    fn with_schema(
+        url: ListingTableUrl,
+        stored: &Schema,
+        partitions: &[String],
+        buckets: Option<Buckets>,
+        committed_only: bool,
+        log: Option<DeltaLog>,
+    ) -> Result<Self> {
+        let partition_cols: Vec<FieldRef> = partitions
+            .iter()
+            .map(|name| {
+                let data_type = stored
+                    .field_with_name(name)
+                    .map_or(DataType::Utf8, |field| field.data_type().clone());
+                Arc::new(Field::new(name, data_type, true))
+            })
+            .collect();
+        // the partition columns are read from the directory names rather than the files
+        let file_fields: Vec<FieldRef> = stored
+            .fields()
+            .iter()
+            .filter(|field| !partitions.contains(field.name()))
+            .cloned()
+            .collect();
+        let file_schema = Arc::new(Schema::new(file_fields));
+        let schema = Arc::new(Schema::new(
+            file_schema
+                .fields()
+                .iter()
+                .chain(&partition_cols)
+                .cloned()
+                .collect::<Vec<_>>(),
+        ));
+        if let Some(buckets) = &buckets
+            && schema.field_with_name(&buckets.column).is_err()
+        {
+            return Err(DataFusionError::Plan(format!(
+                "Bucket column `{}` is not in the table",
+                buckets.column
+            )));
+        }
+        Ok(Self {
+            url,
+            file_schema,
+            partition_cols,
+            schema,
+            buckets,
+            committed_only,
+            log,
+        })
+    }
+
+    /// The order of the rows in every one of `files`, from the sorting columns recorded in each
+    /// row group, or `None` if a file records none or they differ.
+    async fn file_ordering(
+        &self,
+        store: &Arc<dyn ObjectStore>,
+        files: &[PartitionedFile],
+    ) -> Result<Option<LexOrdering>> {
+        let mut common: Option<Vec<(String, SortOptions)>> = None;
+        for file in files {
+            let object = &file.object_meta;
+            let mut reader = ParquetObjectReader::new(store.clone(), object.location.clone())
+                .with_file_size(object.size);
+            let metadata = reader.get_metadata(None).await?;
+            let columns = metadata.file_metadata().schema_descr();
+            for row_group in metadata.row_groups() {
+                let Some(sorting) = row_group.sorting_columns() else {
+                    return Ok(None);
+                };
+                let mut order = Vec::with_capacity(sorting.len());
+                for column in sorting {
+                    let leaf = usize::try_from(column.column_idx)
+                        .ok()
+                        .filter(|leaf| *leaf < columns.num_columns());
+                    // only top-level columns can be named in an ordering
+                    let Some(name) = leaf
+                        .map(|leaf| columns.column(leaf).path().parts().to_vec())
+                        .and_then(|parts| <[String; 1]>::try_from(parts).ok())
+                    else {
+                        return Ok(None);
+                    };
+                    let [name] = name;
+                    let options = SortOptions {
+                        descending: column.descending,
+                        nulls_first: column.nulls_first,
+                    };
+                    order.push((name, options));
+                }
+                match &common {
+                    Some(common) if *common != order => return Ok(None),
+                    Some(_) => {}
+                    None => common = Some(order),
+                }
+            }
+        }
+        let Some(common) = common else {
+            return Ok(None);
+        };
+        let mut exprs = Vec::with_capacity(common.len());
+        for (name, options) in common {
+            let Ok(index) = self.schema.index_of(&name) else {
+                return Ok(None);
+            };
+            exprs.push(PhysicalSortExpr::new(
+                Arc::new(Column::new(&name, index)),
+                options,
+            ));
+        }
+        Ok(LexOrdering::new(exprs))
+    }
+
+    fn partition_names(&self) -> Vec<&str> {
+        self.partition_cols
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect()
+    }
+
+    /// Every data file of the table, with its partition values.
+    async fn list_files(&self, state: &dyn Session) -> Result<Vec<PartitionedFile>> {
+        if let Some(files) = self.log_files().await? {
+            return Ok(files);
+        }
+        let mut files = Vec::new();
+        for object in list_objects(state, &self.url, self.committed_only).await? {
+            let parts = relative_parts(&self.url, &object.location);
+            let dirs = parts.split_last().map_or(&[][..], |(_, dirs)| dirs);
+            if let Some(values) = self.partition_values(dirs)? {
+                files.push(PartitionedFile::from(object).with_partition_values(values));
+            } else {
+                debug!("Ignoring {} outside the partition layout", object.location);
+            }
+        }
+        Ok(files)
+    }
+
+    /// The files in the table's log, brought up to date, or `None` if it has no log.
+    async fn log_files(&self) -> Result<Option<Vec<PartitionedFile>>> {
+        let Some(log) = &self.log else {
+            return Ok(None);
+        };
+        let snapshot = log.update().await?;
+        snapshot
+            .files
+            .iter()
+            .map(|(path, add)| {
+                let object = ObjectMeta {
+                    location: self
+                        .url
+                        .prefix()
+                        .parts()
+                        .chain(Path::parse(path)?.parts())
+                        .collect(),
+                    last_modified: DateTime::from_timestamp_millis(add.modification_time)
+                        .unwrap_or_default(),
+                    size: add.size,
+                    e_tag: None,
+                    version: None,
+                };
+                let values = self
+                    .partition_cols
+                    .iter()
+                    .map(|field| match add.partition_values.get(field.name()) {
+                        Some(Some(value)) => {
+                            ScalarValue::try_from_string(value.clone(), field.data_type())
+                        }
+                        _ => ScalarValue::try_from(field.data_type()),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(PartitionedFile::from(object).with_partition_values(values))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+
+    /// The values of the partition columns given by the directories `dirs`, or `None` if they
+    /// do not follow the table's layout.
+    fn partition_values(&self, dirs: &[String]) -> Result<Option<Vec<ScalarValue>>> {
+        if dirs.len() != self.partition_cols.len() {
+            return Ok(None);
+        }
+        let mut values = Vec::with_capacity(dirs.len());
+        for (dir, field) in dirs.iter().zip(&self.partition_cols) {
+            let Some((name, value)) = dir.split_once('=') else {
+                return Ok(None);
+            };
+            if name != field.name() {
+                return Ok(None);
+            }
+            let value = match decode_value(value) {
+                Some(value) => ScalarValue::try_from_string(value, field.data_type())?,
+                None => ScalarValue::try_from(field.data_type())?,
+            };
+            values.push(value);
+        }
+        Ok(Some(values))
+    }
+
+    /// The files whose partition values satisfy every filter in `filters`, which may only
+    /// refer to partition columns.
+    fn prune(
+        &self,
+        state: &dyn Session,
+        files: Vec<PartitionedFile>,
+        filters: &[Expr],
+    ) -> Result<Vec<PartitionedFile>> {
+        let Some(predicate) = conjunction(filters.iter().cloned()) else {
+            return Ok(files);
+        };
+        if files.is_empty() {
+            return Ok(files);
+        }
+        let schema = Arc::new(Schema::new(self.partition_cols.clone()));
+        let columns = (0..self.partition_cols.len())
+            .map(|i| {
+                ScalarValue::iter_to_array(
+                    files.iter().map(|file| file.partition_values[i].clone()),
+                )
+            })
+            .collect::<Result<Vec<ArrayRef>>>()?;
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        let predicate =
+            state.create_physical_expr(predicate, &DFSchema::try_from(schema.as_ref().clone())?)?;
+        let matches = predicate.evaluate(&batch)?.into_array(batch.num_rows())?;
+        let matches = matches
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .ok_or_else(|| DataFusionError::Internal("Partition filter is not boolean".into()))?
+            .clone();
+        Ok(files
+            .into_iter()
+            .zip(matches.iter())
+            .filter_map(|(file, keep)| keep.unwrap_or(false).then_some(file))
+            .collect())
+    }
+
+    /// The buckets that can hold rows matching every filter in `filters`, or `None` if the
+    /// filters do not narrow them down.
+    fn matching_buckets(&self, filters: &[Expr]) -> Option<HashSet<u32>> {
+        let buckets = self.buckets.as_ref()?;
+        let field = self.schema.field_with_name(&buckets.column).ok()?;
+        let mut matching: Option<HashSet<u32>> = None;
+        for filter in filters {
+            let Some(values) = equal_values(filter, &buckets.column) else {
+                continue;
+            };
+            // a value that cannot be hashed as plano-sync did leaves every bucket in play
+            let Some(hashed) = values
+                .into_iter()
+                .map(|value| {
+                    let value = bucket_value(value, field.data_type()).ok()?;
+                    Some(bucket_of(value.as_deref(), buckets.count))
+                })
+                .collect::<Option<HashSet<u32>>>()
+            else {
+                continue;
+            };
+            matching = Some(match matching {
+                Some(matching) => matching.intersection(&hashed).copied().collect(),
+                None => hashed,
+            });
+        }
+        matching
+    }
+}
+
+/// The Parquet files under `url`, leaving out those in hidden (`_` or `.`) directories.
+///
+/// With `committed_only`, a directory's files are also left out until its `_SUCCESS` marker
+/// lists them, and the store is listed on every call rather than through the session's cache,
+/// so that each query reads the latest commit.
+async fn list_objects(
+    state: &dyn Session,
+    url: &ListingTableUrl,
+    committed_only: bool,
+) -> Result<Vec<ObjectMeta>> {
+    let store = state.runtime_env().object_store(url)?;
+    // a single file is committed by being there
+    let committed_only = committed_only && url.is_collection();
+    let (objects, committed): (Vec<ObjectMeta>, _) = if committed_only {
+        let committed = committed_files(store.as_ref(), url).await?;
+        (
+            store.list(Some(url.prefix())).try_collect().await?,
+            committed,
+        )
+    } else {
+        let objects = url
+            .list_all_files(state, store.as_ref(), ".parquet")
+            .await?
+            .try_collect()
+            .await?;
+        (objects, None)
+    };
+    Ok(objects
+        .into_iter()
+        .filter(|object| {
+            let parts = relative_parts(url, &object.location);
+            object.location.extension() == Some("parquet")
+                && !parts.iter().any(|part| part.starts_with(['_', '.']))
+                && committed
+                    .as_ref()
+                    .is_none_or(|files| files.contains(&parts.join("/")))
+        })
+        .collect())
+}
+
+/// The files the `_SUCCESS` marker under `url` commits, relative to it, or `None` if it
+/// commits every file.
+async fn committed_files(
+    store: &dyn ObjectStore,
+    url: &ListingTableUrl,
+) -> Result<Option<HashSet<String>>> {
+    let marker = url.prefix().clone().join(COMMIT_MARKER);
+    let contents = match store.get(&marker).await {
+        Ok(result) => result.bytes().await?,
+        Err(object_store::Error::NotFound { .. }) => {
+            return Err(DataFusionError::Plan(format!(
+                "{url} has no {COMMIT_MARKER} marker; nothing has been committed to it yet"
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if contents.is_empty() {
+        return Ok(None);
+    }
+    let manifest: Manifest =
+        serde_json::from_slice(&contents).map_err(|e| DataFusionError::External(Box::new(e)))?;
+    Ok(Some(manifest.files.into_iter().collect()))
+}
+
+/// The directories and name of the file at `location` under `url`, or nothing when the table
+/// is a single file.
+fn relative_parts(url: &ListingTableUrl, location: &Path) -> Vec<String> {
+    location
+        .prefix_match(url.prefix())
+        .map(|parts| parts.map(|part| part.as_ref().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The values `filter` restricts `column` to, if it is `column = value`,
+/// `column IN (values...)`, or an `OR` of these, which short `IN` lists are rewritten to.
+fn equal_values<'a>(filter: &'a Expr, column: &str) -> Option<Vec<&'a ScalarValue>> {
+    let is_column = |expr: &Expr| matches!(expr, Expr::Column(c) if c.name == column);
+    match filter {
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        }) => {
+            let mut values = equal_values(left, column)?;
+            values.extend(equal_values(right, column)?);
+            Some(values)
+        }
+        Expr::BinaryExpr(BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        }) => match (left.as_ref(), right.as_ref()) {
+            (expr, Expr::Literal(value, _)) | (Expr::Literal(value, _), expr)
+                if is_column(expr) =>
+            {
+                Some(vec![value])
+            }
+            _ => None,
+        },
+        Expr::InList(InList {
+            expr,
+            list,
+            negated: false,
+        }) if is_column(expr) => list
+            .iter()
+            .map(|item| match item {
+                Expr::Literal(value, _) => Some(value),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// `value` as plano-sync hashes it: cast to the column's type and formatted as it would be in a
+/// directory name, or `None` if it is null.
+fn bucket_value(value: &ScalarValue, data_type: &DataType) -> Result<Option<String>> {
+    let array = value.cast_to(data_type)?.to_array()?;
+    if array.is_null(0) {
+        return Ok(None);
+    }
+    let formatter = ArrayFormatter::try_new(array.as_ref(), &FormatOptions::default())?;
+    Ok(Some(formatter.value(0).to_string()))
+}
+
+#[async_trait]
+impl TableProvider for PartitionedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> Result<Vec<TableProviderFilterPushDown>> {
+        let names = self.partition_names();
+        Ok(filters
+            .iter()
+            .map(|filter| {
+                // filters on partition columns are applied in full by leaving out files
+                if expr_applicable_for_cols(&names, filter) {
+                    TableProviderFilterPushDown::Exact
+                } else {
+                    TableProviderFilterPushDown::Inexact
+                }
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let names = self.partition_names();
+        let (partition_filters, other_filters): (Vec<Expr>, Vec<Expr>) = filters
+            .iter()
+            .cloned()
+            .partition(|filter| expr_applicable_for_cols(&names, filter));
+
+        let files = self.list_files(state).await?;
+        let mut files = self.prune(state, files, &partition_filters)?;
+        if let Some(buckets) = self.matching_buckets(filters) {
+            // files without a bucket in their name may hold any value
+            files.retain(|file| {
+                file.object_meta
+                    .location
+                    .filename()
+                    .and_then(file_bucket)
+                    .is_none_or(|bucket| buckets.contains(&bucket))
+            });
+        }
+        if files.is_empty() {
+            let schema = project_schema(&self.schema, projection)?;
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+
+        let store = state.runtime_env().object_store(&self.url)?;
+        let ordering = self.file_ordering(&store, &files).await?;
+        let format = ParquetFormat::default();
+        let table_schema = TableSchema::new(self.file_schema.clone(), self.partition_cols.clone());
+        let mut builder =
+            FileScanConfigBuilder::new(self.url.object_store(), format.file_source(table_schema));
+        if let Some(ordering) = ordering {
+            // files are each in order, but not in order of one another
+            builder = builder
+                .with_file_groups(
+                    files
+                        .into_iter()
+                        .map(|file| FileGroup::new(vec![file]))
+                        .collect(),
+                )
+                .with_output_ordering(vec![ordering]);
+        } else {
+            builder = builder.with_file_group(FileGroup::new(files));
+        }
+        let config = builder
+            .with_projection_indices(projection.cloned())?
+            // a limit only holds once every row has been read when rows are filtered
+            .with_limit(if other_filters.is_empty() {
+                limit
+            } else {
+                None
+            })
+            .build();
+        format.create_physical_plan(state, config).await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int32Array, StringArray};
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::parquet::file::metadata::SortingColumn;
+    use datafusion::parquet::file::properties::WriterProperties;
+    use datafusion::prelude::{SessionContext, col, lit};
+    use plano_core::partition::encode_value;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    /// Writes one file per `(region, shard)`, with the partition columns kept in the file.
+    fn write_table(dir: &std::path::Path, rows: &[(Option<&str>, Option<i32>)]) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("region", DataType::Utf8, true),
+            Field::new("shard", DataType::Int32, true),
+            Field::new("n", DataType::Int32, false),
+        ]));
+        for (i, (region, shard)) in rows.iter().enumerate() {
+            let shard_dir = encode_value(shard.map(|s| s.to_string()).as_deref());
+            let path = dir
+                .join(format!("region={}", encode_value(*region)))
+                .join(format!("shard={shard_dir}"));
+            fs::create_dir_all(&path).unwrap();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(vec![*region])),
+                    Arc::new(Int32Array::from(vec![*shard])),
+                    Arc::new(Int32Array::from(vec![i32::try_from(i).unwrap()])),
+                ],
+            )
+            .unwrap();
+            let file = File::create(path.join("part-00000.parquet")).unwrap();
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), None).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+        }
+    }
+
+    /// Writes a file with `name` `x` in every row to `path`.
+    fn write_file(path: &std::path::Path, n: &[i32]) {
+        write_sorted_file(path, n, None);
+    }
+
+    /// Writes a file whose row groups record `sorting` as their order.
+    fn write_sorted_file(path: &std::path::Path, n: &[i32], sorting: Option<SortingColumn>) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("n", DataType::Int32, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["x"; n.len()])),
+                Arc::new(Int32Array::from(n.to_vec())),
+            ],
+        )
+        .unwrap();
+        let props = WriterProperties::builder()
+            .set_sorting_columns(sorting.map(|sorting| vec![sorting]))
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    async fn query(ctx: &SessionContext, sql: &str) -> Vec<String> {
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let text = datafusion::arrow::util::pretty::pretty_format_batches(&batches)
+            .unwrap()
+            .to_string();
+        text.lines()
+            .skip(3)
+            .filter(|line| !line.starts_with('+'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_values() {
+        let dir = tempdir().unwrap();
+        write_table(
+            dir.path(),
+            &[
+                (Some("eu/west=1"), Some(-3)),
+                (None, Some(7)),
+                (Some("100%"), None),
+                (Some("us"), Some(7)),
+            ],
+        );
+        fs::write(dir.path().join("_sync_state.json"), "{}").unwrap();
+        fs::create_dir(dir.path().join("stray")).unwrap();
+        fs::write(dir.path().join("stray/part-00000.parquet"), "").unwrap();
+
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let partitions = ["region".to_string(), "shard".to_string()];
+        let table = PartitionedTable::try_new(&ctx.state(), url, &partitions, None, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            table.schema().field_with_name("shard").unwrap().data_type(),
+            &DataType::Int32
+        );
+        ctx.register_table("t", Arc::new(table)).unwrap();
+
+        assert_eq!(
+            query(&ctx, "SELECT region, shard, n FROM t ORDER BY n").await,
+            [
+                "| eu/west=1 | -3    | 0 |",
+                "|           | 7     | 1 |",
+                "| 100%      |       | 2 |",
+                "| us        | 7     | 3 |",
+            ]
+        );
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE shard = 7 AND region IS NULL").await,
+            ["| 1 |"]
+        );
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE region = 'eu/west=1'").await,
+            ["| 0 |"]
+        );
+        assert!(
+            query(&ctx, "SELECT n FROM t WHERE shard > 100")
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bucket_pruning() {
+        let dir = tempdir().unwrap();
+        // every file holds `x`, so the rows returned show which files were read
+        for bucket in 0..4 {
+            let name = format!(
+                "part-00000-0191{}",
+                plano_core::bucket::bucket_suffix(bucket)
+            );
+            write_file(&dir.path().join(name), &[i32::try_from(bucket).unwrap()]);
+        }
+        write_file(&dir.path().join("part-00000.parquet"), &[99]);
+
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let buckets = Buckets::parse("name/4").unwrap();
+        let table = PartitionedTable::try_new(&ctx.state(), url, &[], Some(buckets), false)
+            .await
+            .unwrap();
+        let x = col("name").eq(lit("x"));
+        assert_eq!(
+            table.matching_buckets(std::slice::from_ref(&x)),
+            Some(HashSet::from([3]))
+        );
+        let y = col("name").in_list(vec![lit("y")], false);
+        let x_or_y = col("name").eq(lit("x")).or(col("name").eq(lit("y")));
+        assert_eq!(
+            table.matching_buckets(&[x_or_y]),
+            Some(HashSet::from([0, 3]))
+        );
+        assert_eq!(table.matching_buckets(&[x, y]), Some(HashSet::new()));
+        let other = col("n").eq(lit(1));
+        assert_eq!(table.matching_buckets(&[other]), None);
+        ctx.register_table("t", Arc::new(table)).unwrap();
+
+        let row = |value: &str| format!("| {:<2} |", bucket_of(Some(value), 4));
+        assert_eq!(query(&ctx, "SELECT n FROM t ORDER BY n").await.len(), 5);
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE name = 'x' ORDER BY n").await,
+            [row("x"), "| 99 |".to_string()]
+        );
+        let mut expected = vec![row("x"), row("y"), "| 99 |".to_string()];
+        expected.sort();
+        expected.dedup();
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE name IN ('x', 'y') ORDER BY n").await,
+            expected
+        );
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE n = 2 OR name = 'x' ORDER BY n")
+                .await
+                .len(),
+            5
+        );
+
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let missing = Buckets::parse("vessel/4").unwrap();
+        assert!(
+            PartitionedTable::try_new(&ctx.state(), url, &[], Some(missing), false)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sort_order() {
+        let dir = tempdir().unwrap();
+        let by_n = SortingColumn {
+            column_idx: 1,
+            descending: false,
+            nulls_first: false,
+        };
+        write_sorted_file(
+            &dir.path().join("part-00000-a.parquet"),
+            &[1, 4, 6],
+            Some(by_n.clone()),
+        );
+        write_sorted_file(
+            &dir.path().join("part-00000-b.parquet"),
+            &[2, 3, 5],
+            Some(by_n),
+        );
+        let commit = |files: &[&str]| {
+            let manifest = Manifest {
+                version: 1,
+                run_id: "run".to_string(),
+                files: files.iter().map(ToString::to_string).collect(),
+            };
+            fs::write(
+                dir.path().join(COMMIT_MARKER),
+                serde_json::to_vec(&manifest).unwrap(),
+            )
+            .unwrap();
+        };
+        commit(&["part-00000-a.parquet", "part-00000-b.parquet"]);
+
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let table = PartitionedTable::try_new(&ctx.state(), url.clone(), &[], None, true)
+            .await
+            .unwrap();
+        let store = ctx.state().runtime_env().object_store(&url).unwrap();
+        let files = table.list_files(&ctx.state()).await.unwrap();
+        assert_eq!(
+            table
+                .file_ordering(&store, &files)
+                .await
+                .unwrap()
+                .unwrap()
+                .to_string(),
+            "n@1 ASC NULLS LAST"
+        );
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        let plan = query(&ctx, "EXPLAIN SELECT n FROM t ORDER BY n")
+            .await
+            .join("\n");
+        assert!(plan.contains("SortPreservingMergeExec"), "{plan}");
+        assert!(!plan.contains("SortExec"), "{plan}");
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t ORDER BY n").await,
+            ["| 1 |", "| 2 |", "| 3 |", "| 4 |", "| 5 |", "| 6 |"]
+        );
+
+        // a run that adds a file in another order, or none, leaves the table unordered
+        write_file(&dir.path().join("part-00001-c.parquet"), &[0]);
+        commit(&[
+            "part-00000-a.parquet",
+            "part-00000-b.parquet",
+            "part-00001-c.parquet",
+        ]);
+        let plan = query(&ctx, "EXPLAIN SELECT n FROM t ORDER BY n")
+            .await
+            .join("\n");
+        assert!(plan.contains("SortExec"), "{plan}");
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t ORDER BY n").await,
+            [
+                "| 0 |", "| 1 |", "| 2 |", "| 3 |", "| 4 |", "| 5 |", "| 6 |"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_committed_only() {
+        let dir = tempdir().unwrap();
+        write_file(&dir.path().join("part-00000-a.parquet"), &[1]);
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let error = PartitionedTable::try_new(&ctx.state(), url.clone(), &[], None, true)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("no _SUCCESS marker"), "{error}");
+
+        let commit = |files: &[&str]| {
+            let manifest = Manifest {
+                version: 1,
+                run_id: "run".to_string(),
+                files: files.iter().map(ToString::to_string).collect(),
+            };
+            fs::write(
+                dir.path().join(COMMIT_MARKER),
+                serde_json::to_vec(&manifest).unwrap(),
+            )
+            .unwrap();
+        };
+        commit(&["part-00000-a.parquet"]);
+        // a run that has not been committed yet
+        write_file(&dir.path().join("part-00001-b.parquet"), &[2]);
+        let table = PartitionedTable::try_new(&ctx.state(), url, &[], None, true)
+            .await
+            .unwrap();
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        assert_eq!(query(&ctx, "SELECT n FROM t").await, ["| 1 |"]);
+
+        // each query reads the latest commit
+        commit(&["part-00001-b.parquet"]);
+        fs::remove_file(dir.path().join("part-00000-a.parquet")).unwrap();
+        assert_eq!(query(&ctx, "SELECT n FROM t").await, ["| 2 |"]);
+        write_file(&dir.path().join("part-00002-c.parquet"), &[3]);
+        commit(&["part-00001-b.parquet", "part-00002-c.parquet"]);
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t ORDER BY n").await,
+            ["| 2 |", "| 3 |"]
+        );
+
+        // an empty marker, as Hadoop writes, commits every file
+        fs::write(dir.path().join(COMMIT_MARKER), "").unwrap();
+        write_file(&dir.path().join("part-00003-d.parquet"), &[4]);
+        assert_eq!(query(&ctx, "SELECT n FROM t").await.len(), 3);
+    }
+
+    /// Writes a table and the two commits of its `_delta_log`, the first adding the `eu/west`
+    /// file as run 1 at time 1000 and the second the `us` file as run 2 at time 2000, and
+    /// returns the second for the caller to write.
+    fn write_logged_table(dir: &std::path::Path) -> Vec<delta::Action> {
+        write_table(dir, &[(Some("eu/west"), Some(1)), (Some("us"), None)]);
+        fs::create_dir(dir.join(LOG_DIR)).unwrap();
+        let add = |path: &str, region: &str, shard: Option<&str>| {
+            let size = fs::metadata(dir.join(path)).unwrap().len();
+            delta::Action {
+                add: Some(delta::Add {
+                    path: delta::encode_path(path),
+                    partition_values: [
+                        ("region".to_string(), Some(region.to_string())),
+                        ("shard".to_string(), shard.map(ToString::to_string)),
+                    ]
+                    .into(),
+                    size,
+                    modification_time: 0,
+                    data_change: true,
+                }),
+                ..Default::default()
+            }
+        };
+        let info = |timestamp: i64, run: &str| delta::Action {
+            commit_info: Some(delta::CommitInfo {
+                timestamp: Some(timestamp),
+                txn_id: Some(run.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let schema = Schema::new(vec![
+            Field::new("region", DataType::Utf8, true),
+            Field::new("shard", DataType::Int32, true),
+            Field::new("n", DataType::Int32, false),
+        ]);
+        let first = [
+            info(1_000, "0199a1b2-0000-7000-8000-000000000001"),
+            delta::Action {
+                protocol: Some(delta::Protocol::for_schema(&schema)),
+                ..Default::default()
+            },
+            delta::Action {
+                meta_data: Some(delta::Metadata {
+                    id: "t".to_string(),
+                    format: delta::Format::default(),
+                    schema_string: delta::delta_schema(&schema).unwrap(),
+                    partition_columns: vec!["region".to_string(), "shard".to_string()],
+                    configuration: [].into(),
+                    created_time: None,
+                }),
+                ..Default::default()
+            },
+            add(
+                "region=eu%2Fwest/shard=1/part-00000.parquet",
+                "eu/west",
+                Some("1"),
+            ),
+        ];
+        write_commit(dir, 0, &first);
+        vec![
+            info(2_000, "0199a1b2-0000-7000-8000-000000000002"),
+            add(
+                "region=us/shard=__HIVE_DEFAULT_PARTITION__/part-00000.parquet",
+                "us",
+                None,
+            ),
+        ]
+    }
+
+    fn write_commit(dir: &std::path::Path, version: u64, actions: &[delta::Action]) {
+        fs::write(
+            dir.join(LOG_DIR).join(delta::commit_file(version)),
+            delta::format_commit(actions).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delta_log() {
+        let dir = tempdir().unwrap();
+        let second = write_logged_table(dir.path());
+
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let wrong = ["shard".to_string()];
+        let error = PartitionedTable::try_new(&ctx.state(), url.clone(), &wrong, None, false)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("partitioned by region,shard"),
+            "{error}"
+        );
+
+        // the partitions come from the log, and files it does not list are not read
+        let table = PartitionedTable::try_new(&ctx.state(), url, &[], None, true)
+            .await
+            .unwrap();
+        assert_eq!(table.partition_names(), ["region", "shard"]);
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        assert_eq!(
+            query(&ctx, "SELECT region, shard, n FROM t").await,
+            ["| eu/west | 1     | 0 |"]
+        );
+
+        // each scan reads the commits made since the last
+        write_commit(dir.path(), 1, &second);
+        assert_eq!(
+            query(&ctx, "SELECT n FROM t WHERE shard IS NULL").await,
+            ["| 1 |"]
+        );
+        assert_eq!(query(&ctx, "SELECT n FROM t ORDER BY n").await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_as_of() {
+        let dir = tempdir().unwrap();
+        let second = write_logged_table(dir.path());
+        write_commit(dir.path(), 1, &second);
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let table = PartitionedTable::try_new(&ctx.state(), url, &[], None, false)
+            .await
+            .unwrap();
+
+        // earlier versions are read as of a version, a time or the run that committed them
+        for (at, rows) in [
+            (AsOf::Version(0), 1),
+            (AsOf::Version(1), 2),
+            (AsOf::Time(1_500), 1),
+            (AsOf::Time(2_000), 2),
+            (AsOf::Run("0199a1b2000070008000000000000001".to_string()), 1),
+        ] {
+            let ctx = SessionContext::new();
+            ctx.register_table("t", Arc::new(table.as_of(&at).await.unwrap()))
+                .unwrap();
+            assert_eq!(query(&ctx, "SELECT n FROM t").await.len(), rows, "{at}");
+        }
+        for at in [
+            AsOf::Version(2),
+            AsOf::Time(999),
+            AsOf::Run("0199a1b2000070008000000000000003".to_string()),
+        ] {
+            let error = table.as_of(&at).await.unwrap_err();
+            assert!(
+                error.to_string().contains("has no version as of"),
+                "{error}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_as_of_without_log() {
+        let dir = tempdir().unwrap();
+        write_table(dir.path(), &[(Some("us"), Some(1))]);
+        let ctx = SessionContext::new();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap()).unwrap();
+        let partitions = ["region".to_string(), "shard".to_string()];
+        let table = PartitionedTable::try_new(&ctx.state(), url, &partitions, None, false)
+            .await
+            .unwrap();
+        let error = table.as_of(&AsOf::Version(0)).await.unwrap_err();
+        assert!(error.to_string().contains("--delta-log"), "{error}");
+    }
+}
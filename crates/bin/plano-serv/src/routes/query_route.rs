@@ -2,6 +2,7 @@
 /// This module provides query handling functionality for the `Plano server`
 ///
 use crate::routes::{PlanoBadRequest, PlanoServerError};
+use crate::time_travel::{self, AsOf};
 use bytes::Bytes;
 use datafusion::arrow::array::RecordBatch;
 use datafusion::prelude::*;
@@ -48,16 +49,43 @@ async fn handle_query(
             );
     };
 
-    if let Some(cached_batches) = check_cache(&cache, query).await {
+    let as_of = match form
+        .get("as_of")
+        .map(|as_of| AsOf::parse(as_of))
+        .transpose()
+    {
+        Ok(as_of) => as_of,
+        Err(reason) => {
+            debug!(reason);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(reason)
+                .map_or_else(
+                    |e| {
+                        Err(warp::reject::custom(PlanoBadRequest {
+                            reason: e.to_string(),
+                        }))
+                    },
+                    Ok,
+                );
+        }
+    };
+    // the same SQL reads other files as of another version
+    let key = as_of.as_ref().map_or_else(
+        || query.clone(),
+        |as_of| format!("{query}\n-- as of {as_of}"),
+    );
+
+    if let Some(cached_batches) = check_cache(&cache, &key).await {
         return build_response(&cached_batches, format, content_type);
     }
 
-    let results = match execute_query(&ctx, query).await {
+    let results = match execute_query(&ctx, query, as_of).await {
         Ok(results) => results,
         Err(err) => return Err(err.into()),
     };
 
-    cache.lock().await.put(query.clone(), results.clone());
+    cache.lock().await.put(key, results.clone());
     build_response(&results, format, content_type)
 }
 
@@ -104,7 +132,18 @@ async fn check_cache(cache: &QueryCache, query: &str) -> Option<Vec<RecordBatch>
 async fn execute_query(
     ctx: &Arc<SessionContext>,
     query: &str,
+    as_of: Option<AsOf>,
 ) -> Result<Vec<RecordBatch>, PlanoServerError> {
+    let server_error = |e: datafusion::error::DataFusionError| PlanoServerError {
+        reason: e.to_string(),
+    };
+    let as_of_ctx = match as_of {
+        Some(as_of) => Some(time_travel::as_of_context(ctx, as_of).map_err(server_error)?),
+        None => None,
+    };
+    let ctx = as_of_ctx.as_ref().unwrap_or(ctx);
+    let query = &time_travel::quote_versions(query, &time_travel::table_names(ctx))
+        .map_err(server_error)?;
     match ctx.sql(query).await {
         Ok(df) => df.collect().await.map_err(|e| PlanoServerError {
             reason: e.to_string(),
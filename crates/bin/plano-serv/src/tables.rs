@@ -1,13 +1,9 @@
 ///
 /// This module provides functionality to register multiple tables in a `DataFusion` context
 ///
-use datafusion::arrow::datatypes::{DataType, Field, Schema};
-use datafusion::datasource::file_format::parquet::ParquetFormat;
-use datafusion::datasource::listing::{
-    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
-};
+use crate::partitioned::{Buckets, PartitionedTable};
+use datafusion::datasource::listing::ListingTableUrl;
 use datafusion::prelude::*;
-use std::collections::HashSet;
 use std::sync::Arc;
 use tracing::info;
 
@@ -15,25 +11,34 @@ use tracing::info;
 /// name        — the SQL name clients will use (e.g. "events")
 /// root        — a file:// or s3:// URI pointing at the top-level directory
 /// partitions  — zero or more folder-key names
+/// buckets     — the column plano-sync's `--bucket-by` hashed into files, and how many
 #[derive(Debug)]
 pub struct TableSpec {
     pub name: String,
     pub root: String,
     pub partitions: Vec<String>,
+    pub buckets: Option<Buckets>,
 }
 
 impl TableSpec {
     /// Parse strings of the form
-    ///   name=path[:col1,col2,...]
+    ///   name=path[:col1,col2,...][#bucket_col/buckets]
     /// Examples:
     ///   events=/data/parquet/events:year,month,day
     ///   users=s3://bucket/users
+    ///   vessels=/data/parquet/vessels:year#name/64
     pub fn parse(s: &str) -> Result<Self, String> {
         // split off name=rest
         let (name, rest) = s
             .split_once('=')
             .ok_or_else(|| format!("Invalid table-spec `{s}`"))?;
 
+        // split off optional #column/buckets
+        let (rest, buckets) = match rest.rsplit_once('#') {
+            Some((rest, bucketing)) => (rest, Some(Buckets::parse(bucketing)?)),
+            None => (rest, None),
+        };
+
         // split off optional :part1,part2
         let (root, parts) = rest.rfind(':').map_or_else(
             || (rest.to_string(), String::new()),
@@ -59,51 +64,30 @@ impl TableSpec {
             name: name.to_string(),
             root,
             partitions,
+            buckets,
         })
     }
 }
 
-// Registers a table in the DataFusion context using a `ListingTableConfig`
+// Registers a table in the DataFusion context as a `PartitionedTable`
 //
-// The complexity is due to we use partition keys based on file data but once we start using a
-// file column as a partition key datafusion will fail in sql planning because it can't deal with
-// duplicate cols in the schema.  We need to scrub the file column when we are adding  a
-// partition key.
+// The partition columns are read from the directory names, which plano-sync escapes; the copy
+// of each partition column kept in the files is left out so that the schema has no duplicates.
+// With `require_success`, only the files committed by the table's `_SUCCESS` marker are read.
 async fn register_table(
     ctx: &SessionContext,
     spec: &TableSpec, // your own struct that holds name, path, partition list …
+    require_success: bool,
 ) -> datafusion::error::Result<()> {
-    let base_opts = ListingOptions::new(Arc::new(ParquetFormat::default()))
-        .with_file_extension(".parquet")
-        .with_table_partition_cols(
-            spec.partitions
-                .iter()
-                .map(|c| (c.clone(), DataType::Utf8))
-                .collect(),
-        );
-
     let table_url = ListingTableUrl::parse(&spec.root)?;
-
-    let session_state = ctx.state();
-    let file_schema = base_opts.infer_schema(&session_state, &table_url).await?;
-
-    let part_set: HashSet<&str> = spec.partitions.iter().map(String::as_str).collect();
-
-    // filter out the file columns that are also partition keys
-    let clean_fields: Vec<Field> = file_schema
-        .fields()
-        .iter()
-        .filter(|f| !part_set.contains(f.name().as_str()))
-        .map(|f| (**f).clone()) // <‑‑ convert Arc<Field> → Field
-        .collect();
-
-    let clean_schema = Arc::new(Schema::new(clean_fields));
-
-    let cfg = ListingTableConfig::new(table_url)
-        .with_listing_options(base_opts)
-        .with_schema(clean_schema); // <‑‑ this is the key :contentReference[oaicite:0]{index=0}
-
-    let table = ListingTable::try_new(cfg)?;
+    let table = PartitionedTable::try_new(
+        &ctx.state(),
+        table_url,
+        &spec.partitions,
+        spec.buckets.clone(),
+        require_success,
+    )
+    .await?;
     ctx.register_table(&spec.name, Arc::new(table))?;
 
     Ok(())
@@ -113,13 +97,14 @@ async fn register_table(
 
 /// Registers multiple tables in the `DataFusion` context based on a list of table specs.
 /// Each spec should be in the format:
-/// name=path[:col1,col2,...]
+/// name=path[:col1,col2,...][#bucket_col/buckets]
 pub async fn register_tables(
     ctx: &Arc<SessionContext>,
     table_specs: &[TableSpec],
+    require_success: bool,
 ) -> anyhow::Result<()> {
     for spec in table_specs {
-        register_table(ctx, spec).await?;
+        register_table(ctx, spec, require_success).await?;
         info!("Registered table `{}` at `{}`", spec.name, spec.root);
     }
     Ok(())
@@ -195,6 +180,24 @@ mod tests {
         assert_eq!(spec.partitions, vec!["year", "month"]);
     }
 
+    #[test]
+    #[allow(clippy::unwrap_used)]
+    fn test_parse_buckets() {
+        let spec = TableSpec::parse("vessels=s3://lake/vessels:year#name/64").unwrap();
+        assert_eq!(spec.root, "s3://lake/vessels");
+        assert_eq!(spec.partitions, vec!["year"]);
+        let buckets = spec.buckets.unwrap();
+        assert_eq!((buckets.column.as_str(), buckets.count), ("name", 64));
+
+        let spec = TableSpec::parse("vessels=/data/vessels#name/8").unwrap();
+        assert_eq!(spec.root, "/data/vessels");
+        assert!(spec.partitions.is_empty());
+        assert_eq!(spec.buckets.unwrap().count, 8);
+
+        assert!(TableSpec::parse("vessels=/data/vessels#name").is_err());
+        assert!(TableSpec::parse("vessels=/data/vessels#name/0").is_err());
+    }
+
     // #[tokio::test]
     // async fn test_handle_query_bytes_valid() {
     //     let raw_body = Bytes::from("sql=SELECT%20*%20FROM%20test");
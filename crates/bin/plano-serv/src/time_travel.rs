@@ -0,0 +1,376 @@
+///
+/// Querying tables as they were at an earlier version of their `_delta_log`
+///
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat};
+use datafusion::catalog::{
+    CatalogProvider, CatalogProviderList, MemoryCatalogProvider, MemoryCatalogProviderList,
+    SchemaProvider,
+};
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::SessionStateBuilder;
+use datafusion::prelude::SessionContext;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::tokenizer::{Location, Token, Tokenizer};
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::partitioned::PartitionedTable;
+
+const CATALOG: &str = "datafusion";
+const SCHEMA: &str = "public";
+
+/// A point in a table's history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsOf {
+    /// The version committed last at or before this time, in milliseconds since the epoch.
+    Time(i64),
+    /// The version a plano-sync run committed, by its run id without hyphens.
+    Run(String),
+    /// A version of the log.
+    Version(u64),
+}
+
+impl AsOf {
+    /// Parses a version (`v12`), a date (`2026-10-01`, meaning its start), a timestamp
+    /// (`2026-10-01T06:00:00Z`, in UTC unless it has an offset) or a run id.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(version) = s.strip_prefix('v')
+            && let Ok(version) = version.parse()
+        {
+            return Ok(Self::Version(version));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self::Time(time.timestamp_millis()));
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+            if let Ok(time) = NaiveDateTime::parse_from_str(s, format) {
+                return Ok(Self::Time(time.and_utc().timestamp_millis()));
+            }
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self::Time(
+                date.and_time(chrono::NaiveTime::MIN)
+                    .and_utc()
+                    .timestamp_millis(),
+            ));
+        }
+        let run = s.replace('-', "").to_lowercase();
+        if run.len() == 32 && run.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(Self::Run(run));
+        }
+        Err(format!(
+            "Invalid version `{s}`; expected a date, a timestamp, a run id or a version such as v3"
+        ))
+    }
+}
+
+impl fmt::Display for AsOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Time(time) => match DateTime::from_timestamp_millis(*time) {
+                Some(time) => write!(f, "{}", time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+                None => write!(f, "{time}"),
+            },
+            Self::Run(run) => write!(f, "run {run}"),
+            Self::Version(version) => write!(f, "v{version}"),
+        }
+    }
+}
+
+/// The registered tables, which also resolves `name@version` (e.g. `events@2026-10-01`) to
+/// table `name` as of that version, and, when `as_of` is set, every table to its version then.
+#[derive(Debug)]
+pub struct TimeTravelSchema {
+    tables: Arc<dyn SchemaProvider>,
+    as_of: Option<AsOf>,
+}
+
+#[async_trait]
+impl SchemaProvider for TimeTravelSchema {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.table_names()
+    }
+
+    async fn table(&self, name: &str) -> Result<Option<Arc<dyn TableProvider>>> {
+        let (name, as_of) = match name.rsplit_once('@') {
+            Some((name, version)) => (
+                name,
+                Some(AsOf::parse(version).map_err(DataFusionError::Plan)?),
+            ),
+            None => (name, self.as_of.clone()),
+        };
+        let Some(table) = self.tables.table(name).await? else {
+            return Ok(None);
+        };
+        let Some(as_of) = as_of else {
+            return Ok(Some(table));
+        };
+        let Some(table) = table.as_any().downcast_ref::<PartitionedTable>() else {
+            return Err(DataFusionError::Plan(format!(
+                "Table `{name}` has no earlier versions"
+            )));
+        };
+        Ok(Some(Arc::new(table.as_of(&as_of).await?)))
+    }
+
+    fn register_table(
+        &self,
+        name: String,
+        table: Arc<dyn TableProvider>,
+    ) -> Result<Option<Arc<dyn TableProvider>>> {
+        self.tables.register_table(name, table)
+    }
+
+    fn deregister_table(&self, name: &str) -> Result<Option<Arc<dyn TableProvider>>> {
+        self.tables.deregister_table(name)
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        let name = name.rsplit_once('@').map_or(name, |(name, _)| name);
+        self.tables.table_exist(name)
+    }
+}
+
+/// Lets the queries run in `ctx` name a table's earlier versions as `name@version`.
+///
+/// # Errors
+///
+/// Will return `Err` if `ctx` has no default schema.
+pub fn enable(ctx: &SessionContext) -> Result<()> {
+    let catalog = ctx
+        .catalog(CATALOG)
+        .ok_or_else(|| DataFusionError::Internal(format!("No catalog `{CATALOG}`")))?;
+    let tables = catalog
+        .schema(SCHEMA)
+        .ok_or_else(|| DataFusionError::Internal(format!("No schema `{SCHEMA}`")))?;
+    catalog.register_schema(
+        SCHEMA,
+        Arc::new(TimeTravelSchema {
+            tables,
+            as_of: None,
+        }),
+    )?;
+    Ok(())
+}
+
+/// A context sharing `ctx`'s tables in which each is read as of `as_of`, unless the query names
+/// another version.
+///
+/// # Errors
+///
+/// Will return `Err` if `ctx` has no default schema.
+pub fn as_of_context(ctx: &SessionContext, as_of: AsOf) -> Result<SessionContext> {
+    let schema = ctx
+        .catalog(CATALOG)
+        .and_then(|catalog| catalog.schema(SCHEMA))
+        .ok_or_else(|| DataFusionError::Internal(format!("No schema `{SCHEMA}`")))?;
+    let tables = schema
+        .as_any()
+        .downcast_ref::<TimeTravelSchema>()
+        .map_or_else(|| schema.clone(), |schema| schema.tables.clone());
+    let catalog = MemoryCatalogProvider::new();
+    catalog.register_schema(
+        SCHEMA,
+        Arc::new(TimeTravelSchema {
+            tables,
+            as_of: Some(as_of),
+        }),
+    )?;
+    let catalogs = MemoryCatalogProviderList::new();
+    catalogs.register_catalog(CATALOG.to_string(), Arc::new(catalog));
+    let state = SessionStateBuilder::new_from_existing(ctx.state())
+        .with_catalog_list(Arc::new(catalogs))
+        .build();
+    Ok(SessionContext::new_with_state(state))
+}
+
+/// The names of the tables registered in `ctx`.
+pub fn table_names(ctx: &SessionContext) -> Vec<String> {
+    ctx.catalog(CATALOG)
+        .and_then(|catalog| catalog.schema(SCHEMA))
+        .map(|schema| schema.table_names())
+        .unwrap_or_default()
+}
+
+/// `sql` with each `table@version` of one of `tables` quoted as `"table@version"`, so that it
+/// parses as a table name.  It is split into tokens as the parser reads it, so strings, quoted
+/// identifiers, comments and operators such as `@>` are left as they are.
+///
+/// # Errors
+///
+/// Will return `Err` if a version is followed by a time after a space, as in
+/// `events@2026-10-01 06:30:00`, which would not be read as part of it.
+pub fn quote_versions(sql: &str, tables: &[String]) -> Result<String> {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize_with_location() else {
+        // left for the parser to report
+        return Ok(sql.to_string());
+    };
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(sql.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let offset = |location: Location| {
+        let line = usize::try_from(location.line).unwrap_or(usize::MAX);
+        let column = usize::try_from(location.column).unwrap_or(usize::MAX);
+        let start = line_starts
+            .get(line.saturating_sub(1))
+            .copied()
+            .unwrap_or(sql.len());
+        sql[start..]
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(sql.len(), |(i, _)| start + i)
+    };
+
+    let mut quoted = String::with_capacity(sql.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        // the dialect reads `@` as part of a word, so `events@v3` is one word and `events@>` one
+        // followed by `>`
+        let table = match &tokens[i].token {
+            Token::Word(word) if word.quote_style.is_none() => word
+                .value
+                .split_once('@')
+                .filter(|(name, version)| {
+                    !version.is_empty() && tables.contains(&name.to_lowercase())
+                })
+                .map(|(name, _)| (name.len(), name.to_lowercase())),
+            _ => None,
+        };
+        let Some((name_len, table)) = table else {
+            i += 1;
+            continue;
+        };
+        // dates and times are split into numbers, words and punctuation
+        let end = (i + 1..tokens.len())
+            .find(|&j| !in_version(&tokens[j].token))
+            .unwrap_or(tokens.len());
+        let start = offset(tokens[i].span.start);
+        let stop = offset(tokens[end - 1].span.end);
+        let next: Vec<&Token> = tokens[end..].iter().take(3).map(|t| &t.token).collect();
+        if let [Token::Whitespace(_), Token::Number(..), Token::Colon] = next[..] {
+            return Err(DataFusionError::Plan(format!(
+                "The version `{}` is followed by a time; write it without a space, as in \
+                 {table}@2026-10-01T06:30:00",
+                &sql[start..stop]
+            )));
+        }
+        quoted.push_str(&sql[copied..start]);
+        quoted.push('"');
+        quoted.push_str(&table);
+        quoted.push_str(&sql[start + name_len..stop]);
+        quoted.push('"');
+        copied = stop;
+        i = end;
+    }
+    quoted.push_str(&sql[copied..]);
+    Ok(quoted)
+}
+
+/// Whether `token` can continue a version: `2026-10-01T06:30:00.5+02:00`, `v3` or a run id.
+const fn in_version(token: &Token) -> bool {
+    match token {
+        Token::Word(word) => word.quote_style.is_none(),
+        Token::Number(..) | Token::Minus | Token::Plus | Token::Colon | Token::Period => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int32Array, RecordBatch};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::MemTable;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(AsOf::parse("v12"), Ok(AsOf::Version(12)));
+        let midnight = AsOf::parse("2026-10-01").unwrap();
+        assert_eq!(midnight, AsOf::Time(1_790_812_800_000));
+        assert_eq!(midnight.to_string(), "2026-10-01T00:00:00Z");
+        for time in [
+            "2026-10-01T06:30:00Z",
+            "2026-10-01T08:30:00+02:00",
+            "2026-10-01T06:30:00",
+            "2026-10-01 06:30:00.000",
+        ] {
+            assert_eq!(
+                AsOf::parse(time),
+                Ok(AsOf::Time(1_790_836_200_000)),
+                "{time}"
+            );
+        }
+        assert_eq!(
+            AsOf::parse("0199A1B2-0000-7000-8000-000000000001"),
+            Ok(AsOf::Run("0199a1b2000070008000000000000001".to_string()))
+        );
+        for invalid in ["", "v", "vx", "2026", "2026-13-01", "0199a1b2"] {
+            assert!(AsOf::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_quote_versions() {
+        let tables = ["events".to_string()];
+        let quote = |sql| quote_versions(sql, &tables).unwrap();
+        assert_eq!(
+            quote("SELECT * FROM Events@2026-10-01 e JOIN events@v3, other@v1"),
+            r#"SELECT * FROM "events@2026-10-01" e JOIN "events@v3", other@v1"#
+        );
+        assert_eq!(
+            quote("SELECT count(*) FROM (SELECT 1 FROM events@v1);"),
+            r#"SELECT count(*) FROM (SELECT 1 FROM "events@v1");"#
+        );
+        assert_eq!(
+            quote(
+                "SELECT * FROM events@2026-10-01T06:30:00.5+02:00\nJOIN events@01a14808-1747-7290-b950-e1062fe49aa9 b"
+            ),
+            "SELECT * FROM \"events@2026-10-01T06:30:00.5+02:00\"\nJOIN \"events@01a14808-1747-7290-b950-e1062fe49aa9\" b"
+        );
+        // strings, escapes, quoted identifiers, comments and operators are left alone
+        let untouched = "SELECT 'events@v1', E'it\\'s events@v2', \"events@v2\" -- events@v3\n\
+                         /* events@v4 */ FROM x WHERE events@>'{1}' AND events@ v";
+        assert_eq!(quote(untouched), untouched);
+
+        let error =
+            quote_versions("SELECT * FROM events@2026-10-01 06:30:00", &tables).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("`events@2026-10-01` is followed by a time"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tables_without_history() {
+        let ctx = SessionContext::new();
+        enable(&ctx).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1]))])
+            .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table("t", Arc::new(table)).unwrap();
+        assert_eq!(table_names(&ctx), ["t"]);
+
+        // tables are still registered and read as they are
+        let rows = ctx.sql("SELECT n FROM t").await.unwrap().count().await;
+        assert_eq!(rows.unwrap(), 1);
+
+        // but have no earlier versions to read
+        let error = ctx.sql(r#"SELECT n FROM "t@v0""#).await.unwrap_err();
+        assert!(error.to_string().contains("no earlier versions"), "{error}");
+        let ctx = as_of_context(&ctx, AsOf::Version(0)).unwrap();
+        let error = ctx.sql("SELECT n FROM t").await.unwrap_err();
+        assert!(error.to_string().contains("no earlier versions"), "{error}");
+    }
+}
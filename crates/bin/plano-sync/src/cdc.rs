@@ -0,0 +1,38 @@
+///
+/// Write change logs captured from a logical replication slot
+///
+use rds_sync::cdc::{ChangeFeed, change_schema};
+use rds_sync::{TableRef, infer_arrow_schema, project_schema, resolve_table};
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::output::Output;
+use crate::partitions::write_partitioned_files;
+use crate::{Args, print_batches_if};
+
+/// Writes the changes made to `table` since the previous run, then advances the slot past them.
+///
+/// The slot is only advanced once every file has been closed, so a failed run is retried from
+/// the same position; changes may then be written twice but are never lost.
+pub async fn sync_changes(
+    args: &Args,
+    output: &Output,
+    pool: &PgPool,
+    slot: &str,
+) -> anyhow::Result<()> {
+    let table = resolve_table(&TableRef::parse(&args.table)?, pool).await?;
+    let table_schema = infer_arrow_schema(&table, pool).await?;
+    let table_schema = project_schema(&table_schema, &args.columns, &args.exclude_columns)?;
+    let publication = args.cdc_publication.as_deref().unwrap_or(slot);
+    let feed = ChangeFeed::new(slot, publication, table.clone());
+    feed.ensure(pool).await?;
+
+    let schema_ref = change_schema(&table_schema)?;
+    let upto = feed.current_lsn(pool).await?;
+    let batches = feed.stream(schema_ref.clone(), pool, upto, args.batch_size);
+    write_partitioned_files(args, output, &schema_ref, print_batches_if(args, batches)).await?;
+
+    feed.advance(pool, upto).await?;
+    info!("Captured changes to {table} up to {upto} from slot {slot}");
+    Ok(())
+}
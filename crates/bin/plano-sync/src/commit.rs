@@ -0,0 +1,971 @@
+///
+/// Committing a run's files to the table's directory
+///
+use anyhow::{Context, Result, bail};
+use arrow::datatypes::{DataType, Field, Schema};
+use chrono::{DateTime, TimeDelta, Utc};
+use object_store::path::Path;
+use plano_core::commit::{COMMIT_MARKER, Manifest};
+use plano_core::delta::{
+    self, Action, Add, CommitInfo, Format, LOG_DIR, Metadata, Protocol, Remove, Snapshot,
+};
+use plano_core::partition::decode_value;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::Args;
+use crate::mode::{remove_files, superseded};
+use crate::output::Output;
+
+/// The directory in a table's directory where runs stage their files.
+pub const STAGING_DIR: &str = "_temporary";
+
+/// The directory in a table's directory holding its numbered manifests.
+const MANIFEST_DIR: &str = "_manifests";
+
+/// The number of manifests kept before the latest, so that a run that read one of them just
+/// before the others were committed still finds its number taken.
+const KEPT_MANIFESTS: u64 = 10;
+
+/// How long a run's staging directory can go without new files before a commit to the table
+/// takes the run to have died and removes it.
+const STAGING_EXPIRY_HOURS: i64 = 24;
+
+/// A run writing to a table's directory.
+///
+/// On the local file system its files are written under `_temporary/<run id>/` and renamed
+/// into place once the run's manifest is committed, so a run that dies before then leaves
+/// nothing in the partitions, and one that dies after has its files moved by the next run.
+/// On object stores, where a rename is a copy, they are written in place.  Either way, a run
+/// only becomes visible to readers that honour the `_SUCCESS` marker once it lists its files.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    run_id: Uuid,
+    table_dir: Path,
+    staged: bool,
+    operation: Operation,
+}
+
+/// What a run does to the table, as recorded in its `_delta_log` commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Writes rows synced from the source
+    Write,
+    /// Rewrites the table's files into fewer, without changing its rows or schema
+    Compact,
+    /// Removes the partitions older than the table's retention
+    Expire,
+}
+
+impl Commit {
+    pub fn new(args: &Args, output: &Output) -> Self {
+        Self {
+            run_id: Uuid::now_v7(),
+            table_dir: args.table_dir(),
+            staged: output.is_local(),
+            operation: Operation::Write,
+        }
+    }
+
+    /// Records the run as `operation` rather than a write.
+    pub const fn with_operation(mut self, operation: Operation) -> Self {
+        self.operation = operation;
+        self
+    }
+
+    /// Identifies the run in the names of its files.
+    pub const fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    /// Where the file committed to `path` is written.
+    pub fn staging_path(&self, path: &Path) -> Path {
+        let staging_dir = self
+            .table_dir
+            .clone()
+            .join(STAGING_DIR)
+            .join(self.run_id.simple().to_string());
+        match path.prefix_match(&self.table_dir) {
+            Some(parts) if self.staged => staging_dir.parts().chain(parts).collect(),
+            _ => path.clone(),
+        }
+    }
+
+    /// Commits the `written` files and only then removes those they supersede, so that readers
+    /// honouring the `_SUCCESS` marker go from one run's files to the next in a single step.
+    /// `schema` is that of the files.
+    ///
+    /// The commit is the next numbered manifest in `_manifests`, which is only created if no
+    /// other run has created one of the same number.  The staged files are then moved into
+    /// place, listed in the table's transaction log with --delta-log, and the manifest copied
+    /// to the marker.  A run that dies on the way has its commit finished by the next one.
+    ///
+    /// With --delta-log, files the log removes are kept for readers of its older versions, and
+    /// only deleted by the first run after --delta-retention hours.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a file cannot be moved or removed, the manifests, marker or log
+    /// cannot be read or written, or another run committed first.
+    pub async fn commit(
+        &self,
+        args: &Args,
+        output: &Output,
+        schema: &Schema,
+        written: &[Path],
+    ) -> Result<()> {
+        let stale = superseded(args, output, written).await?;
+        self.replace(args, output, schema, written, stale).await
+    }
+
+    /// Commits the `written` files in place of the `stale` ones, as [`Commit::commit`] does
+    /// with the files the --mode supersedes.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a file cannot be moved or removed, the manifests, marker or log
+    /// cannot be read or written, or another run committed first.
+    pub async fn replace(
+        &self,
+        args: &Args,
+        output: &Output,
+        schema: &Schema,
+        written: &[Path],
+        mut stale: Vec<Path>,
+    ) -> Result<()> {
+        let marker = self.table_dir.clone().join(COMMIT_MARKER);
+        let log_dir = self.table_dir.clone().join(LOG_DIR);
+        let manifest_dir = self.table_dir.clone().join(MANIFEST_DIR);
+        stale.retain(|path| {
+            *path != marker
+                && path.prefix_match(&log_dir).is_none()
+                && path.prefix_match(&manifest_dir).is_none()
+        });
+        let latest = self.latest(output).await?;
+        if latest.version > 0
+            && self
+                .marker(output)
+                .await?
+                .is_none_or(|committed| committed.version < latest.version)
+        {
+            self.roll_forward(output, &latest).await?;
+        }
+        let mut files: BTreeSet<String> = latest.files.into_iter().collect();
+        for path in &stale {
+            if let Some(file) = self.relative(path) {
+                files.remove(&file);
+            }
+        }
+        files.extend(written.iter().filter_map(|path| self.relative(path)));
+        let manifest = Manifest {
+            version: latest.version + 1,
+            run_id: self.run_id.to_string(),
+            files: files.iter().cloned().collect(),
+        };
+        let contents = self.create_manifest(output, &manifest).await?;
+
+        for path in written {
+            let staged = self.staging_path(path);
+            if staged != *path {
+                output.rename(&staged, path).await?;
+            }
+        }
+        if args.delta_log {
+            let snapshot = self.commit_log(args, output, schema, &files).await?;
+            stale.retain(|path| {
+                self.relative(path)
+                    .is_none_or(|file| !snapshot.removed.contains_key(&file))
+            });
+            stale.extend(self.expired(args, output, &snapshot).await?);
+        }
+        output.write(&marker, contents).await?;
+        info!(
+            "Committed {} files to {}",
+            written.len(),
+            output.display(&self.table_dir)
+        );
+
+        stale.extend(self.abandoned(output).await?);
+        stale.extend(self.superseded_manifests(output, manifest.version).await?);
+        remove_files(output, &stale).await
+    }
+
+    /// Creates `manifest` in `_manifests` unless another run has already committed its version,
+    /// and returns its contents.
+    async fn create_manifest(&self, output: &Output, manifest: &Manifest) -> Result<Vec<u8>> {
+        let contents = serde_json::to_vec_pretty(manifest)?;
+        let path = self
+            .table_dir
+            .clone()
+            .join(MANIFEST_DIR)
+            .join(delta::commit_file(manifest.version));
+        if !output.create(&path, contents.clone()).await? {
+            bail!(
+                "Another run committed version {} of {} first; run again to commit on top of it",
+                manifest.version,
+                output.display(&self.table_dir)
+            );
+        }
+        Ok(contents)
+    }
+
+    /// The table's files, relative to its directory, as the last run committed them.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the manifests or marker cannot be read or the directory listed.
+    pub async fn files(&self, output: &Output) -> Result<BTreeSet<String>> {
+        Ok(self.latest(output).await?.files.into_iter().collect())
+    }
+
+    /// The path of a `file` the marker lists.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `file` is not a valid path.
+    pub fn absolute(&self, file: &str) -> Result<Path> {
+        Ok(Path::parse(format!("{}/{file}", self.table_dir))?)
+    }
+
+    /// The table's last commit: its newest manifest, or for a table committed before manifests
+    /// were numbered, the files committed by earlier runs as version 0.
+    async fn latest(&self, output: &Output) -> Result<Manifest> {
+        let manifest_dir = self.table_dir.clone().join(MANIFEST_DIR);
+        let version = output
+            .list(&manifest_dir)
+            .await?
+            .iter()
+            .filter_map(|name| delta::commit_version(name))
+            .max();
+        let Some(version) = version else {
+            return Ok(Manifest {
+                files: self.committed(output).await?.into_iter().collect(),
+                ..Default::default()
+            });
+        };
+        let path = manifest_dir.join(delta::commit_file(version));
+        let contents = output
+            .read(&path)
+            .await?
+            .with_context(|| format!("{} is gone", output.display(&path)))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing {}", output.display(&path)))
+    }
+
+    /// The contents of the table's marker, if it has a marker that lists files.
+    async fn marker(&self, output: &Output) -> Result<Option<Manifest>> {
+        let marker = self.table_dir.clone().join(COMMIT_MARKER);
+        let Some(contents) = output.read(&marker).await? else {
+            return Ok(None);
+        };
+        if contents.is_empty() {
+            return Ok(None);
+        }
+        let manifest = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing {}", output.display(&marker)))?;
+        Ok(Some(manifest))
+    }
+
+    /// The files committed by earlier runs: those in the marker, or, for a table written before
+    /// markers or by another tool, every data file in the directory.
+    async fn committed(&self, output: &Output) -> Result<BTreeSet<String>> {
+        if let Some(manifest) = self.marker(output).await? {
+            return Ok(manifest.files.into_iter().collect());
+        }
+        Ok(output
+            .list_all(&self.table_dir)
+            .await?
+            .iter()
+            .filter_map(|path| self.relative(path))
+            .filter(|file| {
+                file.ends_with(".parquet")
+                    && !file.split('/').any(|part| part.starts_with(['_', '.']))
+            })
+            .collect())
+    }
+
+    /// Finishes the commit of `manifest`, whose run died before it had moved all its files into
+    /// place and copied the manifest to the marker.
+    async fn roll_forward(&self, output: &Output, manifest: &Manifest) -> Result<()> {
+        if let Ok(run_id) = Uuid::parse_str(&manifest.run_id) {
+            let staging_dir = self
+                .table_dir
+                .clone()
+                .join(STAGING_DIR)
+                .join(run_id.simple().to_string());
+            let files: BTreeSet<&String> = manifest.files.iter().collect();
+            for staged in output.list_all(&staging_dir).await? {
+                let Some(parts) = staged.prefix_match(&staging_dir) else {
+                    continue;
+                };
+                let file = parts
+                    .map(|part| part.as_ref().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if files.contains(&file) {
+                    output.rename(&staged, &self.absolute(&file)?).await?;
+                }
+            }
+        }
+        output
+            .write(
+                &self.table_dir.clone().join(COMMIT_MARKER),
+                serde_json::to_vec_pretty(manifest)?,
+            )
+            .await?;
+        info!(
+            "Finished commit {} to {} of run {}, which did not finish it",
+            manifest.version,
+            output.display(&self.table_dir),
+            manifest.run_id
+        );
+        Ok(())
+    }
+
+    /// The manifests more than [`KEPT_MANIFESTS`] versions older than `version`.
+    async fn superseded_manifests(&self, output: &Output, version: u64) -> Result<Vec<Path>> {
+        let manifest_dir = self.table_dir.clone().join(MANIFEST_DIR);
+        Ok(output
+            .list(&manifest_dir)
+            .await?
+            .iter()
+            .filter(|name| {
+                delta::commit_version(name).is_some_and(|old| old + KEPT_MANIFESTS <= version)
+            })
+            .map(|name| manifest_dir.clone().join(name.as_str()))
+            .collect())
+    }
+
+    /// Adds the next commit to the table's `_delta_log`, which adds the live `files` the log
+    /// does not have yet and removes those it has that are gone, and returns the table as of
+    /// that commit.  The commit file is only written if no other run has written one of the
+    /// same version.
+    async fn commit_log(
+        &self,
+        args: &Args,
+        output: &Output,
+        schema: &Schema,
+        files: &BTreeSet<String>,
+    ) -> Result<Snapshot> {
+        let log_dir = self.table_dir.clone().join(LOG_DIR);
+        let mut snapshot = self.snapshot(output, &log_dir).await?;
+        let now = Utc::now().timestamp_millis();
+
+        let mut actions = Vec::new();
+        // compacting leaves the table's schema and rows as they are, expiring only its rows
+        let data_change = self.operation != Operation::Compact;
+        if self.operation == Operation::Write {
+            let protocol = Protocol::for_schema(schema);
+            if snapshot.protocol.as_ref() != Some(&protocol) {
+                actions.push(Action {
+                    protocol: Some(protocol),
+                    ..Default::default()
+                });
+            }
+            let metadata = table_metadata(args, schema, snapshot.metadata.as_ref(), now)?;
+            if snapshot.metadata.as_ref() != Some(&metadata) {
+                actions.push(Action {
+                    meta_data: Some(metadata),
+                    ..Default::default()
+                });
+            }
+        }
+        // the log names files URI-encoded, and the marker as they are on disk
+        for (path, add) in &snapshot.files {
+            if !files.contains(path) {
+                actions.push(Action {
+                    remove: Some(Remove {
+                        data_change,
+                        ..Remove::of(add, now)
+                    }),
+                    ..Default::default()
+                });
+            }
+        }
+        for path in files {
+            if snapshot.files.contains_key(path) {
+                continue;
+            }
+            let meta = output.meta(&self.absolute(path)?).await?;
+            actions.push(Action {
+                add: Some(Add {
+                    path: delta::encode_path(path),
+                    partition_values: partition_values(path),
+                    size: meta.size,
+                    modification_time: meta.last_modified.timestamp_millis(),
+                    data_change,
+                }),
+                ..Default::default()
+            });
+        }
+        if actions.is_empty() {
+            return Ok(snapshot);
+        }
+
+        actions.insert(
+            0,
+            Action {
+                commit_info: Some(self.commit_info(args, now)),
+                ..Default::default()
+            },
+        );
+        let version = snapshot.version.map_or(0, |version| version + 1);
+        let path = log_dir.clone().join(delta::commit_file(version));
+        if !output
+            .create(&path, delta::format_commit(&actions)?)
+            .await?
+        {
+            bail!(
+                "Another run committed version {version} of {} first; run again to commit on top of it",
+                output.display(&log_dir)
+            );
+        }
+        info!(
+            "Committed version {version} of {} for run {}",
+            output.display(&log_dir),
+            self.run_id
+        );
+        snapshot.apply(version, actions);
+        Ok(snapshot)
+    }
+
+    /// What the run's `_delta_log` commit records about it.
+    fn commit_info(&self, args: &Args, now: i64) -> CommitInfo {
+        let (operation, parameters) = match self.operation {
+            Operation::Write => (
+                "WRITE",
+                BTreeMap::from([
+                    (
+                        "mode".to_string(),
+                        json!(format!("{:?}", args.write_mode())),
+                    ),
+                    ("partitionBy".to_string(), json!(args.partition_by)),
+                ]),
+            ),
+            Operation::Compact => (
+                "OPTIMIZE",
+                BTreeMap::from([
+                    ("targetSize".to_string(), json!(args.max_file_bytes())),
+                    ("sortBy".to_string(), json!(args.sort_by)),
+                ]),
+            ),
+            Operation::Expire => (
+                "DELETE",
+                BTreeMap::from([("retentionDays".to_string(), json!(args.retention_days))]),
+            ),
+        };
+        CommitInfo {
+            timestamp: Some(now),
+            operation: Some(operation.to_string()),
+            operation_parameters: Some(parameters),
+            engine_info: Some(format!("plano-sync/{}", env!("CARGO_PKG_VERSION"))),
+            txn_id: Some(self.run_id.to_string()),
+        }
+    }
+
+    /// The files the log removed more than --delta-retention hours ago that are still there.
+    async fn expired(
+        &self,
+        args: &Args,
+        output: &Output,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<Path>> {
+        let retention = i64::try_from(args.delta_retention)
+            .unwrap_or(i64::MAX)
+            .saturating_mul(60 * 60 * 1000);
+        let horizon = Utc::now().timestamp_millis().saturating_sub(retention);
+        let expired: BTreeSet<&String> = snapshot
+            .removed
+            .iter()
+            .filter(|(_, remove)| remove.deletion_timestamp.unwrap_or_default() <= horizon)
+            .map(|(path, _)| path)
+            .collect();
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(output
+            .list_all(&self.table_dir)
+            .await?
+            .into_iter()
+            .filter(|path| {
+                self.relative(path)
+                    .is_some_and(|file| expired.contains(&file))
+            })
+            .collect())
+    }
+
+    /// The files staged by runs that have written nothing for [`STAGING_EXPIRY_HOURS`], which
+    /// died before they were committed.
+    async fn abandoned(&self, output: &Output) -> Result<Vec<Path>> {
+        if !self.staged {
+            return Ok(Vec::new());
+        }
+        let staging_dir = self.table_dir.clone().join(STAGING_DIR);
+        let own = self.run_id.simple().to_string();
+        let horizon = Utc::now() - TimeDelta::hours(STAGING_EXPIRY_HOURS);
+        let mut runs: BTreeMap<String, (DateTime<Utc>, Vec<Path>)> = BTreeMap::new();
+        for (path, modified) in output.list_modified(&staging_dir).await? {
+            let Some(run) = path
+                .prefix_match(&staging_dir)
+                .and_then(|mut parts| parts.next())
+            else {
+                continue;
+            };
+            if run.as_ref() == own {
+                continue;
+            }
+            let (last_modified, paths) = runs.entry(run.as_ref().to_string()).or_default();
+            *last_modified = modified.max(*last_modified);
+            paths.push(path);
+        }
+        runs.retain(|_, (last_modified, _)| *last_modified < horizon);
+        if !runs.is_empty() {
+            info!(
+                "Removing the files staged by {} runs that did not finish",
+                runs.len()
+            );
+        }
+        Ok(runs.into_values().flat_map(|(_, paths)| paths).collect())
+    }
+
+    /// The table as of the last commit in `log_dir`.
+    async fn snapshot(&self, output: &Output, log_dir: &Path) -> Result<Snapshot> {
+        let mut versions: Vec<u64> = output
+            .list(log_dir)
+            .await?
+            .iter()
+            .filter_map(|name| delta::commit_version(name))
+            .collect();
+        versions.sort_unstable();
+        let mut snapshot = Snapshot::default();
+        for version in versions {
+            let path = log_dir.clone().join(delta::commit_file(version));
+            let Some(contents) = output.read(&path).await? else {
+                continue;
+            };
+            let actions = delta::parse_commit(&contents)
+                .with_context(|| format!("reading {}", output.display(&path)))?;
+            snapshot.apply(version, actions);
+        }
+        Ok(snapshot)
+    }
+
+    /// `path` relative to the table's directory, as the marker lists it.
+    pub fn relative(&self, path: &Path) -> Option<String> {
+        let parts: Vec<_> = path.prefix_match(&self.table_dir)?.collect();
+        Some(
+            parts
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<&str>>()
+                .join("/"),
+        )
+    }
+}
+
+/// The table's metadata for files of `schema`, keeping the id and creation time of `current`.
+/// Partition keys that are not columns, such as `year`, are added to the schema as strings.
+fn table_metadata(
+    args: &Args,
+    schema: &Schema,
+    current: Option<&Metadata>,
+    now: i64,
+) -> Result<Metadata> {
+    let mut fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    for key in &args.partition_by {
+        if schema.field_with_name(key).is_err() {
+            fields.push(Field::new(key, DataType::Utf8, true));
+        }
+    }
+    Ok(Metadata {
+        id: current.map_or_else(|| Uuid::new_v4().to_string(), |current| current.id.clone()),
+        format: Format::default(),
+        schema_string: delta::delta_schema(&Schema::new(fields))?,
+        partition_columns: args.partition_by.clone(),
+        configuration: BTreeMap::new(),
+        created_time: current.map_or(Some(now), |current| current.created_time),
+    })
+}
+
+/// The partition values of the file at `path`, from its `column=value` directories.
+fn partition_values(path: &str) -> BTreeMap<String, Option<String>> {
+    let dirs = path.rsplit_once('/').map_or("", |(dirs, _)| dirs);
+    dirs.split('/')
+        .filter_map(|dir| dir.split_once('='))
+        .map(|(column, value)| (column.to_string(), decode_value(value)))
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::mode::WriteMode;
+    use object_store::memory::InMemory;
+    use std::fs::File;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+    use tempfile::tempdir;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ])
+    }
+
+    async fn commit_log(output: &Output, version: u64) -> Option<Vec<Action>> {
+        let path = Path::from(format!("events/{LOG_DIR}/{}", delta::commit_file(version)));
+        let contents = output.read(&path).await.unwrap()?;
+        Some(delta::parse_commit(&contents).unwrap())
+    }
+
+    async fn manifest(output: &Output) -> Manifest {
+        let contents = output
+            .read(&Path::from("events/_SUCCESS"))
+            .await
+            .unwrap()
+            .unwrap();
+        serde_json::from_slice(&contents).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_commit() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let args = |mode| Args {
+            table: "events".to_string(),
+            partition_by: vec!["day".to_string()],
+            mode: Some(mode),
+            ..Default::default()
+        };
+        // written before there were markers, or by a run that died
+        for path in [
+            "events/day=01/part-00000-a.parquet",
+            "events/day=02/_x.parquet",
+        ] {
+            output.write(&Path::from(path), Vec::new()).await.unwrap();
+        }
+
+        let commit = Commit::new(&args(WriteMode::Append), &output);
+        let written = [Path::from("events/day=02/part-00000-b.parquet")];
+        // on object stores, files are written where they stay
+        assert_eq!(commit.staging_path(&written[0]), written[0]);
+        output.write(&written[0], Vec::new()).await.unwrap();
+        commit
+            .commit(&args(WriteMode::Append), &output, &schema(), &written)
+            .await
+            .unwrap();
+        let first = manifest(&output).await;
+        assert_eq!(first.run_id, commit.run_id().to_string());
+        assert_eq!(
+            first.files,
+            ["day=01/part-00000-a.parquet", "day=02/part-00000-b.parquet"]
+        );
+
+        // a run that is not committed is not listed
+        output
+            .write(
+                &Path::from("events/day=03/part-00000-c.parquet"),
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+        let commit = Commit::new(&args(WriteMode::OverwritePartitions), &output);
+        let written = [Path::from("events/day=01/part-00001-d.parquet")];
+        output.write(&written[0], Vec::new()).await.unwrap();
+        commit
+            .commit(
+                &args(WriteMode::OverwritePartitions),
+                &output,
+                &schema(),
+                &written,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            manifest(&output).await.files,
+            ["day=01/part-00001-d.parquet", "day=02/part-00000-b.parquet"]
+        );
+        assert!(
+            !output
+                .exists(&Path::from("events/day=01/part-00000-a.parquet"))
+                .await
+                .unwrap()
+        );
+
+        let commit = Commit::new(&args(WriteMode::Overwrite), &output);
+        let written = [Path::from("events/day=04/part-00000-e.parquet")];
+        output.write(&written[0], Vec::new()).await.unwrap();
+        commit
+            .commit(&args(WriteMode::Overwrite), &output, &schema(), &written)
+            .await
+            .unwrap();
+        assert_eq!(
+            manifest(&output).await.files,
+            ["day=04/part-00000-e.parquet"]
+        );
+        let mut left = output.list_all(&Path::from("events")).await.unwrap();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                Path::from("events/_SUCCESS"),
+                Path::from("events/_manifests/00000000000000000001.json"),
+                Path::from("events/_manifests/00000000000000000002.json"),
+                Path::from("events/_manifests/00000000000000000003.json"),
+                Path::from("events/day=04/part-00000-e.parquet")
+            ]
+        );
+        assert_eq!(manifest(&output).await.version, 3);
+    }
+
+    #[tokio::test]
+    async fn test_commit_staged() {
+        let dir = tempdir().unwrap();
+        let output = Output::open(dir.path().to_str().unwrap()).unwrap();
+        let args = Args {
+            table: "events".to_string(),
+            partition_by: vec!["day".to_string()],
+            ..Default::default()
+        };
+        let commit = Commit::new(&args, &output);
+        let written = [Path::from("events/day=01/part-00000-a.parquet")];
+        let staged = commit.staging_path(&written[0]);
+        assert_eq!(
+            staged.to_string(),
+            format!(
+                "events/_temporary/{}/day=01/part-00000-a.parquet",
+                commit.run_id().simple()
+            )
+        );
+        output.write(&staged, Vec::new()).await.unwrap();
+        // nothing is in the partitions until the run is committed
+        assert!(!dir.path().join("events/day=01").exists());
+
+        commit
+            .commit(&args, &output, &schema(), &written)
+            .await
+            .unwrap();
+        assert!(
+            dir.path()
+                .join("events/day=01/part-00000-a.parquet")
+                .exists()
+        );
+        assert!(!dir.path().join("events/_temporary").exists());
+        assert_eq!(
+            manifest(&output).await.files,
+            ["day=01/part-00000-a.parquet"]
+        );
+
+        // left by a run that died, and by one still writing
+        let dead = Path::from("events/_temporary/dead/day=02/part-00000-b.parquet");
+        let running = Path::from("events/_temporary/running/day=02/part-00000-c.parquet");
+        for path in [&dead, &running] {
+            output.write(path, Vec::new()).await.unwrap();
+        }
+        let day_ago = SystemTime::now() - Duration::from_hours(25);
+        File::options()
+            .write(true)
+            .open(dir.path().join(dead.as_ref()))
+            .unwrap()
+            .set_modified(day_ago)
+            .unwrap();
+        let commit = Commit::new(&args, &output);
+        let written = [Path::from("events/day=01/part-00001-d.parquet")];
+        output
+            .write(&commit.staging_path(&written[0]), Vec::new())
+            .await
+            .unwrap();
+        commit
+            .commit(&args, &output, &schema(), &written)
+            .await
+            .unwrap();
+        assert!(!dir.path().join("events/_temporary/dead").exists());
+        assert!(output.exists(&running).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_commit_conflict() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let args = Args {
+            table: "events".to_string(),
+            mode: Some(WriteMode::Append),
+            ..Default::default()
+        };
+        let written = [Path::from("events/part-00000-a.parquet")];
+        output.write(&written[0], Vec::new()).await.unwrap();
+        let first = Commit::new(&args, &output);
+        first
+            .commit(&args, &output, &schema(), &written)
+            .await
+            .unwrap();
+
+        // a run that read the table before the first committed to it
+        let second = Commit::new(&args, &output);
+        let pending = Manifest {
+            version: 1,
+            run_id: second.run_id().to_string(),
+            files: vec!["part-00000-b.parquet".to_string()],
+        };
+        let error = second.create_manifest(&output, &pending).await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Another run committed version 1"),
+            "{error}"
+        );
+        assert_eq!(manifest(&output).await.run_id, first.run_id().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_roll_forward() {
+        let dir = tempdir().unwrap();
+        let output = Output::open(dir.path().to_str().unwrap()).unwrap();
+        let args = Args {
+            table: "events".to_string(),
+            mode: Some(WriteMode::Append),
+            ..Default::default()
+        };
+        // a run that died after creating its manifest, before moving its file into place
+        let died = Commit::new(&args, &output);
+        let written = Path::from("events/part-00000-a.parquet");
+        output
+            .write(&died.staging_path(&written), Vec::new())
+            .await
+            .unwrap();
+        let pending = Manifest {
+            version: 1,
+            run_id: died.run_id().to_string(),
+            files: vec!["part-00000-a.parquet".to_string()],
+        };
+        died.create_manifest(&output, &pending).await.unwrap();
+
+        let commit = Commit::new(&args, &output);
+        let written = [Path::from("events/part-00000-b.parquet")];
+        output
+            .write(&commit.staging_path(&written[0]), Vec::new())
+            .await
+            .unwrap();
+        commit
+            .commit(&args, &output, &schema(), &written)
+            .await
+            .unwrap();
+        assert!(dir.path().join("events/part-00000-a.parquet").exists());
+        assert!(!dir.path().join("events/_temporary").exists());
+        let committed = manifest(&output).await;
+        assert_eq!(committed.version, 2);
+        assert_eq!(
+            committed.files,
+            ["part-00000-a.parquet", "part-00000-b.parquet"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_log() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let args = |mode| Args {
+            table: "events".to_string(),
+            partition_by: vec!["day".to_string()],
+            mode: Some(mode),
+            delta_log: true,
+            delta_retention: 1,
+            ..Default::default()
+        };
+        let log_dir = Path::from("events/_delta_log");
+
+        let written = [
+            Path::from("events/day=01/part-00000-a.parquet"),
+            Path::from("events/day=__HIVE_DEFAULT_PARTITION__/part-00000-a.parquet"),
+        ];
+        for path in &written {
+            output.write(path, vec![0; 10]).await.unwrap();
+        }
+        let commit = Commit::new(&args(WriteMode::Append), &output);
+        commit
+            .commit(&args(WriteMode::Append), &output, &schema(), &written)
+            .await
+            .unwrap();
+        let actions = commit_log(&output, 0).await.unwrap();
+        assert_eq!(actions.len(), 5);
+        let info = actions[0].commit_info.as_ref().unwrap();
+        assert_eq!(info.operation.as_deref(), Some("WRITE"));
+        assert_eq!(info.txn_id, Some(commit.run_id().to_string()));
+        assert_eq!(actions[1].protocol.as_ref().unwrap().min_reader_version, 1);
+        let metadata = actions[2].meta_data.as_ref().unwrap();
+        assert_eq!(metadata.partition_columns, ["day"]);
+        let columns = delta::arrow_schema(&metadata.schema_string).unwrap();
+        assert_eq!(columns.field(2), &Field::new("day", DataType::Utf8, true));
+        let adds: Vec<_> = actions[3..]
+            .iter()
+            .map(|action| action.add.clone().unwrap())
+            .collect();
+        assert_eq!(adds[0].path, "day=01/part-00000-a.parquet");
+        assert_eq!(adds[0].size, 10);
+        assert_eq!(adds[0].partition_values["day"].as_deref(), Some("01"));
+        assert_eq!(adds[1].partition_values["day"], None);
+
+        // the marker is still written next to the log
+        assert_eq!(manifest(&output).await.files.len(), 2);
+
+        // nothing changed, so nothing is committed
+        let commit = Commit::new(&args(WriteMode::Append), &output);
+        commit
+            .commit(&args(WriteMode::Append), &output, &schema(), &[])
+            .await
+            .unwrap();
+        assert!(commit_log(&output, 1).await.is_none());
+
+        let written = [Path::from("events/day=01/part-00001-b.parquet")];
+        output.write(&written[0], vec![0; 20]).await.unwrap();
+        let commit = Commit::new(&args(WriteMode::OverwritePartitions), &output);
+        commit
+            .commit(
+                &args(WriteMode::OverwritePartitions),
+                &output,
+                &schema(),
+                &written,
+            )
+            .await
+            .unwrap();
+        let actions = commit_log(&output, 1).await.unwrap();
+        assert_eq!(actions.len(), 3);
+        assert_eq!(
+            actions[1].remove.as_ref().unwrap().path,
+            "day=01/part-00000-a.parquet"
+        );
+        assert_eq!(
+            actions[2].add.as_ref().unwrap().path,
+            "day=01/part-00001-b.parquet"
+        );
+
+        // the replaced file is kept for readers of version 0 until it expires
+        let replaced = Path::from("events/day=01/part-00000-a.parquet");
+        assert!(output.exists(&replaced).await.unwrap());
+        let expire = Args {
+            delta_retention: 0,
+            ..args(WriteMode::Append)
+        };
+        Commit::new(&expire, &output)
+            .commit(&expire, &output, &schema(), &[])
+            .await
+            .unwrap();
+        assert!(!output.exists(&replaced).await.unwrap());
+        assert!(commit_log(&output, 2).await.is_none());
+
+        let snapshot = commit.snapshot(&output, &log_dir).await.unwrap();
+        assert_eq!(snapshot.version, Some(1));
+        assert_eq!(
+            snapshot.files.keys().collect::<Vec<_>>(),
+            [
+                "day=01/part-00001-b.parquet",
+                "day=__HIVE_DEFAULT_PARTITION__/part-00000-a.parquet"
+            ]
+        );
+    }
+}
@@ -0,0 +1,493 @@
+///
+/// Rewriting the small files of synced tables into fewer, larger ones
+///
+use anyhow::{Result, bail};
+use arrow::datatypes::SchemaRef;
+use clap::ArgAction;
+use futures::TryStreamExt;
+use plano_core::bucket::file_bucket;
+use plano_core::delta::LOG_DIR;
+use rds_sync::DEFAULT_BATCH_SIZE;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::{info, warn};
+
+use crate::commit::{Commit, Operation};
+use crate::mode::WriteMode;
+use crate::output::Output;
+use crate::partitions::{Group, PartitionWriters, close_partitions};
+use crate::properties::ParquetOptions;
+use crate::sort::SortOrder;
+use crate::{Args, parse_positive};
+
+const MIB: u64 = 1024 * 1024;
+
+/// Arguments of `plano-sync compact`
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct CompactArgs {
+    /// Name of a synced table to compact, as its directory in --output-dir is named (can
+    /// repeat)
+    #[arg(short = 't', long = "table", value_name = "TABLE", required = true, action = ArgAction::Append)]
+    tables: Vec<String>,
+
+    /// Directory the tables were synced to (default: /tmp): a local path, or a `file://` or
+    /// `s3://bucket/prefix` URL
+    #[arg(long, short, default_value = "/tmp")]
+    output_dir: String,
+
+    /// MiB at which a rewritten file is closed and the next one started, as estimated while
+    /// it is written
+    #[arg(long, default_value_t = 128, value_parser = parse_positive)]
+    target_file_size: usize,
+
+    /// Files smaller than this many MiB are rewritten, in the partitions (and buckets) that
+    /// have more than one (default: half the --target-file-size)
+    #[arg(long, value_parser = parse_positive)]
+    min_file_size: Option<usize>,
+
+    /// Sort the rows of each rewritten file by these columns (can repeat or be
+    /// comma-separated), e.g. `ts` or `name,ts desc`
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append)]
+    sort_by: Vec<String>,
+
+    /// MiB of rows held in memory for --sort-by before they are spilled, sorted, to temporary
+    /// files
+    #[arg(long, default_value_t = 256, value_parser = parse_positive)]
+    sort_memory: usize,
+
+    /// Maximum number of rows read from a file and held in memory at a time
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE, value_parser = parse_positive)]
+    batch_size: usize,
+
+    /// For tables with a `_delta_log`, hours the replaced files are kept for readers of older
+    /// versions before a later run deletes them
+    #[arg(long, default_value_t = 168)]
+    delta_retention: u64,
+
+    /// Only report which files would be rewritten
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    parquet: ParquetOptions,
+}
+
+impl CompactArgs {
+    /// The sync arguments that write and commit files the way compacting `table` does.
+    fn table_args(&self, table: &str) -> Args {
+        Args {
+            tables: vec![table.to_string()],
+            table: table.to_string(),
+            output_dir: self.output_dir.clone(),
+            // new files are numbered after those already in each partition
+            mode: Some(WriteMode::Append),
+            sort_by: self.sort_by.clone(),
+            sort_memory: self.sort_memory,
+            max_file_size: Some(self.target_file_size),
+            batch_size: self.batch_size,
+            delta_retention: self.delta_retention,
+            parquet: self.parquet.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The size in bytes under which files are rewritten.
+    fn min_file_bytes(&self) -> u64 {
+        let target = self.target_file_size as u64 * MIB;
+        self.min_file_size
+            .map_or(target / 2, |size| size as u64 * MIB)
+    }
+}
+
+/// The small files of one partition, or of one bucket of it, which are rewritten together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rewrite {
+    group: Group,
+    /// The files, relative to the table's directory, and their sizes.
+    files: Vec<(String, u64)>,
+}
+
+impl Rewrite {
+    fn bytes(&self) -> u64 {
+        self.files.iter().map(|(_, size)| size).sum()
+    }
+}
+
+/// Compacts each of the `--table`s in turn.
+///
+/// # Errors
+///
+/// Will return `Err` if the output directory cannot be opened or a table cannot be compacted.
+pub async fn compact_tables(args: &CompactArgs) -> Result<()> {
+    let output = Output::open(&args.output_dir)?;
+    for table in &args.tables {
+        compact_table(args, &output, table).await?;
+    }
+    Ok(())
+}
+
+/// Rewrites the small files of each partition of `table` into files of the target size, and
+/// commits them in place of the originals in one step, as a sync run does.  Returns the number
+/// of files rewritten, or that would be with --dry-run.
+async fn compact_table(args: &CompactArgs, output: &Output, table: &str) -> Result<usize> {
+    let mut table_args = args.table_args(table);
+    let table_dir = table_args.table_dir();
+    table_args.delta_log = !output
+        .list(&table_dir.clone().join(LOG_DIR))
+        .await?
+        .is_empty();
+    let commit = Commit::new(&table_args, output).with_operation(Operation::Compact);
+    let files = commit.files(output).await?;
+    if files.is_empty() {
+        bail!("{} has no files to compact", output.display(&table_dir));
+    }
+    let sizes: HashMap<String, u64> = output
+        .list_sizes(&table_dir)
+        .await?
+        .into_iter()
+        .filter_map(|(path, size)| Some((commit.relative(&path)?, size)))
+        .collect();
+    let rewrites = plan(&files, &sizes, args.min_file_bytes());
+    report(
+        output,
+        &table_args,
+        &rewrites,
+        if args.dry_run {
+            "Would rewrite"
+        } else {
+            "Rewriting"
+        },
+    );
+    let count = rewrites.iter().map(|rewrite| rewrite.files.len()).sum();
+    if rewrites.is_empty() {
+        info!("{table} has no small files to compact");
+        return Ok(0);
+    }
+    if args.dry_run {
+        info!(
+            "Would rewrite {count} files of {table} in {} partitions",
+            rewrites.len()
+        );
+        return Ok(count);
+    }
+
+    // the files are written with the schema of the table's first small file
+    let first = commit.absolute(&rewrites[0].files[0].0)?;
+    let schema = output
+        .parquet_reader(&first, table_args.batch_size)
+        .await?
+        .schema()
+        .clone();
+    let mut writers = writers(&commit, &table_args, &schema)?;
+
+    let mut replaced = Vec::new();
+    for rewrite in &rewrites {
+        let mut readers = Vec::with_capacity(rewrite.files.len());
+        for (file, _) in &rewrite.files {
+            let path = commit.absolute(file)?;
+            readers.push(output.parquet_reader(&path, table_args.batch_size).await?);
+        }
+        // files written before a column was added or changed are left as they are
+        if readers
+            .iter()
+            .any(|reader| reader.schema().fields() != schema.fields())
+        {
+            warn!(
+                "Leaving {} of {table} as it is; its files have different columns",
+                rewrite.group.0
+            );
+            continue;
+        }
+        for mut reader in readers {
+            while let Some(batch) = reader.try_next().await? {
+                writers
+                    .add(output, &table_args, &schema, &rewrite.group, batch)
+                    .await?;
+            }
+        }
+        // the group's files are done with, so none of them is held open for the next
+        writers
+            .close_group(output, &table_args, &schema, &rewrite.group)
+            .await?;
+        for (file, _) in &rewrite.files {
+            replaced.push(commit.absolute(file)?);
+        }
+    }
+    if replaced.is_empty() {
+        return Ok(0);
+    }
+    let written = close_partitions(output, &table_args, &schema, writers).await?;
+    commit
+        .replace(&table_args, output, &schema, &written, replaced.clone())
+        .await?;
+    info!(
+        "Compacted {} files of {table} into {}",
+        replaced.len(),
+        written.len()
+    );
+    Ok(replaced.len())
+}
+
+/// Writers of the rewritten files, rolling at the target size and sorted by the --sort-by.
+fn writers(commit: &Commit, args: &Args, schema: &SchemaRef) -> Result<PartitionWriters> {
+    let mut props = args.parquet.properties(schema)?;
+    let order = SortOrder::new(&args.sort_by, schema)?;
+    if let Some(order) = &order {
+        props = order.record(props)?;
+    }
+    let mut writers =
+        PartitionWriters::new(commit.clone(), props).with_limits(None, args.max_file_bytes());
+    if let Some(order) = order {
+        writers = writers.with_sort(order, args.batch_size, args.sort_memory_bytes());
+    }
+    Ok(writers)
+}
+
+/// The committed `files` smaller than `min_bytes`, by partition and bucket, of the partitions
+/// and buckets with more than one of them.
+fn plan(files: &BTreeSet<String>, sizes: &HashMap<String, u64>, min_bytes: u64) -> Vec<Rewrite> {
+    let mut groups: BTreeMap<Group, Vec<(String, u64)>> = BTreeMap::new();
+    for file in files {
+        let Some(size) = sizes.get(file) else {
+            continue;
+        };
+        if *size >= min_bytes {
+            continue;
+        }
+        let (partition, name) = file.rsplit_once('/').unwrap_or(("", file));
+        groups
+            .entry((partition.to_string(), file_bucket(name)))
+            .or_default()
+            .push((file.clone(), *size));
+    }
+    groups
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(group, files)| Rewrite { group, files })
+        .collect()
+}
+
+/// Logs what is done, or would be, with each partition's files.
+fn report(output: &Output, args: &Args, rewrites: &[Rewrite], doing: &str) {
+    let table_dir = output.display(&args.table_dir());
+    let target = args.max_file_bytes().unwrap_or_default() as u64;
+    for rewrite in rewrites {
+        let (partition, bucket) = &rewrite.group;
+        let dir = if partition.is_empty() {
+            table_dir.clone()
+        } else {
+            format!("{table_dir}/{partition}")
+        };
+        let bucket = bucket.map_or_else(String::new, |bucket| format!(" bucket {bucket}"));
+        info!(
+            "{doing} {} files of {} in {dir}{bucket} into about {}",
+            rewrite.files.len(),
+            mib(rewrite.bytes()),
+            rewrite.bytes().div_ceil(target.max(1)),
+        );
+    }
+}
+
+/// `bytes` in MiB, to one decimal place.
+pub fn mib(bytes: u64) -> String {
+    format!("{}.{} MiB", bytes / MIB, bytes % MIB * 10 / MIB)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, RecordBatch};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use parquet::file::properties::WriterProperties;
+    use plano_core::delta;
+    use std::sync::Arc;
+
+    /// Writes a file of the `ids` to `path` under the output's `events` table.
+    async fn write_file(output: &Output, path: &str, ids: Vec<i64>) -> Path {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(ids))]).unwrap();
+        let path = Path::from(format!("events/{path}"));
+        let mut writer = output
+            .parquet_writer(&path, schema, WriterProperties::default())
+            .unwrap();
+        writer.write(&batch).await.unwrap();
+        writer.close().await.unwrap();
+        path
+    }
+
+    /// The ids in the table's files, in the order they are in each file.
+    async fn ids(output: &Output, files: &BTreeSet<String>) -> Vec<Vec<i64>> {
+        let mut ids = Vec::new();
+        for file in files {
+            let path = Path::parse(format!("events/{file}")).unwrap();
+            let batches: Vec<RecordBatch> = output
+                .parquet_reader(&path, 100)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            ids.push(
+                batches
+                    .iter()
+                    .flat_map(|batch| {
+                        let column = batch.column(0).as_any();
+                        column
+                            .downcast_ref::<Int64Array>()
+                            .unwrap()
+                            .values()
+                            .to_vec()
+                    })
+                    .collect(),
+            );
+        }
+        ids
+    }
+
+    fn compact_args() -> CompactArgs {
+        CompactArgs {
+            tables: vec!["events".to_string()],
+            target_file_size: 1,
+            batch_size: 100,
+            sort_memory: 1,
+            delta_retention: 168,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let files: BTreeSet<String> = [
+            "day=01/part-00000-a.parquet",
+            "day=01/part-00001-b.parquet",
+            "day=01/part-00002-c.parquet",
+            "day=02/part-00000-a.parquet",
+            "day=02/part-00001-gone.parquet",
+            "day=03/part-00000-a_00001.parquet",
+            "day=03/part-00001-b_00001.parquet",
+            "day=03/part-00002-b_00002.parquet",
+            "part-00000-a.parquet",
+            "part-00001-b.parquet",
+        ]
+        .map(ToString::to_string)
+        .into();
+        let mut sizes: HashMap<String, u64> = files.iter().map(|file| (file.clone(), 10)).collect();
+        sizes.insert("day=01/part-00002-c.parquet".to_string(), 100);
+        sizes.remove("day=02/part-00001-gone.parquet");
+        let plan = plan(&files, &sizes, 100);
+        let groups: Vec<(&str, Option<u32>, usize)> = plan
+            .iter()
+            .map(|rewrite| {
+                let (partition, bucket) = &rewrite.group;
+                (partition.as_str(), *bucket, rewrite.files.len())
+            })
+            .collect();
+        // large files, and partitions and buckets with a single small file, are left alone
+        assert_eq!(
+            groups,
+            [("", None, 2), ("day=01", None, 2), ("day=03", Some(1), 2)]
+        );
+        assert_eq!(plan[1].bytes(), 20);
+        assert_eq!(mib(MIB * 3 / 2), "1.5 MiB");
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        for (path, ids) in [
+            ("day=01/part-00000-a.parquet", vec![3, 1]),
+            ("day=01/part-00001-b.parquet", vec![2]),
+            ("day=02/part-00000-a.parquet", vec![4]),
+        ] {
+            write_file(&output, path, ids).await;
+        }
+        let args = CompactArgs {
+            dry_run: true,
+            ..compact_args()
+        };
+        assert_eq!(compact_table(&args, &output, "events").await.unwrap(), 2);
+        let commit = Commit::new(&args.table_args("events"), &output);
+        assert_eq!(commit.files(&output).await.unwrap().len(), 3);
+
+        let args = CompactArgs {
+            sort_by: vec!["id".to_string()],
+            ..compact_args()
+        };
+        assert_eq!(compact_table(&args, &output, "events").await.unwrap(), 2);
+        let files = commit.files(&output).await.unwrap();
+        let names: Vec<&str> = files.iter().map(String::as_str).collect();
+        assert_eq!(names[1], "day=02/part-00000-a.parquet");
+        // the new file is numbered after those it replaces
+        assert!(names[0].starts_with("day=01/part-00002-"), "{names:?}");
+        assert_eq!(ids(&output, &files).await, [vec![1, 2, 3], vec![4]]);
+        // the marker, the manifest it copies and the two data files
+        let all = output.list_all(&Path::from("events")).await.unwrap();
+        assert_eq!(all.len(), 4, "{all:?}");
+
+        // nothing is left to compact
+        assert_eq!(compact_table(&args, &output, "events").await.unwrap(), 0);
+        assert!(
+            compact_table(&args, &output, "missing")
+                .await
+                .unwrap_err()
+                .to_string()
+                .contains("no files to compact")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_logged() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let mut written = Vec::new();
+        for (path, ids) in [
+            ("day=01/part-00000-a_00000.parquet", vec![1]),
+            ("day=01/part-00001-b_00000.parquet", vec![2]),
+            ("day=01/part-00002-b_00001.parquet", vec![3]),
+        ] {
+            written.push(write_file(&output, path, ids).await);
+        }
+        let sync_args = Args {
+            table: "events".to_string(),
+            partition_by: vec!["day".to_string()],
+            mode: Some(WriteMode::Append),
+            delta_log: true,
+            ..Default::default()
+        };
+        let schema = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        Commit::new(&sync_args, &output)
+            .commit(&sync_args, &output, &schema, &written)
+            .await
+            .unwrap();
+
+        // the buckets are compacted apart
+        assert_eq!(
+            compact_table(&compact_args(), &output, "events")
+                .await
+                .unwrap(),
+            2
+        );
+        let contents = output
+            .read(&Path::from(format!(
+                "events/{LOG_DIR}/{}",
+                delta::commit_file(1)
+            )))
+            .await
+            .unwrap()
+            .unwrap();
+        let actions = delta::parse_commit(&contents).unwrap();
+        let info = actions[0].commit_info.as_ref().unwrap();
+        assert_eq!(info.operation.as_deref(), Some("OPTIMIZE"));
+        let removes: Vec<_> = actions.iter().filter_map(|a| a.remove.as_ref()).collect();
+        let adds: Vec<_> = actions.iter().filter_map(|a| a.add.as_ref()).collect();
+        assert_eq!(removes.len(), 2);
+        assert_eq!(adds.len(), 1);
+        assert!(adds[0].path.ends_with("_00000.parquet"), "{}", adds[0].path);
+        assert!(!adds[0].data_change && !removes[0].data_change);
+        assert_eq!(adds[0].partition_values["day"].as_deref(), Some("01"));
+        // the files replaced are kept for readers of the log's earlier versions
+        assert!(output.exists(&written[0]).await.unwrap());
+    }
+}
@@ -0,0 +1,264 @@
+///
+/// Per-table settings read from the `--config` file
+///
+use anyhow::{Context, Result, bail};
+use rds_sync::TableRef;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::Args;
+use crate::properties::ParquetOptions;
+
+/// The contents of a `--config` file: settings that replace the command line's for some
+/// tables, keyed by table name (`orders`) or qualified name (`sales.orders`).
+///
+/// ```toml
+/// [tables.orders]
+/// partition_by = ["year", "month"]
+/// timestamp_col = "created_at"
+/// incremental_col = "id"
+///
+/// [tables.orders.parquet]
+/// compression = "zstd"
+/// bloom_filters = ["customer_id"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    #[serde(default)]
+    tables: BTreeMap<String, TableConfig>,
+}
+
+/// Settings for one table; each one that is set replaces the command-line option of the same
+/// name.  `retention_days` is only read by `plano-sync expire`, where it replaces --keep-days.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableConfig {
+    partition_by: Option<Vec<String>>,
+    timestamp_col: Option<String>,
+    partition_tz: Option<String>,
+    bucket_by: Option<String>,
+    buckets: Option<u32>,
+    sort_by: Option<Vec<String>>,
+    max_file_rows: Option<usize>,
+    max_file_size: Option<usize>,
+    delta_log: Option<bool>,
+    delta_retention: Option<u64>,
+    retention_days: Option<u64>,
+    incremental_col: Option<String>,
+    split_col: Option<String>,
+    columns: Option<Vec<String>>,
+    exclude_columns: Option<Vec<String>>,
+    #[serde(rename = "where")]
+    filter: Option<String>,
+    parquet: Option<ParquetOptions>,
+}
+
+impl SyncConfig {
+    /// Reads a config file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be read or parsed, or if a table name is invalid.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+        for name in config.tables.keys() {
+            TableRef::parse(name).with_context(|| format!("in {}", path.display()))?;
+        }
+        Ok(config)
+    }
+
+    /// Applies the settings for the resolved `table` to its `args`.  Settings under the
+    /// qualified name take precedence over those under the bare name.
+    pub fn apply(&self, table: &TableRef, args: &mut Args) {
+        let mut matches: Vec<(TableRef, &TableConfig)> = self
+            .entries()
+            .filter(|(key, _)| key_matches(key, table))
+            .collect();
+        matches.sort_by_key(|(key, _)| key.schema().is_some());
+        for (_, settings) in matches {
+            settings.apply(args);
+        }
+    }
+
+    /// Checks that every table in the file is one of `tables`, so that a misspelt name is not
+    /// silently ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` naming the tables that are not being synced.
+    pub fn check_used(&self, tables: &[TableRef]) -> Result<()> {
+        let unused: Vec<String> = self
+            .entries()
+            .filter(|(key, _)| !tables.iter().any(|table| key_matches(key, table)))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        if !unused.is_empty() {
+            bail!(
+                "The config has settings for tables that are not being synced: {}",
+                unused.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// The names of the tables the file has settings for, as their output directories are
+    /// named.
+    pub fn table_names(&self) -> BTreeSet<String> {
+        self.entries()
+            .map(|(key, _)| key.name().to_string())
+            .collect()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (TableRef, &TableConfig)> {
+        // names were checked when the file was loaded
+        self.tables
+            .iter()
+            .filter_map(|(name, settings)| Some((TableRef::parse(name).ok()?, settings)))
+    }
+}
+
+impl TableConfig {
+    fn apply(&self, args: &mut Args) {
+        if let Some(partition_by) = &self.partition_by {
+            args.partition_by.clone_from(partition_by);
+        }
+        if let Some(column) = &self.timestamp_col {
+            args.timestamp_col = Some(column.clone());
+        }
+        if let Some(tz) = &self.partition_tz {
+            args.partition_tz = Some(tz.clone());
+        }
+        if let Some(column) = &self.bucket_by {
+            args.bucket_by = Some(column.clone());
+        }
+        if let Some(buckets) = self.buckets {
+            args.buckets = Some(buckets);
+        }
+        if let Some(sort_by) = &self.sort_by {
+            args.sort_by.clone_from(sort_by);
+        }
+        if let Some(rows) = self.max_file_rows {
+            args.max_file_rows = Some(rows);
+        }
+        if let Some(size) = self.max_file_size {
+            args.max_file_size = Some(size);
+        }
+        if let Some(delta_log) = self.delta_log {
+            args.delta_log = delta_log;
+        }
+        if let Some(hours) = self.delta_retention {
+            args.delta_retention = hours;
+        }
+        if let Some(days) = self.retention_days {
+            args.retention_days = Some(days);
+        }
+        if let Some(column) = &self.incremental_col {
+            args.incremental_col = Some(column.clone());
+        }
+        if let Some(column) = &self.split_col {
+            args.split_col = Some(column.clone());
+        }
+        if let Some(columns) = &self.columns {
+            args.columns.clone_from(columns);
+        }
+        if let Some(columns) = &self.exclude_columns {
+            args.exclude_columns.clone_from(columns);
+        }
+        if let Some(filter) = &self.filter {
+            args.filter = Some(filter.clone());
+        }
+        if let Some(parquet) = &self.parquet {
+            parquet.apply(&mut args.parquet);
+        }
+    }
+}
+
+/// Whether the config key `key` names the resolved `table`.
+fn key_matches(key: &TableRef, table: &TableRef) -> bool {
+    key.name() == table.name()
+        && key
+            .schema()
+            .is_none_or(|schema| table.schema() == Some(schema))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::Schema;
+    use parquet::basic::{Compression, ZstdLevel};
+    use parquet::schema::types::ColumnPath;
+
+    const CONFIG: &str = r#"
+        [tables.orders]
+        partition_by = ["year", "month"]
+        timestamp_col = "created_at"
+
+        [tables."sales.orders"]
+        incremental_col = "id"
+        partition_by = ["region"]
+
+        [tables.events]
+        where = "kind <> 'debug'"
+        retention_days = 30
+
+        [tables.events.parquet]
+        compression = "zstd"
+    "#;
+
+    #[test]
+    fn test_apply() {
+        let config: SyncConfig = toml::from_str(CONFIG).unwrap();
+        let mut args = Args {
+            partition_by: vec!["day".to_string()],
+            ..Default::default()
+        };
+        config.apply(&TableRef::new(Some("sales"), "orders"), &mut args);
+        assert_eq!(args.partition_by, ["region"]);
+        assert_eq!(args.timestamp_col.as_deref(), Some("created_at"));
+        assert_eq!(args.incremental_col.as_deref(), Some("id"));
+
+        let mut args = Args::default();
+        config.apply(&TableRef::new(Some("public"), "orders"), &mut args);
+        assert_eq!(args.partition_by, ["year", "month"]);
+        assert_eq!(args.incremental_col, None);
+
+        let mut args = Args::default();
+        config.apply(&TableRef::new(Some("public"), "users"), &mut args);
+        assert!(args.partition_by.is_empty());
+
+        let mut args = Args::default();
+        config.apply(&TableRef::new(None, "events"), &mut args);
+        assert_eq!(args.retention_days, Some(30));
+        let props = args.parquet.properties(&Schema::empty()).unwrap();
+        assert_eq!(
+            props.compression(&ColumnPath::from("id")),
+            Compression::ZSTD(ZstdLevel::default())
+        );
+    }
+
+    #[test]
+    fn test_check_used() {
+        let config: SyncConfig = toml::from_str(CONFIG).unwrap();
+        let tables = [
+            TableRef::new(Some("sales"), "orders"),
+            TableRef::new(Some("public"), "events"),
+        ];
+        assert!(config.check_used(&tables).is_ok());
+        let error = config.check_used(&tables[..1]).unwrap_err().to_string();
+        assert!(error.ends_with("\"events\""), "{error}");
+        let names: Vec<String> = config.table_names().into_iter().collect();
+        assert_eq!(names, ["events", "orders"]);
+    }
+
+    #[test]
+    fn test_unknown_setting() {
+        assert!(toml::from_str::<SyncConfig>("[tables.orders]\npartition = [\"day\"]").is_err());
+    }
+}
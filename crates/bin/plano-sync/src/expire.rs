@@ -0,0 +1,340 @@
+///
+/// Deleting the time partitions of synced tables once they are past their retention
+///
+use anyhow::{Result, bail};
+use arrow::datatypes::Schema;
+use chrono::{NaiveDateTime, SubsecRound, TimeDelta, Utc};
+use clap::ArgAction;
+use plano_core::delta::LOG_DIR;
+use rds_sync::TableRef;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::Args;
+use crate::commit::{Commit, Operation};
+use crate::compact::mib;
+use crate::config::SyncConfig;
+use crate::output::Output;
+use crate::time_partition::{parse_tz, partition_end};
+
+/// Arguments of `plano-sync expire`
+#[derive(clap::Args, Debug, Default, Clone)]
+pub struct ExpireArgs {
+    /// Name of a synced table to expire, as its directory in --output-dir is named (can
+    /// repeat; default: the tables in the --config)
+    #[arg(short = 't', long = "table", value_name = "TABLE", required_unless_present = "config", action = ArgAction::Append)]
+    tables: Vec<String>,
+
+    /// Directory the tables were synced to (default: /tmp): a local path, or a `file://` or
+    /// `s3://bucket/prefix` URL
+    #[arg(long, short, default_value = "/tmp")]
+    output_dir: String,
+
+    /// Delete the partitions whose year, quarter, month, date, day, hour or minute ended more
+    /// than this many days ago
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    keep_days: Option<u64>,
+
+    /// Time zone the tables' time partitions are in, as --partition-tz was when they were
+    /// synced (default: UTC)
+    #[arg(long)]
+    partition_tz: Option<String>,
+
+    /// TOML file of per-table settings, as given when syncing, whose `retention_days` replaces
+    /// --keep-days for its tables
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// For tables with a `_delta_log`, hours the expired files are kept for readers of older
+    /// versions before a later run deletes them
+    #[arg(long, default_value_t = 168)]
+    delta_retention: u64,
+
+    /// Only report which partitions would be deleted
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl ExpireArgs {
+    /// The sync arguments that commit `table` the way expiring its partitions does.
+    fn table_args(&self, table: &str) -> Args {
+        Args {
+            tables: vec![table.to_string()],
+            table: table.to_string(),
+            output_dir: self.output_dir.clone(),
+            partition_tz: self.partition_tz.clone(),
+            delta_retention: self.delta_retention,
+            retention_days: self.keep_days,
+            ..Default::default()
+        }
+    }
+}
+
+/// Expires the partitions of each of the `--table`s in turn, or of the tables in the --config
+/// that have a retention.
+///
+/// # Errors
+///
+/// Will return `Err` if the config cannot be read, the output directory cannot be opened, a
+/// `--table` has no retention or a table cannot be expired.
+pub async fn expire_tables(args: &ExpireArgs) -> Result<()> {
+    let config = args
+        .config
+        .as_deref()
+        .map(SyncConfig::load)
+        .transpose()?
+        .unwrap_or_default();
+    let output = Output::open(&args.output_dir)?;
+    let listed = !args.tables.is_empty();
+    let tables = if listed {
+        args.tables.clone()
+    } else {
+        config.table_names().into_iter().collect()
+    };
+    for table in &tables {
+        let mut table_args = args.table_args(table);
+        config.apply(&TableRef::parse(table)?, &mut table_args);
+        if table_args.retention_days.is_none() {
+            if listed {
+                bail!("{table} has no retention; set --keep-days or its `retention_days`");
+            }
+            info!("Keeping all partitions of {table}, which has no `retention_days`");
+            continue;
+        }
+        expire_table(&mut table_args, &output, args.dry_run).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the time partitions of `args.table` that ended more than `args.retention_days` ago,
+/// and commits the table without them, as a sync run does.  Returns the number of files
+/// deleted, or that would be with `dry_run`.
+async fn expire_table(args: &mut Args, output: &Output, dry_run: bool) -> Result<usize> {
+    let table = args.table.clone();
+    let table_dir = args.table_dir();
+    args.delta_log = !output
+        .list(&table_dir.clone().join(LOG_DIR))
+        .await?
+        .is_empty();
+    let tz = parse_tz(args.partition_tz.as_deref().unwrap_or("UTC"))?;
+    let Some(days) = args.retention_days else {
+        bail!("{table} has no retention");
+    };
+    let now = Utc::now().with_timezone(&tz).naive_local().trunc_subsecs(0);
+    let cutoff = i64::try_from(days)
+        .ok()
+        .and_then(TimeDelta::try_days)
+        .and_then(|age| now.checked_sub_signed(age))
+        .unwrap_or(NaiveDateTime::MIN);
+
+    let commit = Commit::new(args, output).with_operation(Operation::Expire);
+    let files = commit.files(output).await?;
+    if files.is_empty() {
+        bail!("{} has no files to expire", output.display(&table_dir));
+    }
+    let ends: Vec<(&String, Option<NaiveDateTime>)> = files
+        .iter()
+        .map(|file| (file, partition_end(file)))
+        .collect();
+    if ends.iter().all(|(_, end)| end.is_none()) {
+        bail!(
+            "{} is not partitioned by year or date, so has no partitions to expire",
+            output.display(&table_dir)
+        );
+    }
+    let mut expired: BTreeMap<&str, Vec<&String>> = BTreeMap::new();
+    for (file, end) in ends {
+        if end.is_some_and(|end| end <= cutoff) {
+            let (partition, _) = file.rsplit_once('/').unwrap_or_default();
+            expired.entry(partition).or_default().push(file);
+        }
+    }
+    let count = expired.values().map(Vec::len).sum();
+    if expired.is_empty() {
+        info!("{table} has no partitions that ended before {cutoff}");
+        return Ok(0);
+    }
+    report(
+        output,
+        args,
+        &commit,
+        &expired,
+        if dry_run { "Would expire" } else { "Expiring" },
+    )
+    .await?;
+    if dry_run {
+        info!(
+            "Would expire {count} files of {table} in {} partitions that ended before {cutoff}",
+            expired.len()
+        );
+        return Ok(count);
+    }
+
+    let stale = expired
+        .values()
+        .flatten()
+        .map(|file| commit.absolute(file))
+        .collect::<Result<Vec<_>>>()?;
+    commit
+        .replace(args, output, &Schema::empty(), &[], stale)
+        .await?;
+    info!(
+        "Expired {count} files of {table} in {} partitions that ended before {cutoff}",
+        expired.len()
+    );
+    Ok(count)
+}
+
+/// Logs each partition that is expired, or would be, with the number and size of its files.
+async fn report(
+    output: &Output,
+    args: &Args,
+    commit: &Commit,
+    expired: &BTreeMap<&str, Vec<&String>>,
+    doing: &str,
+) -> Result<()> {
+    let table_dir = args.table_dir();
+    let sizes: HashMap<String, u64> = output
+        .list_sizes(&table_dir)
+        .await?
+        .into_iter()
+        .filter_map(|(path, size)| Some((commit.relative(&path)?, size)))
+        .collect();
+    for (partition, files) in expired {
+        let bytes: u64 = files.iter().filter_map(|file| sizes.get(*file)).sum();
+        info!(
+            "{doing} {}/{partition}: {} files of {}",
+            output.display(&table_dir),
+            files.len(),
+            mib(bytes)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::mode::WriteMode;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use plano_core::delta;
+    use std::sync::Arc;
+
+    const FILES: [&str; 4] = [
+        "year=2000/month=01/part-00000-a.parquet",
+        "year=2000/month=02/part-00000-a.parquet",
+        "year=2999/month=01/part-00000-a.parquet",
+        "year=__HIVE_DEFAULT_PARTITION__/month=__HIVE_DEFAULT_PARTITION__/part-00000-a.parquet",
+    ];
+
+    /// Writes the `FILES` of the output's `events` table and commits them.
+    async fn write_table(output: &Output, delta_log: bool) -> Vec<Path> {
+        let mut written = Vec::new();
+        for file in FILES {
+            let path = Path::parse(format!("events/{file}")).unwrap();
+            output.write(&path, b"rows".to_vec()).await.unwrap();
+            written.push(path);
+        }
+        let sync_args = Args {
+            table: "events".to_string(),
+            partition_by: vec!["year".to_string(), "month".to_string()],
+            mode: Some(WriteMode::Append),
+            delta_log,
+            ..Default::default()
+        };
+        Commit::new(&sync_args, output)
+            .commit(&sync_args, output, &Schema::empty(), &written)
+            .await
+            .unwrap();
+        written
+    }
+
+    fn expire_args() -> Args {
+        ExpireArgs {
+            keep_days: Some(30),
+            delta_retention: 168,
+            ..Default::default()
+        }
+        .table_args("events")
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let written = write_table(&output, false).await;
+        let commit = Commit::new(&expire_args(), &output);
+        assert_eq!(
+            expire_table(&mut expire_args(), &output, true)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(commit.files(&output).await.unwrap().len(), 4);
+
+        assert_eq!(
+            expire_table(&mut expire_args(), &output, false)
+                .await
+                .unwrap(),
+            2
+        );
+        // partitions still in their retention, and rows without a time, are kept
+        let files: Vec<String> = commit.files(&output).await.unwrap().into_iter().collect();
+        assert_eq!(files, [FILES[2], FILES[3]]);
+        assert!(!output.exists(&written[0]).await.unwrap());
+        assert!(output.exists(&written[2]).await.unwrap());
+        assert_eq!(
+            expire_table(&mut expire_args(), &output, false)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let mut args = expire_args();
+        args.table = "missing".to_string();
+        let error = expire_table(&mut args, &output, false).await.unwrap_err();
+        assert!(error.to_string().contains("no files to expire"), "{error}");
+        output
+            .write(&Path::from("plain/plain.parquet"), b"rows".to_vec())
+            .await
+            .unwrap();
+        args.table = "plain".to_string();
+        let error = expire_table(&mut args, &output, false).await.unwrap_err();
+        assert!(error.to_string().contains("not partitioned"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_expire_logged() {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        let written = write_table(&output, true).await;
+        assert_eq!(
+            expire_table(&mut expire_args(), &output, false)
+                .await
+                .unwrap(),
+            2
+        );
+        let contents = output
+            .read(&Path::from(format!(
+                "events/{LOG_DIR}/{}",
+                delta::commit_file(1)
+            )))
+            .await
+            .unwrap()
+            .unwrap();
+        let actions = delta::parse_commit(&contents).unwrap();
+        let info = actions[0].commit_info.as_ref().unwrap();
+        assert_eq!(info.operation.as_deref(), Some("DELETE"));
+        assert_eq!(
+            info.operation_parameters.as_ref().unwrap()["retentionDays"],
+            30
+        );
+        let removes: Vec<_> = actions.iter().filter_map(|a| a.remove.as_ref()).collect();
+        assert_eq!(removes.len(), 2);
+        assert!(removes.iter().all(|remove| remove.data_change));
+        assert_eq!(actions.len(), 3, "{actions:?}");
+        // the expired files are kept for readers of the log's earlier versions
+        assert!(output.exists(&written[0]).await.unwrap());
+    }
+}
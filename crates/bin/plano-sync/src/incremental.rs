@@ -0,0 +1,86 @@
+///
+/// Incremental syncs that only read rows beyond the previous run's high-water mark
+///
+use anyhow::bail;
+use arrow::datatypes::Schema;
+use futures::StreamExt;
+use rds_sync::{SourceConnector, StreamOptions, Watermark};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use crate::output::Output;
+use crate::partitions::write_partitioned_files;
+use crate::source::Source;
+use crate::state::SyncState;
+use crate::{Args, print_batches_if};
+
+/// Syncs the rows whose `column` is beyond the saved watermark, then advances the watermark.
+/// Returns the number of rows written.
+///
+/// The watermark is only saved once every file has been closed, so a failed run is retried from
+/// the same position.
+pub async fn sync_incremental(
+    args: &Args,
+    output: &Output,
+    connector: &dyn SourceConnector,
+    source: &Source,
+    schema_ref: &Arc<Schema>,
+    mut options: StreamOptions,
+    column: &str,
+) -> anyhow::Result<usize> {
+    let Ok(column_idx) = schema_ref.index_of(column) else {
+        bail!("Incremental column `{column}` is not among the extracted columns of {source}");
+    };
+    let data_type = schema_ref.field(column_idx).data_type();
+    if !Watermark::supports(data_type) {
+        bail!(
+            "Cannot use `{column}` of type {data_type} as a watermark; use an integer, date or timestamp column"
+        );
+    }
+
+    let table_dir = args.table_dir();
+    let previous = SyncState::load(output, &table_dir, &source.to_string(), column).await?;
+    if let Some(previous) = &previous {
+        info!("Syncing rows with {column} > {:?}", previous.watermark);
+        options = options.with_watermark(column, previous.watermark.clone());
+    }
+
+    let high_water = Arc::new(Mutex::new(previous.map(|state| state.watermark)));
+    let tracker = high_water.clone();
+    let batches = source
+        .stream(connector, schema_ref.clone(), &options)
+        .map(move |batch| {
+            let batch = batch?;
+            if let Some(seen) = Watermark::max_in(batch.column(column_idx))? {
+                let mut high_water = tracker.lock().map_err(|e| anyhow::anyhow!("{e}"))?;
+                if high_water.as_ref().is_none_or(|current| seen > *current) {
+                    *high_water = Some(seen);
+                }
+            }
+            Ok(batch)
+        })
+        .boxed();
+
+    // Incremental runs always add files under the table directory, partitioned or not.
+    let batches = print_batches_if(args, batches);
+    let rows = write_partitioned_files(args, output, schema_ref, batches).await?;
+
+    let high_water = high_water
+        .lock()
+        .map_err(|e| anyhow::anyhow!("{e}"))?
+        .clone();
+    if let Some(watermark) = high_water {
+        let state = SyncState {
+            table: source.to_string(),
+            column: column.to_string(),
+            watermark,
+            updated_at: chrono::Utc::now(),
+        };
+        state.save(output, &table_dir).await?;
+        info!("Saved high-water mark {:?}", state.watermark);
+    } else {
+        info!("No rows in {source}; nothing to record");
+    }
+
+    Ok(rows)
+}
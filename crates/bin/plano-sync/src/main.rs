@@ -1,102 +1,657 @@
 ///
 /// Synchronize a Postgres table and write to Parquet with optional partitioning
 ///
+use anyhow::{Context, bail};
 use arrow::datatypes::Schema;
 use arrow::record_batch::RecordBatch;
 use arrow::util::pretty::print_batches;
-use clap::{ArgAction, Parser};
-use parquet::arrow::ArrowWriter;
-use parquet::file::properties::WriterProperties;
-use partitions::{validate_partition_keys, write_partitioned_files};
-use rds_sync::{infer_arrow_schema, sync_table};
+use cdc::sync_changes;
+use clap::{ArgAction, Parser, Subcommand};
+use compact::{CompactArgs, compact_tables};
+use expire::{ExpireArgs, expire_tables};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use incremental::sync_incremental;
+use mode::{WriteMode, check_existing, check_mode, remove_superseded};
+use object_store::path::Path as ObjectPath;
+use output::Output;
+use partitions::write_partitioned_files;
+use properties::ParquetOptions;
+use rds_sync::{
+    DEFAULT_BATCH_SIZE, PostgresConnector, Predicate, SourceConnector, SqliteConnector,
+    StreamOptions, TableRef, project_schema,
+};
+use sort::{SortOrder, Sorter};
+use source::Source;
 use sqlx::postgres::PgPoolOptions;
 use std::env;
-use std::fs::{self, File};
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::info;
+use tables::sync_tables;
+use tracing::{info, warn};
 
+mod cdc;
+mod commit;
+mod compact;
+mod config;
+mod expire;
+mod incremental;
+mod mode;
+mod output;
 mod partitions;
+mod properties;
+mod snapshot;
+mod sort;
+mod source;
+mod state;
+mod tables;
+mod time_partition;
 
 /// Command-line arguments for the sync CLI
-#[derive(Parser, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser, Debug, Default, Clone)]
 #[command(name = "plano-sync")]
 #[command(about = "Synchronize a table from Postgres and write Parquet with optional partitioning", long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Name of the table to sync
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Name of the table to sync, optionally schema-qualified (`schema.table`).
+    /// Double-quote mixed-case names, e.g. `'sales."Orders"'`.
+    /// Output files are named after the table, without its schema.
+    /// Repeat to sync several tables from one consistent snapshot of a Postgres database.
+    /// With --query, the name to write the query's result under.
+    #[arg(short = 't', long = "table", value_name = "TABLE", required_unless_present = "schema", action = ArgAction::Append)]
+    tables: Vec<String>,
+
+    /// The table being synced, one of `tables`.
+    #[arg(skip)]
     table: String,
 
+    /// Sync every table in this schema (or attached `SQLite` database, e.g. `main`), as well as
+    /// any --table
+    #[arg(long, conflicts_with_all = ["query", "cdc_slot"])]
+    schema: Option<String>,
+
+    /// Only sync the --schema tables whose names match one of these globs, e.g. `orders_*`
+    /// (can repeat or be comma-separated)
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append, requires = "schema")]
+    include: Vec<String>,
+
+    /// Leave out the --schema tables whose names match one of these globs (can repeat or be
+    /// comma-separated)
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append, requires = "schema")]
+    exclude: Vec<String>,
+
+    /// Also sync the views and materialized views of --schema
+    #[arg(long, requires = "schema")]
+    views: bool,
+
+    /// TOML file of per-table settings that replace the command line's, e.g.
+    /// `[tables.orders]` with `partition_by = ["year", "month"]` and `timestamp_col`
+    #[arg(long, conflicts_with_all = ["query", "cdc_slot"])]
+    config: Option<PathBuf>,
+
+    /// Stop at the first table that fails rather than syncing the others
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Sync the result of this `SELECT` instead of a table, e.g. a join.  Its columns and their
+    /// types are taken from the prepared statement; give computed columns an alias.
+    #[arg(long, conflicts_with = "cdc_slot")]
+    query: Option<String>,
+
     /// Print the `RecordBatch` to stdout
     #[arg(long)]
     print: bool,
 
-    /// Directory in which to write Parquet files (default: /tmp)
+    /// Directory in which to write Parquet files (default: /tmp): a local path, or a `file://`
+    /// or `s3://bucket/prefix` URL.  S3 credentials, region and endpoint (e.g. for `MinIO`) are
+    /// read from the usual `AWS_*` environment variables.
     #[arg(long, short, default_value = "/tmp")]
     output_dir: String,
 
-    /// Partition keys (can repeat).
-    /// If using reserved time keys (year, month, day, hour), must set --timestamp-col.
+    /// What to do with the output of earlier runs (default: `append` with --incremental-col or
+    /// --cdc-slot, otherwise `overwrite`)
+    #[arg(long, value_enum)]
+    mode: Option<WriteMode>,
+
+    /// Partition keys (can repeat): columns, or the time keys year, quarter, month, week (ISO),
+    /// day, hour, minute and date (`YYYY-MM-DD`) of --timestamp-col, which must then be set.
+    /// A column named like a time key is used as it is.  Rows with a null value go to
+    /// `__HIVE_DEFAULT_PARTITION__`.
     #[arg(long, short, action = ArgAction::Append)]
     partition_by: Vec<String>,
 
     /// When partitioning by timestamp components, select which timestamp column to break down.
     #[arg(long)]
     timestamp_col: Option<String>,
+
+    /// Time zone in which --timestamp-col values with a time zone are broken down, e.g.
+    /// `Europe/Amsterdam` or `-05:00` (default: UTC).  Timestamps without a time zone and dates
+    /// are broken down as they are.
+    #[arg(long)]
+    partition_tz: Option<String>,
+
+    /// Split each partition (or the table, if it is not partitioned) into --buckets files by a
+    /// hash of this column, for columns with too many values to partition by.  plano-serv reads
+    /// only the matching file for `=` and `IN` filters on the column.
+    #[arg(long, requires = "buckets")]
+    bucket_by: Option<String>,
+
+    /// Number of files --bucket-by splits each partition into
+    #[arg(long, requires = "bucket_by", value_parser = clap::value_parser!(u32).range(1..))]
+    buckets: Option<u32>,
+
+    /// Sort the rows of each file by these columns (can repeat or be comma-separated), e.g.
+    /// `ts` or `name,ts desc`, so that min/max statistics let readers skip row groups.  The
+    /// order is recorded in the files, where plano-serv finds it.
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append)]
+    sort_by: Vec<String>,
+
+    /// MiB of rows held in memory for --sort-by before they are spilled, sorted, to temporary
+    /// files
+    #[arg(long, default_value_t = 256, value_parser = parse_positive)]
+    sort_memory: usize,
+
+    /// MiB of encoded rows the files being written may hold in memory between them, in the row
+    /// groups they are building, before the largest are written out early as smaller row
+    /// groups
+    #[arg(long, default_value_t = 256, value_parser = parse_positive)]
+    write_memory: usize,
+
+    /// Start a new file once one has this many rows, so that large partitions are split into
+    /// files readers can scan in parallel (default: no limit)
+    #[arg(long, value_parser = parse_positive)]
+    max_file_rows: Option<usize>,
+
+    /// Start a new file once one reaches this many MiB, as estimated while it is written
+    /// (default: no limit)
+    #[arg(long, value_parser = parse_positive)]
+    max_file_size: Option<usize>,
+
+    /// Keep a Delta Lake transaction log in the table's `_delta_log/` directory, recording the
+    /// files each run adds and removes, so that plano-serv and other Delta readers find the
+    /// table's files and schema without listing its directories
+    #[arg(long)]
+    delta_log: bool,
+
+    /// Hours the files a --delta-log commit removes are kept for readers of older versions
+    /// before a later run deletes them
+    #[arg(long, default_value_t = 168)]
+    delta_retention: u64,
+
+    /// Days the table's time partitions are kept by `plano-sync expire`, from its --config.
+    #[arg(skip)]
+    retention_days: Option<u64>,
+
+    /// Maximum number of rows read from Postgres and held in memory at a time
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE, value_parser = parse_positive)]
+    batch_size: usize,
+
+    /// Read rows with a binary `COPY` instead of a query, which is usually much faster for wide
+    /// tables
+    #[arg(long, conflicts_with = "cdc_slot")]
+    copy: bool,
+
+    /// Number of connections reading the table at once, each over one range of --split-col
+    #[arg(long, default_value_t = 1, value_parser = parse_positive)]
+    workers: usize,
+
+    /// Integer, date or timestamp column whose values are divided between the --workers
+    /// (default: the leading primary key column)
+    #[arg(long)]
+    split_col: Option<String>,
+
+    /// Columns to extract, in order (can repeat or be comma-separated; default: all)
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append)]
+    columns: Vec<String>,
+
+    /// Columns to leave out, e.g. wide blobs (can repeat or be comma-separated)
+    #[arg(long, value_delimiter = ',', action = ArgAction::Append)]
+    exclude_columns: Vec<String>,
+
+    /// Only extract rows matching this condition, e.g. `created_at > now() - interval '90 days'`.
+    /// It must be a plain expression over the table's columns: subqueries, parameters and all
+    /// but a few scalar functions are rejected.
+    #[arg(long = "where", conflicts_with = "cdc_slot")]
+    filter: Option<String>,
+
+    /// Only sync rows whose value in this column is greater than on the previous run.
+    /// The column must only ever increase (a serial id, an `updated_at` timestamp, ...).
+    /// New rows are added as new files under the table's directory, next to a
+    /// `_sync_state.json` file that records the high-water mark.
+    #[arg(long, conflicts_with = "cdc_slot")]
+    incremental_col: Option<String>,
+
+    /// Capture inserts, updates and deletes from this logical replication slot instead of
+    /// reading the table.  The slot (and its publication) are created on the first run, which
+    /// captures nothing yet; later runs write the changes made since the previous run to
+    /// `<table>_changes`, with `_op`, `_lsn` and `_commit_ts` columns.
+    #[arg(long)]
+    cdc_slot: Option<String>,
+
+    /// Publication to read changes from (default: the slot name)
+    #[arg(long, requires = "cdc_slot")]
+    cdc_publication: Option<String>,
+
+    #[command(flatten)]
+    parquet: ParquetOptions,
+}
+
+/// Work on tables that have already been synced
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Rewrite the small files of each partition of synced tables into fewer, larger ones, and
+    /// commit them in place of the originals
+    Compact(CompactArgs),
+    /// Delete the time partitions of synced tables that are older than their retention, and
+    /// commit the tables without them
+    Expire(ExpireArgs),
+}
+
+impl Args {
+    /// These arguments applied to one of the tables.
+    fn for_table(&self, table: &str) -> Self {
+        Self {
+            table: table.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// Checks that several tables are only given where they can be synced together.
+    fn check_tables(&self) -> anyhow::Result<()> {
+        if self.tables.len() == 1 {
+            return Ok(());
+        }
+        if self.query.is_some() {
+            bail!("--query writes a single output; name it with one --table");
+        }
+        if self.cdc_slot.is_some() {
+            bail!("--cdc-slot captures the changes of a single table");
+        }
+        Ok(())
+    }
+
+    /// The table name used for output paths: unquoted and without its schema.
+    fn output_name(&self) -> String {
+        let name = TableRef::parse(&self.table)
+            .map_or_else(|_| self.table.clone(), |table| table.name().to_string());
+        if self.cdc_slot.is_some() {
+            format!("{name}_changes")
+        } else {
+            name
+        }
+    }
+
+    /// Whether runs add new files to the output rather than replacing it.
+    const fn appends(&self) -> bool {
+        self.incremental_col.is_some() || self.cdc_slot.is_some()
+    }
+
+    /// The --mode, or the default for the other options.
+    const fn write_mode(&self) -> WriteMode {
+        match self.mode {
+            Some(mode) => mode,
+            None if self.appends() => WriteMode::Append,
+            None => WriteMode::Overwrite,
+        }
+    }
+
+    /// The directory holding a table's partitions and incremental state.
+    fn table_dir(&self) -> ObjectPath {
+        ObjectPath::from(self.output_name())
+    }
+
+    /// The --sort-memory in bytes.
+    const fn sort_memory_bytes(&self) -> usize {
+        self.sort_memory.saturating_mul(1024 * 1024)
+    }
+
+    /// The --write-memory in bytes.
+    const fn write_memory_bytes(&self) -> usize {
+        self.write_memory.saturating_mul(1024 * 1024)
+    }
+
+    /// The --max-file-size in bytes.
+    fn max_file_bytes(&self) -> Option<usize> {
+        self.max_file_size
+            .map(|size| size.saturating_mul(1024 * 1024))
+    }
+
+    /// The file an unpartitioned table is written to when it is replaced on each run.
+    fn table_file(&self) -> ObjectPath {
+        ObjectPath::from(format!("{}.parquet", self.output_name()))
+    }
+}
+
+/// Parses a strictly positive count
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be greater than zero".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
     let args = Args::parse();
-    let pool = initialize_db_pool().await?;
-
-    validate_partition_keys(&args);
+    match &args.command {
+        Some(Command::Compact(compact)) => return compact_tables(compact).await,
+        Some(Command::Expire(expire)) => return expire_tables(expire).await,
+        None => {}
+    }
 
-    let schema_ref = infer_arrow_schema(&args.table, &pool).await?;
-    let batch = sync_table(&args.table, &schema_ref, &pool).await?;
+    args.check_tables()?;
+    let output = Output::open(&args.output_dir)?;
 
-    handle_output(&args, &schema_ref, &batch)?;
+    let db_url = env::var("DATABASE_URL").context("DATABASE_URL environment variable not set")?;
+    // one connection per worker, plus one for metadata queries
+    let max_connections = u32::try_from(args.workers + 1).unwrap_or(u32::MAX);
+    if db_url.starts_with("sqlite:") {
+        if args.cdc_slot.is_some() {
+            bail!("--cdc-slot needs a Postgres database");
+        }
+        let connector = SqliteConnector::connect(&db_url, max_connections).await?;
+        return sync_tables(&args, &output, &connector).await;
+    }
 
-    Ok(())
+    let pool = PgPoolOptions::new()
+        .max_connections(max_connections)
+        .connect(&db_url)
+        .await?;
+    if let Some(slot) = &args.cdc_slot {
+        check_mode(&args)?;
+        return sync_changes(&args.for_table(&args.tables[0]), &output, &pool, slot).await;
+    }
+    sync_tables(&args, &output, &PostgresConnector::new(pool)).await
 }
 
-async fn initialize_db_pool() -> sqlx::Result<sqlx::Pool<sqlx::Postgres>> {
-    let db_url = env::var("DATABASE_URL")
-        .unwrap_or_else(|_| panic!("DATABASE_URL environment variable not set"));
-    PgPoolOptions::new().connect(&db_url).await
+/// Syncs the table or query named by `args` from `connector`, reading from `snapshot` if given,
+/// and returns the number of rows written.
+async fn run(
+    args: &Args,
+    output: &Output,
+    connector: &dyn SourceConnector,
+    snapshot: Option<&str>,
+) -> anyhow::Result<usize> {
+    let (source, table_schema) = if let Some(sql) = &args.query {
+        let source = Source::Query {
+            name: args.output_name(),
+            sql: sql.clone(),
+        };
+        (source, connector.infer_query_schema(sql).await?)
+    } else {
+        let table = connector
+            .resolve_table(&TableRef::parse(&args.table)?)
+            .await?;
+        let table_schema = connector.infer_schema(&table).await?;
+        (Source::Table(table), table_schema)
+    };
+    let schema_ref = project_schema(&table_schema, &args.columns, &args.exclude_columns)?;
+    check_existing(args, output).await?;
+
+    let mut options = StreamOptions::new(args.batch_size);
+    if args.copy {
+        options = options.with_copy();
+    }
+    if let Some(snapshot) = snapshot {
+        options = options.with_snapshot(snapshot);
+    }
+    if let Some(filter) = &args.filter {
+        // validated against every column, so rows can be filtered on columns left out
+        options = options.with_predicate(Predicate::parse(filter, &table_schema)?);
+    }
+    if args.workers > 1 {
+        let split_col = match (&args.split_col, &source) {
+            (Some(column), _) => Some(column.clone()),
+            (None, Source::Table(table)) => {
+                connector.detect_split_column(table, &schema_ref).await?
+            }
+            (None, Source::Query { .. }) => bail!("Set --split-col to read a query on workers"),
+        };
+        match split_col {
+            Some(split_col) => {
+                info!(
+                    "Reading {source} on {} workers split by {split_col}",
+                    args.workers
+                );
+                options = options.with_split(&split_col, args.workers);
+            }
+            // when syncing many tables, those without a key (such as views) are read as a whole
+            None if args.tables.len() > 1 => {
+                warn!(
+                    "{source} has no integer, date or timestamp primary key; reading it on one connection"
+                );
+            }
+            None => {
+                bail!("{source} has no integer, date or timestamp primary key; set --split-col")
+            }
+        }
+    }
+
+    let Some(column) = &args.incremental_col else {
+        let batches = source.stream(connector, schema_ref.clone(), &options);
+        return handle_output(args, output, &schema_ref, batches).await;
+    };
+    sync_incremental(
+        args,
+        output,
+        connector,
+        &source,
+        &schema_ref,
+        options,
+        column,
+    )
+    .await
 }
 
-fn handle_output(args: &Args, schema_ref: &Arc<Schema>, batch: &RecordBatch) -> anyhow::Result<()> {
-    if args.partition_by.is_empty() {
-        write_single_file(args, schema_ref, batch)?;
+async fn handle_output(
+    args: &Args,
+    output: &Output,
+    schema_ref: &Arc<Schema>,
+    batches: BoxStream<'static, anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<usize> {
+    let batches = print_batches_if(args, batches);
+    if args.partition_by.is_empty()
+        && args.bucket_by.is_none()
+        && args.max_file_rows.is_none()
+        && args.max_file_size.is_none()
+        && !args.delta_log
+        && args.write_mode() != WriteMode::Append
+    {
+        write_single_file(args, output, schema_ref, batches).await
     } else {
-        write_partitioned_files(args, schema_ref, batch)?;
-    }
-    if args.print {
-        print_batches(std::slice::from_ref(batch))?;
+        write_partitioned_files(args, output, schema_ref, batches).await
     }
-    Ok(())
 }
 
-#[allow(clippy::expect_used)]
-fn write_single_file(
+/// Echoes each batch to stdout as it passes through when `--print` is set.
+fn print_batches_if(
+    args: &Args,
+    batches: BoxStream<'static, anyhow::Result<RecordBatch>>,
+) -> BoxStream<'static, anyhow::Result<RecordBatch>> {
+    let print = args.print;
+    batches
+        .inspect_ok(move |batch| {
+            if print {
+                let _ = print_batches(std::slice::from_ref(batch));
+            }
+        })
+        .boxed()
+}
+
+async fn write_single_file(
     args: &Args,
+    output: &Output,
     schema_ref: &Arc<Schema>,
-    batch: &RecordBatch,
-) -> anyhow::Result<()> {
-    let output_path = Path::new(&args.output_dir).join(format!("{}.parquet", args.table));
-    fs::create_dir_all(
-        output_path
-            .parent()
-            .expect("Output directory must have a parent"),
-    )?;
-    let file = File::create(&output_path)?;
-    let props = WriterProperties::builder().build();
-    let mut writer =
-        ArrowWriter::try_new(file, Arc::new(schema_ref.as_ref().clone()), Some(props))?;
-    writer.write(batch)?;
-    writer.close()?;
-    info!("Wrote Parquet file to {}", output_path.display());
-    Ok(())
+    mut batches: BoxStream<'static, anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<usize> {
+    let output_path = args.table_file();
+    let order = SortOrder::new(&args.sort_by, schema_ref)?;
+    let mut props = args.parquet.properties(schema_ref)?;
+    if let Some(order) = &order {
+        props = order.record(props)?;
+    }
+    let mut writer = output.parquet_writer(&output_path, schema_ref.clone(), props)?;
+    let mut sorter = order.map(|order| Sorter::new(order, args.batch_size));
+    let mut rows = 0;
+    while let Some(batch) = batches.try_next().await? {
+        rows += batch.num_rows();
+        if let Some(sorter) = &mut sorter {
+            sorter.push(batch);
+            if sorter.buffered_bytes() > args.sort_memory_bytes() {
+                sorter.spill()?;
+            }
+        } else {
+            writer.write(&batch).await?;
+        }
+    }
+    if let Some(sorter) = sorter {
+        for batch in sorter.finish()? {
+            writer.write(&batch?).await?;
+        }
+    }
+    writer.close().await?;
+    info!(
+        "Wrote {rows} rows to Parquet file {}",
+        output.display(&output_path)
+    );
+    remove_superseded(args, output, &[output_path]).await?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tempfile::tempdir;
+
+    /// The files in `dir` of `output`, in order.
+    async fn files(output: &Output, dir: &str) -> Vec<String> {
+        let mut names = output.list(&ObjectPath::from(dir)).await.unwrap();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_run_sqlite() {
+        let dir = tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("edge.db").display());
+        let pool = SqlitePoolOptions::new().connect(&url).await.unwrap();
+        sqlx::query("CREATE TABLE readings (id INTEGER PRIMARY KEY, sensor TEXT, value REAL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO readings VALUES (1, 'a', 1.5), (2, 'b', 2.5), (3, 'a', 3.5)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let output_dir = dir.path().join("out");
+        let args = Args {
+            table: "readings".to_string(),
+            output_dir: output_dir.display().to_string(),
+            partition_by: vec!["sensor".to_string()],
+            batch_size: 2,
+            workers: 2,
+            incremental_col: Some("id".to_string()),
+            ..Default::default()
+        };
+        let connector = SqliteConnector::connect(&url, 3).await.unwrap();
+        let output = Output::open(&args.output_dir).unwrap();
+        run(&args, &output, &connector, None).await.unwrap();
+        let a = files(&output, "readings/sensor=a").await;
+        assert_eq!(a.len(), 1);
+        assert!(a[0].starts_with("part-00000-"));
+        assert_eq!(files(&output, "readings/sensor=b").await.len(), 1);
+        assert!(output_dir.join("readings").join(state::STATE_FILE).exists());
+
+        sqlx::query("INSERT INTO readings VALUES (4, 'a', 4.5)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        run(&args, &output, &connector, None).await.unwrap();
+        let a = files(&output, "readings/sensor=a").await;
+        assert_eq!(a.len(), 2);
+        assert!(a[1].starts_with("part-00001-"));
+        assert_eq!(files(&output, "readings/sensor=b").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_to_object_store() {
+        let dir = tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("edge.db").display());
+        let pool = SqlitePoolOptions::new().connect(&url).await.unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, sensor TEXT);
+             INSERT INTO readings VALUES (1, 'a'), (2, 'b');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let output = Output::new(Arc::new(object_store::memory::InMemory::new()), "lake");
+        let connector = SqliteConnector::connect(&url, 1).await.unwrap();
+        let args = Args {
+            table: "readings".to_string(),
+            partition_by: vec!["sensor".to_string()],
+            batch_size: 10,
+            workers: 1,
+            ..Default::default()
+        };
+        assert_eq!(run(&args, &output, &connector, None).await.unwrap(), 2);
+        let first = files(&output, "readings/sensor=a").await;
+        assert_eq!(first.len(), 1);
+
+        // overwriting replaces the partition's file with one of a new name
+        run(&args, &output, &connector, None).await.unwrap();
+        let second = files(&output, "readings/sensor=a").await;
+        assert_eq!(second.len(), 1);
+        assert_ne!(first, second);
+
+        let unpartitioned = Args {
+            partition_by: Vec::new(),
+            ..args.clone()
+        };
+        run(&unpartitioned, &output, &connector, None)
+            .await
+            .unwrap();
+        let all = output.list_all(&ObjectPath::default()).await.unwrap();
+        assert_eq!(all, [ObjectPath::from("readings.parquet")]);
+
+        let refuse = Args {
+            mode: Some(WriteMode::ErrorIfExists),
+            ..args.clone()
+        };
+        let error = run(&refuse, &output, &connector, None).await.unwrap_err();
+        assert!(error.to_string().contains("already has output"), "{error}");
+
+        let append = Args {
+            mode: Some(WriteMode::Append),
+            ..unpartitioned
+        };
+        run(&append, &output, &connector, None).await.unwrap();
+        run(&append, &output, &connector, None).await.unwrap();
+        // the overwritten file is copied into the directory the appended ones go to, then removed
+        assert!(!output.exists(&args.table_file()).await.unwrap());
+        let names = files(&output, "readings").await;
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[0], plano_core::commit::COMMIT_MARKER);
+        assert_eq!(
+            names[1],
+            "part-00000-00000000000000000000000000000000.parquet"
+        );
+        let manifest = output
+            .read(&args.table_dir().join(plano_core::commit::COMMIT_MARKER))
+            .await
+            .unwrap()
+            .unwrap();
+        let manifest: plano_core::commit::Manifest = serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(manifest.files, names[1..]);
+    }
 }
@@ -0,0 +1,270 @@
+///
+/// What a sync does with the output of earlier runs
+///
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use object_store::path::Path;
+use std::collections::HashSet;
+use tracing::info;
+
+use crate::Args;
+use crate::commit::STAGING_DIR;
+use crate::output::Output;
+
+/// How a run's files are combined with those already in the output directory.  Files from
+/// earlier runs are only removed once the new ones have all been written, so a failed run
+/// leaves the previous output in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WriteMode {
+    /// Replace everything previously written for the table
+    Overwrite,
+    /// Add new files next to the existing ones
+    Append,
+    /// Replace the partitions this run writes to and keep the others
+    OverwritePartitions,
+    /// Refuse to write a table that already has output
+    ErrorIfExists,
+}
+
+/// Checks that `args.mode` can be used with the other options.
+///
+/// # Errors
+///
+/// Will return `Err` if a mode other than `append` is combined with an option that only ever
+/// adds rows.
+pub fn check_mode(args: &Args) -> Result<()> {
+    if let Some(mode) = args.mode
+        && mode != WriteMode::Append
+        && args.appends()
+    {
+        bail!(
+            "--incremental-col and --cdc-slot add new files on every run; they need --mode append, not {}",
+            mode.name()
+        );
+    }
+    Ok(())
+}
+
+/// Fails for `error-if-exists` if there is anything at the table's output paths.
+///
+/// # Errors
+///
+/// Will return `Err` if the table has output, or the output directory cannot be listed.
+pub async fn check_existing(args: &Args, output: &Output) -> Result<()> {
+    if args.write_mode() != WriteMode::ErrorIfExists {
+        return Ok(());
+    }
+    let file = args.table_file();
+    if output.exists(&file).await? || !output.list_all(&args.table_dir()).await?.is_empty() {
+        bail!(
+            "{} already has output in {}; choose another --mode to replace or add to it",
+            args.table,
+            output.display(&args.table_dir())
+        );
+    }
+    Ok(())
+}
+
+/// Deletes the files `written` supersedes: every other file of the table for `overwrite`, and
+/// the other files in the directories written to for `overwrite-partitions`.
+///
+/// # Errors
+///
+/// Will return `Err` if the output cannot be listed or a file cannot be deleted.
+pub async fn remove_superseded(args: &Args, output: &Output, written: &[Path]) -> Result<()> {
+    let stale = superseded(args, output, written).await?;
+    remove_files(output, &stale).await
+}
+
+/// The files of earlier runs that `written` supersedes, some of which may not exist.
+///
+/// # Errors
+///
+/// Will return `Err` if the output cannot be listed.
+pub async fn superseded(args: &Args, output: &Output, written: &[Path]) -> Result<Vec<Path>> {
+    let stale = match args.write_mode() {
+        WriteMode::Append | WriteMode::ErrorIfExists => return Ok(Vec::new()),
+        WriteMode::OverwritePartitions if !args.partition_by.is_empty() => {
+            let dirs: HashSet<Path> = written.iter().filter_map(parent).collect();
+            let mut stale = Vec::new();
+            for dir in dirs {
+                stale.extend(
+                    output
+                        .list(&dir)
+                        .await?
+                        .into_iter()
+                        .map(|name| dir.clone().join(name)),
+                );
+            }
+            stale
+        }
+        // without partitions, the table is a single partition; the files other runs are still
+        // staging are not part of it
+        WriteMode::Overwrite | WriteMode::OverwritePartitions => {
+            let staging_dir = args.table_dir().join(STAGING_DIR);
+            let mut stale: Vec<Path> = output
+                .list_all(&args.table_dir())
+                .await?
+                .into_iter()
+                .filter(|path| path.prefix_match(&staging_dir).is_none())
+                .collect();
+            stale.push(args.table_file());
+            stale
+        }
+    };
+    Ok(stale
+        .into_iter()
+        .filter(|path| !written.contains(path))
+        .collect())
+}
+
+/// Deletes those of `paths` that exist.
+///
+/// # Errors
+///
+/// Will return `Err` if a file cannot be deleted.
+pub async fn remove_files(output: &Output, paths: &[Path]) -> Result<()> {
+    let mut removed = 0;
+    for path in paths {
+        if output.exists(path).await? {
+            output.delete(path).await?;
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("Removed {removed} files replaced by this run");
+    }
+    Ok(())
+}
+
+/// The directory `path` is in.
+fn parent(path: &Path) -> Option<Path> {
+    let parts: Vec<_> = path.parts().collect();
+    let (_, dir) = parts.split_last()?;
+    Some(dir.iter().cloned().collect())
+}
+
+impl WriteMode {
+    /// The mode as it is given on the command line.
+    fn name(self) -> String {
+        self.to_possible_value()
+            .map_or_else(|| format!("{self:?}"), |value| value.get_name().to_string())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    async fn output_with(paths: &[&str]) -> Output {
+        let output = Output::new(Arc::new(InMemory::new()), "lake");
+        for path in paths {
+            output.write(&Path::from(*path), Vec::new()).await.unwrap();
+        }
+        output
+    }
+
+    async fn files(output: &Output) -> Vec<String> {
+        let mut paths: Vec<String> = output
+            .list_all(&Path::default())
+            .await
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    const EARLIER: [&str; 5] = [
+        "events.parquet",
+        "events/day=01/part-00000-a.parquet",
+        "events/day=02/part-00000-a.parquet",
+        "events/day=02/part-00001-b.parquet",
+        "events_changes/part-00000-a.parquet",
+    ];
+
+    fn args(mode: WriteMode) -> Args {
+        Args {
+            table: "events".to_string(),
+            partition_by: vec!["day".to_string()],
+            mode: Some(mode),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_superseded() {
+        let written = [Path::from("events/day=02/part-00000-c.parquet")];
+
+        let output = output_with(&EARLIER).await;
+        output.write(&written[0], Vec::new()).await.unwrap();
+        // staged by another run that is still writing
+        let staged = Path::from("events/_temporary/run/day=03/part-00000-d.parquet");
+        output.write(&staged, Vec::new()).await.unwrap();
+        remove_superseded(&args(WriteMode::Overwrite), &output, &written)
+            .await
+            .unwrap();
+        assert_eq!(
+            files(&output).await,
+            [
+                "events/_temporary/run/day=03/part-00000-d.parquet",
+                "events/day=02/part-00000-c.parquet",
+                "events_changes/part-00000-a.parquet"
+            ]
+        );
+
+        let output = output_with(&EARLIER).await;
+        output.write(&written[0], Vec::new()).await.unwrap();
+        remove_superseded(&args(WriteMode::OverwritePartitions), &output, &written)
+            .await
+            .unwrap();
+        assert_eq!(
+            files(&output).await,
+            [
+                "events.parquet",
+                "events/day=01/part-00000-a.parquet",
+                "events/day=02/part-00000-c.parquet",
+                "events_changes/part-00000-a.parquet"
+            ]
+        );
+
+        let output = output_with(&EARLIER).await;
+        remove_superseded(&args(WriteMode::Append), &output, &written)
+            .await
+            .unwrap();
+        assert_eq!(files(&output).await.len(), EARLIER.len());
+    }
+
+    #[tokio::test]
+    async fn test_check_existing() {
+        let args = args(WriteMode::ErrorIfExists);
+        assert!(check_existing(&args, &output_with(&[]).await).await.is_ok());
+        let output = output_with(&["events_changes/part-00000-a.parquet"]).await;
+        assert!(check_existing(&args, &output).await.is_ok());
+        assert!(
+            check_existing(&args, &output_with(&["events.parquet"]).await)
+                .await
+                .is_err()
+        );
+        let output = output_with(&["events/day=01/part-00000-a.parquet"]).await;
+        assert!(check_existing(&args, &output).await.is_err());
+    }
+
+    #[test]
+    fn test_check_mode() {
+        let incremental = |mode| Args {
+            incremental_col: Some("id".to_string()),
+            mode,
+            ..Default::default()
+        };
+        assert!(check_mode(&incremental(None)).is_ok());
+        assert!(check_mode(&incremental(Some(WriteMode::Append))).is_ok());
+        let error = check_mode(&incremental(Some(WriteMode::OverwritePartitions))).unwrap_err();
+        assert!(error.to_string().ends_with("not overwrite-partitions"));
+        assert!(check_mode(&args(WriteMode::Overwrite)).is_ok());
+    }
+}
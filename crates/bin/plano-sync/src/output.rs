@@ -0,0 +1,402 @@
+///
+/// Where synced files are written: a local directory or an object store
+///
+use anyhow::{Context, Result};
+use arrow::datatypes::SchemaRef;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore, ObjectStoreExt, PutMode, PutPayload, parse_url_opts};
+use parquet::arrow::async_reader::{ParquetObjectReader, ParquetRecordBatchStream};
+use parquet::arrow::async_writer::ParquetObjectWriter;
+use parquet::arrow::{AsyncArrowWriter, ParquetRecordBatchStreamBuilder};
+use parquet::file::properties::WriterProperties;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use url::Url;
+
+/// The `--output-dir`, opened as an object store.  Paths passed to its methods are relative to
+/// the output directory.
+#[derive(Debug, Clone)]
+pub struct Output {
+    store: Arc<dyn ObjectStore>,
+    /// Where the output directory is in `store`.
+    root: Path,
+    /// The output directory as given, for messages.
+    location: String,
+    /// The output directory, when it is on the local file system, where files are renamed
+    /// atomically.
+    local_dir: Option<PathBuf>,
+}
+
+impl Output {
+    /// Opens `output_dir`, which is a local directory or a URL such as `s3://bucket/prefix` or
+    /// `file:///var/lib/plano`.  Object store settings, such as `AWS_ENDPOINT` for an
+    /// S3-compatible store, are read from the environment.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL's scheme is not supported or a local directory cannot be
+    /// created.
+    pub fn open(output_dir: &str) -> Result<Self> {
+        let location = output_dir.trim_end_matches('/').to_string();
+        // a single letter is a Windows drive rather than a scheme
+        if let Ok(url) = Url::parse(output_dir)
+            && url.scheme().len() > 1
+        {
+            let (store, root) = parse_url_opts(&url, std::env::vars())
+                .with_context(|| format!("Cannot write to {output_dir}"))?;
+            return Ok(Self {
+                store: Arc::from(store),
+                root,
+                location,
+                local_dir: url.to_file_path().ok(),
+            });
+        }
+        fs::create_dir_all(output_dir)
+            .with_context(|| format!("Cannot create directory {output_dir}"))?;
+        Ok(Self {
+            // remove directories left empty when superseded files are deleted
+            store: Arc::new(
+                LocalFileSystem::new_with_prefix(output_dir)?.with_automatic_cleanup(true),
+            ),
+            root: Path::default(),
+            location,
+            local_dir: Some(PathBuf::from(output_dir)),
+        })
+    }
+
+    /// Writes into `root` of `store`, e.g. an in-memory store in tests.
+    #[cfg(test)]
+    pub fn new(store: Arc<dyn ObjectStore>, root: &str) -> Self {
+        Self {
+            store,
+            root: Path::from(root),
+            location: format!("/{root}"),
+            local_dir: None,
+        }
+    }
+
+    /// Whether the output is on the local file system, where a file can be moved into place
+    /// atomically rather than copied.
+    pub const fn is_local(&self) -> bool {
+        self.local_dir.is_some()
+    }
+
+    /// Where `path` is, for messages.
+    pub fn display(&self, path: &Path) -> String {
+        format!("{}/{path}", self.location)
+    }
+
+    /// A Parquet writer for `path`, which uploads the file in parts as it grows.  Nothing is
+    /// visible at `path` until the writer is closed.
+    pub fn parquet_writer(
+        &self,
+        path: &Path,
+        schema: SchemaRef,
+        props: WriterProperties,
+    ) -> Result<AsyncArrowWriter<ParquetObjectWriter>> {
+        let writer = ParquetObjectWriter::new(self.store.clone(), self.absolute(path));
+        Ok(AsyncArrowWriter::try_new(writer, schema, Some(props))?)
+    }
+
+    /// The rows of the Parquet file at `path`, `batch_size` at a time.
+    pub async fn parquet_reader(
+        &self,
+        path: &Path,
+        batch_size: usize,
+    ) -> Result<ParquetRecordBatchStream<ParquetObjectReader>> {
+        let reader = ParquetObjectReader::new(self.store.clone(), self.absolute(path));
+        let stream = ParquetRecordBatchStreamBuilder::new(reader)
+            .await
+            .with_context(|| format!("reading {}", self.display(path)))?
+            .with_batch_size(batch_size)
+            .build()?;
+        Ok(stream)
+    }
+
+    /// The contents of `path`, or `None` if there is nothing there.
+    pub async fn read(&self, path: &Path) -> Result<Option<Bytes>> {
+        match self.store.get(&self.absolute(path)).await {
+            Ok(result) => Ok(Some(result.bytes().await?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.display(path))),
+        }
+    }
+
+    /// Replaces `path` with `contents`; readers see either the old or the new contents.
+    pub async fn write(&self, path: &Path, contents: Vec<u8>) -> Result<()> {
+        self.store
+            .put(&self.absolute(path), PutPayload::from(contents))
+            .await
+            .with_context(|| format!("writing {}", self.display(path)))?;
+        Ok(())
+    }
+
+    /// Writes `contents` to `path` unless there is already a file there, returning whether it
+    /// did, so that of two runs writing the same file only one succeeds.
+    pub async fn create(&self, path: &Path, contents: Vec<u8>) -> Result<bool> {
+        match self
+            .store
+            .put_opts(
+                &self.absolute(path),
+                PutPayload::from(contents),
+                PutMode::Create.into(),
+            )
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("writing {}", self.display(path))),
+        }
+    }
+
+    /// The size and modification time of the file at `path`.
+    pub async fn meta(&self, path: &Path) -> Result<ObjectMeta> {
+        self.store
+            .head(&self.absolute(path))
+            .await
+            .with_context(|| format!("reading {}", self.display(path)))
+    }
+
+    /// The names of the files directly in `dir`.
+    pub async fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let listing = self
+            .store
+            .list_with_delimiter(Some(&self.absolute(dir)))
+            .await?;
+        Ok(listing
+            .objects
+            .iter()
+            .filter_map(|object| object.location.filename().map(ToString::to_string))
+            .collect())
+    }
+
+    /// Every file under `dir`, however deeply nested.
+    pub async fn list_all(&self, dir: &Path) -> Result<Vec<Path>> {
+        let files = self.list_sizes(dir).await?;
+        Ok(files.into_iter().map(|(path, _)| path).collect())
+    }
+
+    /// Every file under `dir`, however deeply nested, with its size.
+    pub async fn list_sizes(&self, dir: &Path) -> Result<Vec<(Path, u64)>> {
+        let objects = self.list_objects(dir).await?;
+        Ok(objects
+            .into_iter()
+            .map(|(path, object)| (path, object.size))
+            .collect())
+    }
+
+    /// Every file under `dir`, however deeply nested, with when it was last modified.
+    pub async fn list_modified(&self, dir: &Path) -> Result<Vec<(Path, DateTime<Utc>)>> {
+        let objects = self.list_objects(dir).await?;
+        Ok(objects
+            .into_iter()
+            .map(|(path, object)| (path, object.last_modified))
+            .collect())
+    }
+
+    async fn list_objects(&self, dir: &Path) -> Result<Vec<(Path, ObjectMeta)>> {
+        let objects: Vec<ObjectMeta> = self
+            .store
+            .list(Some(&self.absolute(dir)))
+            .try_collect()
+            .await
+            .with_context(|| format!("listing {}", self.display(dir)))?;
+        Ok(objects
+            .into_iter()
+            .filter_map(|object| {
+                let parts = object.location.prefix_match(&self.root)?;
+                Some((parts.collect(), object))
+            })
+            .collect())
+    }
+
+    /// Whether there is a file at `path`.
+    pub async fn exists(&self, path: &Path) -> Result<bool> {
+        match self.store.head(&self.absolute(path)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("reading {}", self.display(path))),
+        }
+    }
+
+    /// Removes the file at `path`.
+    pub async fn delete(&self, path: &Path) -> Result<()> {
+        self.store
+            .delete(&self.absolute(path))
+            .await
+            .with_context(|| format!("deleting {}", self.display(path)))?;
+        Ok(())
+    }
+
+    /// Copies the file at `from` to `to`, replacing any file there.
+    pub async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.store
+            .copy(&self.absolute(from), &self.absolute(to))
+            .await
+            .with_context(|| format!("copying {}", self.display(from)))
+    }
+
+    /// Moves the file at `from` to `to`, replacing any file there.  On the local file system,
+    /// the directories this leaves empty are removed, as they are when files are deleted.
+    pub async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.store
+            .rename(&self.absolute(from), &self.absolute(to))
+            .await
+            .with_context(|| format!("moving {} into place", self.display(from)))?;
+        if let Some(local_dir) = &self.local_dir {
+            let parts: Vec<_> = from.parts().collect();
+            for depth in (1..parts.len()).rev() {
+                let dir = parts[..depth]
+                    .iter()
+                    .fold(local_dir.clone(), |dir, part| dir.join(part.as_ref()));
+                if fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn absolute(&self, path: &Path) -> Path {
+        self.root.parts().chain(path.parts()).collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, RecordBatch};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use object_store::memory::InMemory;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let store = Arc::new(InMemory::new());
+        let output = Output::new(store.clone(), "exports/plano");
+        let path = Path::from("events/part-00000.parquet");
+        assert_eq!(
+            output.display(&path),
+            "/exports/plano/events/part-00000.parquet"
+        );
+
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(vec![1, 2]))])
+                .unwrap();
+        let mut writer = output
+            .parquet_writer(&path, schema, WriterProperties::default())
+            .unwrap();
+        writer.write(&batch).await.unwrap();
+        assert!(output.read(&path).await.unwrap().is_none());
+        writer.close().await.unwrap();
+
+        let written = store
+            .get(&Path::from("exports/plano/events/part-00000.parquet"))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(written.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 2);
+        let batches: Vec<RecordBatch> = output
+            .parquet_reader(&path, 1)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+
+        output
+            .write(&Path::from("events/_state.json"), b"{}".to_vec())
+            .await
+            .unwrap();
+        let mut names = output.list(&Path::from("events")).await.unwrap();
+        names.sort();
+        assert_eq!(names, ["_state.json", "part-00000.parquet"]);
+        assert!(output.list(&Path::from("nope")).await.unwrap().is_empty());
+
+        let mut paths = output.list_all(&Path::default()).await.unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                Path::from("events/_state.json"),
+                Path::from("events/part-00000.parquet")
+            ]
+        );
+        assert!(output.exists(&path).await.unwrap());
+        assert_eq!(output.meta(&path).await.unwrap().size, written.len() as u64);
+        let sizes = output.list_sizes(&Path::from("events")).await.unwrap();
+        assert!(sizes.contains(&(path.clone(), written.len() as u64)));
+        let log = Path::from("events/_delta_log/00000000000000000000.json");
+        assert!(output.create(&log, b"1".to_vec()).await.unwrap());
+        assert!(!output.create(&log, b"2".to_vec()).await.unwrap());
+        assert_eq!(output.read(&log).await.unwrap().unwrap().as_ref(), b"1");
+        output.delete(&path).await.unwrap();
+        assert!(!output.exists(&path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_open() {
+        let dir = tempdir().unwrap();
+        let local = dir.path().join("out");
+        let output = Output::open(local.to_str().unwrap()).unwrap();
+        output
+            .write(&Path::from("t/a.json"), b"1".to_vec())
+            .await
+            .unwrap();
+        assert!(local.join("t/a.json").exists());
+
+        let url = format!("file://{}/", dir.path().join("url").display());
+        let output = Output::open(&url).unwrap();
+        output
+            .write(&Path::from("t/b.json"), b"2".to_vec())
+            .await
+            .unwrap();
+        assert!(dir.path().join("url/t/b.json").exists());
+        assert_eq!(
+            output.read(&Path::from("t/b.json")).await.unwrap().unwrap(),
+            Bytes::from_static(b"2")
+        );
+
+        assert!(output.is_local());
+        output
+            .rename(
+                &Path::from("t/_temporary/run/b.json"),
+                &Path::from("t/c.json"),
+            )
+            .await
+            .unwrap_err();
+        output
+            .write(&Path::from("t/_temporary/run/d.json"), b"3".to_vec())
+            .await
+            .unwrap();
+        output
+            .rename(
+                &Path::from("t/_temporary/run/d.json"),
+                &Path::from("t/d.json"),
+            )
+            .await
+            .unwrap();
+        assert!(dir.path().join("url/t/d.json").exists());
+        // the emptied staging directories go with it
+        assert!(!dir.path().join("url/t/_temporary").exists());
+
+        assert!(!Output::open("s3://bucket/prefix").unwrap().is_local());
+        assert!(Output::open("ftp://host/dir").is_err());
+    }
+}
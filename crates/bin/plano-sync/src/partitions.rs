@@ -1,56 +1,407 @@
 ///
 /// Synchronize a Postgres table and write to Parquet with optional partitioning
 ///
-use parquet::arrow::ArrowWriter;
+use anyhow::{Context, bail};
+use object_store::path::Path;
+use parquet::arrow::AsyncArrowWriter;
+use parquet::arrow::async_writer::ParquetObjectWriter;
 use parquet::file::properties::WriterProperties;
-use std::fs::{self, File};
-use std::path::Path;
 use std::sync::Arc;
 use tracing::info;
 
 // Arrow imports for partition logic
-use arrow::array::{ArrayRef, StringArray, TimestampMicrosecondArray, UInt32Array};
+use arrow::array::timezone::Tz;
+use arrow::array::{Array, ArrayRef, UInt32Array};
 use arrow::compute::take;
-use arrow::datatypes::Schema;
+use arrow::datatypes::{DataType, Schema};
 use arrow::record_batch::RecordBatch;
-use chrono::prelude::*;
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use chrono::NaiveDateTime;
+use futures::TryStreamExt;
+use futures::stream::BoxStream;
+use plano_core::bucket::{bucket_of, bucket_suffix};
+use plano_core::partition::encode_value;
 use std::collections::HashMap;
+use uuid::Uuid;
 
 use crate::Args;
+use crate::commit::Commit;
+use crate::mode::WriteMode;
+use crate::output::Output;
+use crate::sort::{SortOrder, Sorter};
+use crate::time_partition::{TimeKey, check_time_column, local_times, parse_tz};
 
-// TODO it should only look for reserved words when a timestamp_col is not set
-pub fn validate_partition_keys(args: &Args) {
-    let reserved = ["year", "month", "day", "hour"];
-    if args.timestamp_col.is_none() {
-        for key in &args.partition_by {
-            if reserved.contains(&key.as_str()) {
-                eprintln!("error: reserved partition key `{key}`, but --timestamp-col is not set");
-                std::process::exit(1);
+pub async fn write_partitioned_files(
+    args: &Args,
+    output: &Output,
+    schema_ref: &Arc<Schema>,
+    mut batches: BoxStream<'static, anyhow::Result<RecordBatch>>,
+) -> anyhow::Result<usize> {
+    check_partition_columns(args, schema_ref)?;
+    let schema_clone: Schema = schema_ref.as_ref().clone();
+    let mut props = args.parquet.properties(schema_ref)?;
+    let order = SortOrder::new(&args.sort_by, schema_ref)?;
+    if let Some(order) = &order {
+        props = order.record(props)?;
+    }
+    let commit = Commit::new(args, output);
+    let mut writers = PartitionWriters::new(commit.clone(), props)
+        .with_limits(args.max_file_rows, args.max_file_bytes())
+        .with_memory(args.write_memory_bytes());
+    if let Some(order) = order {
+        writers = writers.with_sort(order, args.batch_size, args.sort_memory_bytes());
+    }
+
+    let mut rows = 0;
+    while let Some(batch) = batches.try_next().await? {
+        rows += batch.num_rows();
+        partition_and_write(&batch, &schema_clone, args, output, &mut writers).await?;
+        writers.limit_memory().await?;
+    }
+
+    let mut written = close_partitions(output, args, &schema_clone, writers).await?;
+    let Some(copy) = copy_table_file(args, output, &commit).await? else {
+        commit.commit(args, output, &schema_clone, &written).await?;
+        return Ok(rows);
+    };
+    // an appending run supersedes nothing but the file it copied
+    written.push(copy);
+    let stale = vec![args.table_file()];
+    commit
+        .replace(args, output, &schema_clone, &written, stale)
+        .await?;
+    Ok(rows)
+}
+
+/// Copies the file an earlier run wrote for the whole table, `<table>.parquet`, into the
+/// table's directory when this run appends unpartitioned files there, so that the table only
+/// ever has one of the two layouts.  The copy is named as the first file in the directory,
+/// before those of the runs that appended to it, and the original is only removed once the
+/// commit lists the copy.
+async fn copy_table_file(
+    args: &Args,
+    output: &Output,
+    commit: &Commit,
+) -> anyhow::Result<Option<Path>> {
+    let file = args.table_file();
+    if args.write_mode() != WriteMode::Append
+        || !args.partition_by.is_empty()
+        || !output.exists(&file).await?
+    {
+        return Ok(None);
+    }
+    let path = args.table_dir().join(part_file_name(0, Uuid::nil(), None));
+    output.copy(&file, &commit.staging_path(&path)).await?;
+    Ok(Some(path))
+}
+
+/// A partition path, such as `region=eu/year=2026`, and the --bucket-by bucket within it.
+pub type Group = (String, Option<u32>);
+
+/// Open writers keyed by partition path and bucket.  A partition's rows can arrive spread over
+/// many batches, so each writer stays open until the whole stream has been consumed, or until
+/// its file reaches the --max-file-rows or --max-file-size and the rows that follow go to a
+/// new one.  The row groups the writers are building are held in memory, so past the
+/// --write-memory the largest are written out early.
+pub struct PartitionWriters {
+    /// The run, whose id is in the names of its files so that they never replace another run's.
+    commit: Commit,
+    open: HashMap<Group, OpenFile>,
+    /// The sequence number of this run's first file in each directory, shared by its buckets.
+    sequences: HashMap<Path, u32>,
+    /// The number of files closed so far for each group, which its next file is numbered after.
+    rolled: HashMap<Group, u32>,
+    written: Vec<Path>,
+    props: WriterProperties,
+    limits: FileLimits,
+    /// Bytes the row groups in progress may hold between them before the largest are flushed.
+    memory: Option<usize>,
+    sort: Option<PartitionSort>,
+}
+
+/// A file being written for one group.
+struct OpenFile {
+    path: Path,
+    writer: AsyncArrowWriter<ParquetObjectWriter>,
+    rows: usize,
+}
+
+/// The size at which a file is closed and the next one started.
+#[derive(Debug, Default, Clone, Copy)]
+struct FileLimits {
+    rows: Option<usize>,
+    bytes: Option<usize>,
+}
+
+impl FileLimits {
+    /// Whether `file` is full.  Its size is estimated from the bytes written so far and the
+    /// encoded size of the row group still in memory.
+    fn reached(self, file: &OpenFile) -> bool {
+        self.rows.is_some_and(|rows| file.rows >= rows)
+            || self.bytes.is_some_and(|bytes| {
+                file.writer.bytes_written() + file.writer.in_progress_size() >= bytes
+            })
+    }
+}
+
+/// The rows of each file being sorted for --sort-by, which are only written once the stream
+/// has been consumed.
+struct PartitionSort {
+    order: SortOrder,
+    batch_size: usize,
+    /// Bytes the sorters may hold between them before they spill.
+    memory: usize,
+    buffered_bytes: usize,
+    sorters: HashMap<Group, Sorter>,
+}
+
+impl PartitionWriters {
+    pub fn new(commit: Commit, props: WriterProperties) -> Self {
+        Self {
+            commit,
+            open: HashMap::new(),
+            sequences: HashMap::new(),
+            rolled: HashMap::new(),
+            written: Vec::new(),
+            props,
+            limits: FileLimits::default(),
+            memory: None,
+            sort: None,
+        }
+    }
+
+    /// Flushes the largest row groups in progress once they hold more than `bytes` between them.
+    pub const fn with_memory(mut self, bytes: usize) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Starts a new file once one has `rows` rows or is `bytes` bytes long.
+    pub const fn with_limits(mut self, rows: Option<usize>, bytes: Option<usize>) -> Self {
+        self.limits = FileLimits { rows, bytes };
+        self
+    }
+
+    /// Sorts each file's rows by `order`, holding up to `memory` bytes of them in memory.
+    pub fn with_sort(mut self, order: SortOrder, batch_size: usize, memory: usize) -> Self {
+        self.sort = Some(PartitionSort {
+            order,
+            batch_size,
+            memory,
+            buffered_bytes: 0,
+            sorters: HashMap::new(),
+        });
+        self
+    }
+
+    /// Adds `batch` to the rows of `group`: to those being sorted with --sort-by, which are
+    /// written when the writers are closed, or else straight to its files.
+    pub async fn add(
+        &mut self,
+        output: &Output,
+        args: &Args,
+        schema: &Schema,
+        group: &Group,
+        batch: RecordBatch,
+    ) -> anyhow::Result<()> {
+        if let Some(sort) = &mut self.sort {
+            sort.push(group, batch)
+        } else {
+            self.write(output, args, schema, group, batch).await
+        }
+    }
+
+    /// Writes out the rest of the rows of `group`, sorting them if need be, and closes its
+    /// file, once no more rows of it will be added.
+    pub async fn close_group(
+        &mut self,
+        output: &Output,
+        args: &Args,
+        schema: &Schema,
+        group: &Group,
+    ) -> anyhow::Result<()> {
+        let sorter = self
+            .sort
+            .as_mut()
+            .and_then(|sort| sort.sorters.remove(group));
+        if let Some(sorter) = sorter {
+            for batch in sorter.finish()? {
+                self.write(output, args, schema, group, batch?).await?;
+            }
+        }
+        if let Some(file) = self.open.remove(group) {
+            self.close_file(output, group, file).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the row groups in progress that hold the most memory, largest first, once
+    /// they hold more than the --write-memory between them, until they hold at most half of it.
+    pub async fn limit_memory(&mut self) -> anyhow::Result<()> {
+        let Some(memory) = self.memory else {
+            return Ok(());
+        };
+        let mut held: usize = self
+            .open
+            .values()
+            .map(|file| file.writer.memory_size())
+            .sum();
+        if held <= memory {
+            return Ok(());
+        }
+        let mut files: Vec<&mut OpenFile> = self.open.values_mut().collect();
+        files.sort_by_key(|file| std::cmp::Reverse(file.writer.memory_size()));
+        for file in files {
+            if held <= memory / 2 {
+                break;
+            }
+            held = held.saturating_sub(file.writer.memory_size());
+            file.writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes `batch` to the files of `group`, opening them as needed and closing each one as
+    /// soon as it is full, so that no empty file is left behind.
+    async fn write(
+        &mut self,
+        output: &Output,
+        args: &Args,
+        schema: &Schema,
+        group: &Group,
+        mut batch: RecordBatch,
+    ) -> anyhow::Result<()> {
+        while batch.num_rows() > 0 {
+            let mut file = match self.open.remove(group) {
+                Some(file) => file,
+                None => self.open_file(output, args, schema, group).await?,
+            };
+            let rows = match self.limits.rows {
+                Some(rows) => batch.num_rows().min(rows.saturating_sub(file.rows)),
+                None => batch.num_rows(),
+            };
+            file.writer.write(&batch.slice(0, rows)).await?;
+            file.rows += rows;
+            batch = batch.slice(rows, batch.num_rows() - rows);
+            if self.limits.reached(&file) {
+                self.close_file(output, group, file).await?;
+            } else {
+                self.open.insert(group.clone(), file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the next file of `group`.
+    async fn open_file(
+        &mut self,
+        output: &Output,
+        args: &Args,
+        schema: &Schema,
+        group: &Group,
+    ) -> anyhow::Result<OpenFile> {
+        let (partition, bucket) = group;
+        let dir: Path = args
+            .table_dir()
+            .parts()
+            .chain(Path::parse(partition)?.parts())
+            .collect();
+        // files from earlier runs are numbered first when they are kept
+        let sequence = match self.sequences.get(&dir) {
+            Some(sequence) => *sequence,
+            None if args.write_mode() == WriteMode::Append => next_sequence(output, &dir).await?,
+            None => 0,
+        };
+        self.sequences.insert(dir.clone(), sequence);
+        let sequence = sequence + self.rolled.get(group).copied().unwrap_or_default();
+        let path = dir.join(part_file_name(sequence, self.commit.run_id(), *bucket));
+        let writer = output.parquet_writer(
+            &self.commit.staging_path(&path),
+            Arc::new(schema.clone()),
+            self.props.clone(),
+        )?;
+        Ok(OpenFile {
+            path,
+            writer,
+            rows: 0,
+        })
+    }
+
+    async fn close_file(
+        &mut self,
+        output: &Output,
+        group: &Group,
+        file: OpenFile,
+    ) -> anyhow::Result<()> {
+        file.writer.close().await?;
+        info!(
+            "Wrote {} rows to partitioned file {}",
+            file.rows,
+            output.display(&file.path)
+        );
+        self.written.push(file.path);
+        *self.rolled.entry(group.clone()).or_default() += 1;
+        Ok(())
+    }
+}
+
+impl PartitionSort {
+    /// Adds `batch` to the rows of `group`, spilling every sorter when they hold too much.
+    fn push(&mut self, group: &Group, batch: RecordBatch) -> anyhow::Result<()> {
+        let sorter = self
+            .sorters
+            .entry(group.clone())
+            .or_insert_with(|| Sorter::new(self.order.clone(), self.batch_size));
+        self.buffered_bytes += sorter.push(batch);
+        if self.buffered_bytes > self.memory {
+            for sorter in self.sorters.values_mut() {
+                sorter.spill()?;
             }
+            self.buffered_bytes = 0;
         }
+        Ok(())
     }
 }
 
-pub fn write_partitioned_files(
-    args: &Args,
-    schema_ref: &Arc<Schema>,
+async fn partition_and_write(
     batch: &RecordBatch,
+    schema: &Schema,
+    args: &Args,
+    output: &Output,
+    writers: &mut PartitionWriters,
 ) -> anyhow::Result<()> {
-    let schema_clone: Schema = schema_ref.as_ref().clone();
-    partition_and_write(batch, &schema_clone, args)
-}
-
-fn partition_and_write(batch: &RecordBatch, schema: &Schema, args: &Args) -> anyhow::Result<()> {
     let idx_map = build_column_index_map(schema);
     let groups = group_rows_by_partition(batch, args, &idx_map)?;
 
     for (grp, indices) in groups {
-        write_partition(grp, indices, batch, schema, args)?;
+        write_partition(writers, grp, indices, batch, schema, args, output).await?;
     }
 
     Ok(())
 }
 
+/// Writes out the sorted rows and closes every file, returning the paths of the files written.
+pub async fn close_partitions(
+    output: &Output,
+    args: &Args,
+    schema: &Schema,
+    mut writers: PartitionWriters,
+) -> anyhow::Result<Vec<Path>> {
+    // each group's sorted rows are written in full, so its file is closed before the next
+    let groups: Vec<Group> = writers
+        .sort
+        .as_ref()
+        .map(|sort| sort.sorters.keys().cloned().collect())
+        .unwrap_or_default();
+    for group in groups {
+        writers.close_group(output, args, schema, &group).await?;
+    }
+    for (group, file) in std::mem::take(&mut writers.open) {
+        writers.close_file(output, &group, file).await?;
+    }
+    Ok(writers.written)
+}
+
 fn build_column_index_map(schema: &Schema) -> HashMap<String, usize> {
     schema
         .fields()
@@ -60,17 +411,172 @@ fn build_column_index_map(schema: &Schema) -> HashMap<String, usize> {
         .collect()
 }
 
+/// The time component partition key `key` names, unless `schema` has a column of that name,
+/// which is then partitioned by as it is.
+fn time_key_of(key: &str, schema: &Schema) -> Option<TimeKey> {
+    TimeKey::parse(key).filter(|_| schema.field_with_name(key).is_err())
+}
+
+/// Checks that every partition key is a time component or a column whose values can name a
+/// directory and be read back unchanged.
+fn check_partition_columns(args: &Args, schema: &Schema) -> anyhow::Result<()> {
+    partition_tz(args)?;
+    for key in &args.partition_by {
+        let time_key = time_key_of(key, schema);
+        let column = if time_key.is_some() {
+            let Some(column) = &args.timestamp_col else {
+                bail!("Partitioning by {key} needs --timestamp-col");
+            };
+            column
+        } else {
+            key
+        };
+        let Ok(field) = schema.field_with_name(column) else {
+            bail!("Cannot partition by `{column}`; it is not among the extracted columns");
+        };
+        let data_type = field.data_type();
+        if let Some(time_key) = time_key {
+            check_time_column(time_key, column, data_type)?;
+        } else if !is_partition_type(data_type) {
+            bail!(
+                "Cannot partition by `{column}` of type {data_type}; use a string, number, boolean, date or timestamp column"
+            );
+        }
+    }
+    match (&args.bucket_by, args.buckets) {
+        (Some(_), Some(0)) => bail!("--buckets must be greater than zero"),
+        (Some(column), Some(_)) => {
+            let Ok(field) = schema.field_with_name(column) else {
+                bail!("Cannot bucket by `{column}`; it is not among the extracted columns");
+            };
+            let data_type = field.data_type();
+            if !is_partition_type(data_type) {
+                bail!(
+                    "Cannot bucket by `{column}` of type {data_type}; use a string, number, boolean, date or timestamp column"
+                );
+            }
+        }
+        (Some(_), None) => bail!("--bucket-by needs --buckets"),
+        (None, Some(_)) => bail!("--buckets needs --bucket-by"),
+        (None, None) => {}
+    }
+    if args.max_file_rows == Some(0) || args.max_file_size == Some(0) {
+        bail!("--max-file-rows and --max-file-size must be greater than zero");
+    }
+    Ok(())
+}
+
+/// The `--partition-tz`, UTC by default.
+fn partition_tz(args: &Args) -> anyhow::Result<Tz> {
+    parse_tz(args.partition_tz.as_deref().unwrap_or("UTC"))
+}
+
+/// Whether values of `data_type` can be written in directory names and cast back from them.
+fn is_partition_type(data_type: &DataType) -> bool {
+    data_type.is_integer()
+        || data_type.is_floating()
+        || matches!(
+            data_type,
+            DataType::Boolean
+                | DataType::Utf8
+                | DataType::LargeUtf8
+                | DataType::Utf8View
+                | DataType::Date32
+                | DataType::Timestamp(..)
+                | DataType::Decimal128(..)
+                | DataType::Decimal256(..)
+        )
+}
+
+/// Where the values of one partition key come from.
+enum KeyValues<'a> {
+    /// A component, such as `month`, of the `--timestamp-col`'s local times
+    Time {
+        key: TimeKey,
+        times: &'a [Option<NaiveDateTime>],
+    },
+    /// A column's own values, formatted as Arrow casts them back from strings
+    Column {
+        array: &'a dyn Array,
+        formatter: ArrayFormatter<'a>,
+    },
+}
+
+impl<'a> KeyValues<'a> {
+    fn column(array: &'a dyn Array) -> anyhow::Result<Self> {
+        Ok(Self::Column {
+            array,
+            formatter: ArrayFormatter::try_new(array, &FormatOptions::default())?,
+        })
+    }
+}
+
+impl KeyValues<'_> {
+    /// The value of row `row`, or `None` if it is null.
+    fn value(&self, row: usize) -> Option<String> {
+        match self {
+            Self::Time { key, times } => times[row].as_ref().map(|time| key.format(time)),
+            Self::Column { array, formatter } => {
+                (!array.is_null(row)).then(|| formatter.value(row).to_string())
+            }
+        }
+    }
+}
+
+/// The rows of `batch` in each partition and bucket.
 fn group_rows_by_partition(
     batch: &RecordBatch,
     args: &Args,
     idx_map: &HashMap<String, usize>,
-) -> anyhow::Result<HashMap<String, Vec<u32>>> {
-    let mut groups: HashMap<String, Vec<u32>> = HashMap::new();
+) -> anyhow::Result<HashMap<Group, Vec<u32>>> {
+    let column = |name: &str| {
+        idx_map
+            .get(name)
+            .map(|idx| batch.column(*idx))
+            .with_context(|| format!("Partition column `{name}` not found"))
+    };
+    let schema = batch.schema();
+    // every time key is taken from the same local times
+    let times = if args
+        .partition_by
+        .iter()
+        .any(|key| time_key_of(key, &schema).is_some())
+    {
+        let name = args
+            .timestamp_col
+            .as_deref()
+            .context("--timestamp-col must be set for time-based partitioning")?;
+        local_times(column(name)?, partition_tz(args)?)?
+    } else {
+        Vec::new()
+    };
 
+    let mut keys = Vec::with_capacity(args.partition_by.len());
+    for key in &args.partition_by {
+        let values = if let Some(time_key) = time_key_of(key, &schema) {
+            KeyValues::Time {
+                key: time_key,
+                times: &times,
+            }
+        } else {
+            KeyValues::column(column(key)?.as_ref())?
+        };
+        keys.push((key, values));
+    }
+    // bucket values are hashed as they would be written in a directory name
+    let bucket = match (&args.bucket_by, args.buckets) {
+        (Some(name), Some(buckets)) => Some((KeyValues::column(column(name)?.as_ref())?, buckets)),
+        _ => None,
+    };
+
+    let mut groups: HashMap<Group, Vec<u32>> = HashMap::new();
     for row in 0..batch.num_rows() {
-        let group_key = build_partition_key(row, batch, args, idx_map);
+        let group_key = build_partition_key(row, &keys);
+        let bucket = bucket
+            .as_ref()
+            .map(|(values, buckets)| bucket_of(values.value(row).as_deref(), *buckets));
         groups
-            .entry(group_key)
+            .entry((group_key, bucket))
             .or_default()
             .push(u32::try_from(row)?);
     }
@@ -78,68 +584,23 @@ fn group_rows_by_partition(
     Ok(groups)
 }
 
-fn build_partition_key(
-    row: usize,
-    batch: &RecordBatch,
-    args: &Args,
-    idx_map: &HashMap<String, usize>,
-) -> String {
-    let mut parts = Vec::new();
-
-    #[allow(clippy::expect_used)]
-    for key in &args.partition_by {
-        if ["year", "month", "day", "hour"].contains(&key.as_str()) {
-            let col_name = args
-                .timestamp_col
-                .as_ref()
-                .expect("timestamp_col must be set for time-based partitioning");
-            let col_idx = *idx_map.get(col_name).expect("timestamp col not found");
-            let ts_arr = batch
-                .column(col_idx)
-                .as_any()
-                .downcast_ref::<TimestampMicrosecondArray>()
-                .expect("timestamp type mismatch");
-            let ts = ts_arr
-                .value_as_datetime(row)
-                .expect("invalid timestamp value");
-            let val = match key.as_str() {
-                "year" => ts.year().to_string(),
-                "month" => format!("{:02}", ts.month()),
-                "day" => format!("{:02}", ts.day()),
-                "hour" => format!("{:02}", ts.hour()),
-                _ => unreachable!(),
-            };
-            parts.push(format!("{key}={val}"));
-        } else {
-            let col_idx = *idx_map.get(key).expect("column not found");
-            let arr = batch
-                .column(col_idx)
-                .as_any()
-                .downcast_ref::<StringArray>()
-                .expect("string type mismatch");
-            let val = arr.value(row);
-            parts.push(format!("{key}={val}"));
-        }
-    }
-
-    parts.join("/")
+/// The partition directory of row `row`, e.g. `region=eu%2Fwest/year=2026`.
+fn build_partition_key(row: usize, keys: &[(&String, KeyValues)]) -> String {
+    keys.iter()
+        .map(|(key, values)| format!("{key}={}", encode_value(values.value(row).as_deref())))
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
-fn write_partition(
-    grp: String,
+async fn write_partition(
+    writers: &mut PartitionWriters,
+    grp: Group,
     indices: Vec<u32>,
     batch: &RecordBatch,
     schema: &Schema,
     args: &Args,
+    output: &Output,
 ) -> anyhow::Result<()> {
-    let dir = Path::new(&args.output_dir).join(&args.table).join(grp);
-    fs::create_dir_all(&dir)?;
-    // TODO: support collisions and successive adding to // the same partition
-    let file_path = dir.join("part-00000.parquet");
-    let file = File::create(&file_path)?;
-    let props = WriterProperties::builder().build();
-    let mut writer = ArrowWriter::try_new(file, Arc::new(schema.clone()), Some(props))?;
-
     let idx_arr = UInt32Array::from(indices);
     let arrays: Vec<ArrayRef> = batch
         .columns()
@@ -147,22 +608,177 @@ fn write_partition(
         .map(|array| take(array.as_ref(), &idx_arr, None))
         .collect::<arrow::error::Result<Vec<_>>>()?;
     let sliced_batch = RecordBatch::try_new(Arc::new(schema.clone()), arrays)?;
-    writer.write(&sliced_batch)?;
-    writer.close()?;
-    info!("Wrote partitioned file to {}", file_path.display());
+    writers.add(output, args, schema, &grp, sliced_batch).await
+}
 
-    Ok(())
+/// The name of a run's file in a partition: `part-NNNNN-<run id>.parquet`, or
+/// `part-NNNNN-<run id>_BBBBB.parquet` for bucket `BBBBB`.  The sequence number orders files by
+/// the run that added them, and the run id keeps names unique when two runs pick the same
+/// number.
+fn part_file_name(sequence: u32, run_id: Uuid, bucket: Option<u32>) -> String {
+    let suffix = bucket.map_or_else(|| ".parquet".to_string(), bucket_suffix);
+    format!("part-{sequence:05}-{}{suffix}", run_id.simple())
+}
+
+/// The sequence number after those of every part file in `dir`, so that appending runs add
+/// files to a partition after the ones already there.
+async fn next_sequence(output: &Output, dir: &Path) -> anyhow::Result<u32> {
+    let mut next = 0;
+    for name in output.list(dir).await? {
+        // `part-00007.parquet` from older versions, or `part-00007-<run id>[_<bucket>].parquet`
+        let number = name
+            .strip_prefix("part-")
+            .and_then(|n| n.strip_suffix(".parquet"))
+            .and_then(|n| n.split('-').next())
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(number) = number {
+            next = next.max(number + 1);
+        }
+    }
+    Ok(next)
 }
 
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
-    use arrow::array::StringArray;
+    use arrow::array::{
+        BooleanArray, Date32Array, Decimal128Array, Int32Array, StringArray,
+        TimestampMicrosecondArray,
+    };
+    use arrow::datatypes::TimeUnit;
     use arrow::datatypes::{DataType, Field, Schema};
     use arrow::record_batch::RecordBatch;
+    use futures::StreamExt;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::file::metadata::ParquetMetaData;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use plano_core::commit::{COMMIT_MARKER, Manifest};
+    use std::fs::File;
     use std::sync::Arc;
-    use tempfile::tempdir;
+    use tempfile::{TempDir, tempdir};
+
+    /// The schema of the tables the writing tests partition by `key`.
+    fn key_value_schema() -> Arc<Schema> {
+        Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int32, false),
+        ]))
+    }
+
+    /// A batch of `keys` and their `values`.
+    fn key_values(keys: &[&str], values: &[i32]) -> RecordBatch {
+        RecordBatch::try_new(
+            key_value_schema(),
+            vec![
+                Arc::new(StringArray::from(keys.to_vec())),
+                Arc::new(Int32Array::from(values.to_vec())),
+            ],
+        )
+        .unwrap()
+    }
+
+    /// Writes `batches` to `args.table` in `dir`, partitioned by `key`, and returns the number of
+    /// rows written.
+    async fn write_keyed(dir: &TempDir, args: &Args, batches: Vec<RecordBatch>) -> usize {
+        let args = Args {
+            output_dir: dir.path().to_str().unwrap().to_string(),
+            partition_by: vec!["key".to_string()],
+            ..args.clone()
+        };
+        let output = Output::open(&args.output_dir).unwrap();
+        let stream = futures::stream::iter(batches.into_iter().map(Ok)).boxed();
+        write_partitioned_files(&args, &output, &key_value_schema(), stream)
+            .await
+            .unwrap()
+    }
+
+    /// A file written to a partition.
+    struct WrittenFile {
+        name: String,
+        metadata: ParquetMetaData,
+        values: Vec<i32>,
+    }
+
+    /// The files of partition `key` of `table` in `dir`, in name order.
+    fn partition_files(dir: &TempDir, table: &str, key: &str) -> Vec<WrittenFile> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir.path().join(format!("{table}/key={key}")))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| {
+                let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+                let values = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap()
+                    .flat_map(|batch| {
+                        let batch = batch.unwrap();
+                        let values = batch.column(1).as_any().downcast_ref::<Int32Array>();
+                        values.unwrap().values().to_vec()
+                    })
+                    .collect();
+                WrittenFile {
+                    name: path.file_name().unwrap().to_str().unwrap().to_string(),
+                    metadata: reader.metadata().clone(),
+                    values,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_time_key_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("date", DataType::Date32, false),
+            Field::new(
+                "ts",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Date32Array::from(vec![20_000])),
+                // 2026-10-17 06:30
+                Arc::new(TimestampMicrosecondArray::from(vec![1_792_218_600_000_000])),
+            ],
+        )
+        .unwrap();
+        let idx_map = build_column_index_map(&schema);
+
+        // a column named as a time key is partitioned by as it is
+        let args = Args {
+            partition_by: vec!["date".to_string()],
+            ..Default::default()
+        };
+        check_partition_columns(&args, &schema).unwrap();
+        let groups = group_rows_by_partition(&batch, &args, &idx_map).unwrap();
+        assert_eq!(groups.keys().next().unwrap().0, "date=2024-10-04");
+
+        let args = Args {
+            partition_by: vec!["date".to_string(), "hour".to_string()],
+            timestamp_col: Some("ts".to_string()),
+            ..Default::default()
+        };
+        check_partition_columns(&args, &schema).unwrap();
+        let groups = group_rows_by_partition(&batch, &args, &idx_map).unwrap();
+        assert_eq!(groups.keys().next().unwrap().0, "date=2024-10-04/hour=06");
+
+        let args = Args {
+            partition_by: vec!["year".to_string()],
+            ..Default::default()
+        };
+        let error = check_partition_columns(&args, &schema).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Partitioning by year needs --timestamp-col"
+        );
+    }
 
     #[test]
     fn test_build_column_index_map() {
@@ -195,12 +811,185 @@ mod tests {
         };
         let idx_map = build_column_index_map(&schema);
         let groups = group_rows_by_partition(&batch, &args, &idx_map).unwrap();
-        assert_eq!(groups.get("key=a").unwrap().len(), 2);
-        assert_eq!(groups.get("key=b").unwrap().len(), 1);
+        assert_eq!(groups[&("key=a".to_string(), None)].len(), 2);
+        assert_eq!(groups[&("key=b".to_string(), None)].len(), 1);
+    }
+
+    #[test]
+    fn test_partition_key_types() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("region", DataType::Utf8, true),
+            Field::new("shard", DataType::Int32, true),
+            Field::new("active", DataType::Boolean, false),
+            Field::new("posted", DataType::Date32, false),
+            Field::new("price", DataType::Decimal128(6, 2), false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec![
+                    Some("eu/west=1"),
+                    None,
+                    Some("100%"),
+                ])),
+                Arc::new(Int32Array::from(vec![Some(-3), Some(7), None])),
+                Arc::new(BooleanArray::from(vec![true, false, true])),
+                Arc::new(Date32Array::from(vec![20_000, 20_001, 20_000])),
+                Arc::new(
+                    Decimal128Array::from(vec![1250, 1, 1250])
+                        .with_precision_and_scale(6, 2)
+                        .unwrap(),
+                ),
+            ],
+        )
+        .unwrap();
+        let args = Args {
+            partition_by: vec!["year".to_string(), "hour".to_string()],
+            timestamp_col: Some("posted".to_string()),
+            ..Default::default()
+        };
+        let error = check_partition_columns(&args, &schema).unwrap_err();
+        assert!(
+            error.to_string().contains("without a time of day"),
+            "{error}"
+        );
+        let args = Args {
+            timestamp_col: Some("region".to_string()),
+            ..args
+        };
+        let error = check_partition_columns(&args, &schema).unwrap_err();
+        assert!(error.to_string().contains("not a timestamp"), "{error}");
+        let args = Args {
+            partition_by: ["region", "shard", "active", "posted", "price"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        check_partition_columns(&args, &schema).unwrap();
+        let groups =
+            group_rows_by_partition(&batch, &args, &build_column_index_map(&schema)).unwrap();
+        let mut keys: Vec<&String> = groups.keys().map(|(key, _)| key).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "region=100%25/shard=__HIVE_DEFAULT_PARTITION__/active=true/posted=2024-10-04/price=12.50",
+                "region=__HIVE_DEFAULT_PARTITION__/shard=7/active=false/posted=2024-10-05/price=0.01",
+                "region=eu%2Fwest%3D1/shard=-3/active=true/posted=2024-10-04/price=12.50",
+            ]
+        );
+        for key in keys {
+            assert_eq!(Path::parse(key).unwrap().parts().count(), 5);
+        }
+
+        let args = Args {
+            partition_by: vec!["tags".to_string()],
+            ..Default::default()
+        };
+        let schema = Schema::new(vec![Field::new("tags", DataType::Binary, false)]);
+        let error = check_partition_columns(&args, &schema).unwrap_err();
+        assert!(error.to_string().contains("type Binary"), "{error}");
+    }
+
+    #[test]
+    fn test_time_partitions() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        )]));
+        // 2026-10-16T23:30:00Z, null, 2026-10-17T09:00:00Z
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(
+                TimestampMicrosecondArray::from(vec![
+                    Some(1_792_193_400_000_000),
+                    None,
+                    Some(1_792_227_600_000_000),
+                ])
+                .with_timezone("UTC"),
+            )],
+        )
+        .unwrap();
+        let args = Args {
+            partition_by: vec!["date".to_string(), "hour".to_string()],
+            timestamp_col: Some("ts".to_string()),
+            partition_tz: Some("Europe/Amsterdam".to_string()),
+            ..Default::default()
+        };
+        check_partition_columns(&args, &schema).unwrap();
+        let groups =
+            group_rows_by_partition(&batch, &args, &build_column_index_map(&schema)).unwrap();
+        let mut keys: Vec<&String> = groups.keys().map(|(key, _)| key).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "date=2026-10-17/hour=01",
+                "date=2026-10-17/hour=11",
+                "date=__HIVE_DEFAULT_PARTITION__/hour=__HIVE_DEFAULT_PARTITION__",
+            ]
+        );
+
+        let args = Args {
+            partition_tz: Some("Nowhere".to_string()),
+            ..args
+        };
+        assert!(check_partition_columns(&args, &schema).is_err());
     }
 
     #[test]
-    fn test_write_partition() {
+    fn test_bucket_rows() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("region", DataType::Utf8, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let names = ["ada", "bob", "cy", "ada", "dee", "bob"];
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["eu", "eu", "eu", "eu", "us", "us"])),
+                Arc::new(StringArray::from(names.map(Some).to_vec())),
+            ],
+        )
+        .unwrap();
+        let args = Args {
+            bucket_by: Some("name".to_string()),
+            buckets: Some(4),
+            ..Default::default()
+        };
+        check_partition_columns(&args, &schema).unwrap();
+        let idx_map = build_column_index_map(&schema);
+        let groups = group_rows_by_partition(&batch, &args, &idx_map).unwrap();
+        for ((dir, bucket), rows) in &groups {
+            assert_eq!(dir, "");
+            for row in rows {
+                let name = names[usize::try_from(*row).unwrap()];
+                assert_eq!(*bucket, Some(bucket_of(Some(name), 4)));
+            }
+        }
+
+        let args = Args {
+            partition_by: vec!["region".to_string()],
+            ..args
+        };
+        let groups = group_rows_by_partition(&batch, &args, &idx_map).unwrap();
+        let bob = |region: &str| {
+            groups[&(format!("region={region}"), Some(bucket_of(Some("bob"), 4)))].clone()
+        };
+        assert!(bob("eu").contains(&1));
+        assert!(bob("us").contains(&5));
+
+        let args = Args {
+            buckets: None,
+            ..args
+        };
+        let error = check_partition_columns(&args, &schema).unwrap_err();
+        assert_eq!(error.to_string(), "--bucket-by needs --buckets");
+    }
+
+    #[tokio::test]
+    async fn test_write_partition() {
         let schema = Arc::new(Schema::new(vec![
             Field::new("key", DataType::Utf8, false),
             Field::new("value", DataType::Utf8, false),
@@ -219,11 +1008,235 @@ mod tests {
             table: "test_table".to_string(),
             ..Default::default()
         };
-        write_partition("key=a".to_string(), vec![0, 2], &batch, &schema, &args).unwrap();
-        let output_path = dir.path().join("test_table/key=a/part-00000.parquet");
+        let output = Output::open(&args.output_dir).unwrap();
+        let commit = Commit::new(&args, &output);
+        let run_id = commit.run_id();
+        let mut writers = PartitionWriters::new(commit.clone(), WriterProperties::default());
+        write_partition(
+            &mut writers,
+            ("key=a".to_string(), None),
+            vec![0, 2],
+            &batch,
+            &schema,
+            &args,
+            &output,
+        )
+        .await
+        .unwrap();
+        write_partition(
+            &mut writers,
+            ("key=a".to_string(), None),
+            vec![1],
+            &batch,
+            &schema,
+            &args,
+            &output,
+        )
+        .await
+        .unwrap();
+        let written = close_partitions(&output, &args, &schema, writers)
+            .await
+            .unwrap();
+        let name = part_file_name(0, run_id, None);
+        assert_eq!(written, [Path::from(format!("test_table/key=a/{name}"))]);
+        commit
+            .commit(&args, &output, &schema, &written)
+            .await
+            .unwrap();
+        let output_path = dir.path().join("test_table/key=a").join(name);
         assert!(
             output_path.exists(),
             "Expected partition file to be written"
         );
+        let reader = SerializedFileReader::new(File::open(&output_path).unwrap()).unwrap();
+        assert_eq!(
+            reader.metadata().file_metadata().num_rows(),
+            3,
+            "Expected rows from successive batches in the same file"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_sorted_partitions() {
+        let dir = tempdir().unwrap();
+        let args = Args {
+            table: "sorted".to_string(),
+            sort_by: vec!["value".to_string()],
+            batch_size: 2,
+            // spill on every batch
+            sort_memory: 0,
+            ..Default::default()
+        };
+        let batches = vec![
+            key_values(&["a", "b", "a"], &[5, 4, 1]),
+            key_values(&["b", "a", "a"], &[2, 3, 0]),
+        ];
+        assert_eq!(write_keyed(&dir, &args, batches).await, 6);
+
+        let files = partition_files(&dir, "sorted", "a");
+        let sorting = files[0].metadata.row_group(0).sorting_columns().unwrap();
+        assert_eq!(sorting[0].column_idx, 1);
+        assert_eq!(files[0].values, [0, 1, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_roll_files() {
+        let dir = tempdir().unwrap();
+        let args = Args {
+            table: "rolled".to_string(),
+            max_file_rows: Some(2),
+            ..Default::default()
+        };
+        let batches = vec![
+            key_values(&["a", "a", "a", "b"], &[1, 2, 3, 4]),
+            key_values(&["a", "a", "a"], &[5, 6, 7]),
+        ];
+        write_keyed(&dir, &args, batches).await;
+        let files = partition_files(&dir, "rolled", "a");
+        // three full files and no empty fourth one
+        assert_eq!(files.len(), 3);
+        for (sequence, file) in files.iter().enumerate() {
+            assert!(
+                file.name.starts_with(&format!("part-{sequence:05}-")),
+                "{}",
+                file.name
+            );
+            assert_eq!(file.metadata.file_metadata().num_rows(), 2);
+        }
+        assert_eq!(partition_files(&dir, "rolled", "b").len(), 1);
+
+        // every batch fills a file of one byte
+        let schema = key_value_schema();
+        let output = Output::open(dir.path().join("sized").to_str().unwrap()).unwrap();
+        let commit = Commit::new(&args, &output);
+        let run_id = commit.run_id();
+        let mut writers =
+            PartitionWriters::new(commit, WriterProperties::default()).with_limits(None, Some(1));
+        let group = ("key=a".to_string(), None);
+        for values in [vec![1, 2], vec![3]] {
+            let keys = vec!["a"; values.len()];
+            writers
+                .write(&output, &args, &schema, &group, key_values(&keys, &values))
+                .await
+                .unwrap();
+        }
+        let written = close_partitions(&output, &args, &schema, writers)
+            .await
+            .unwrap();
+        let names: Vec<_> = (0..2)
+            .map(|sequence| {
+                Path::from(format!(
+                    "rolled/key=a/{}",
+                    part_file_name(sequence, run_id, None)
+                ))
+            })
+            .collect();
+        assert_eq!(written, names);
+    }
+
+    #[tokio::test]
+    async fn test_limit_memory() {
+        let dir = tempdir().unwrap();
+        for (table, write_memory) in [("roomy", 256), ("tight", 0)] {
+            let args = Args {
+                table: table.to_string(),
+                write_memory,
+                ..Default::default()
+            };
+            let batch = key_values(&["a", "b", "a"], &[1, 2, 3]);
+            write_keyed(&dir, &args, vec![batch.clone(), batch]).await;
+        }
+        let row_groups = |table, key| {
+            partition_files(&dir, table, key)[0]
+                .metadata
+                .num_row_groups()
+        };
+        assert_eq!(row_groups("roomy", "a"), 1);
+        // with no memory to spare, each batch's rows are written out as they arrive
+        assert_eq!(row_groups("tight", "a"), 2);
+        assert_eq!(row_groups("tight", "b"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_copy_table_file() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "value",
+            DataType::Int32,
+            false,
+        )]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1]))])
+            .unwrap();
+        let dir = tempdir().unwrap();
+        let args = Args {
+            output_dir: dir.path().to_str().unwrap().to_string(),
+            table: "readings".to_string(),
+            mode: Some(WriteMode::Append),
+            batch_size: 10,
+            ..Default::default()
+        };
+        let output = Output::open(&args.output_dir).unwrap();
+        let stream = || futures::stream::iter([Ok(batch.clone())]).boxed();
+        // written by an earlier overwrite
+        output
+            .write(&args.table_file(), b"earlier".to_vec())
+            .await
+            .unwrap();
+
+        // a run whose commit fails leaves the table's file where it was
+        let marker = args.table_dir().join(COMMIT_MARKER);
+        output.write(&marker, b"{".to_vec()).await.unwrap();
+        assert!(
+            write_partitioned_files(&args, &output, &schema, stream())
+                .await
+                .is_err()
+        );
+        assert!(output.exists(&args.table_file()).await.unwrap());
+
+        output.delete(&marker).await.unwrap();
+        write_partitioned_files(&args, &output, &schema, stream())
+            .await
+            .unwrap();
+        assert!(!output.exists(&args.table_file()).await.unwrap());
+        let copy = args
+            .table_dir()
+            .join("part-00000-00000000000000000000000000000000.parquet");
+        assert_eq!(
+            output.read(&copy).await.unwrap().unwrap().as_ref(),
+            b"earlier"
+        );
+        let manifest: Manifest =
+            serde_json::from_slice(&output.read(&marker).await.unwrap().unwrap()).unwrap();
+        // the failed run's file was never moved into place
+        assert_eq!(manifest.files.len(), 2, "{:?}", manifest.files);
+        assert_eq!(manifest.files[0], copy.filename().unwrap());
+    }
+
+    #[test]
+    fn test_part_file_name() {
+        let run_id = Uuid::from_u128(0x0192_0000_0000_7000_8000_0000_0000_0001);
+        assert_eq!(
+            part_file_name(12, run_id, None),
+            "part-00012-01920000000070008000000000000001.parquet"
+        );
+        assert_eq!(
+            part_file_name(0, run_id, Some(3)),
+            "part-00000-01920000000070008000000000000001_00003.parquet"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_next_sequence() {
+        let dir = tempdir().unwrap();
+        let output = Output::open(dir.path().to_str().unwrap()).unwrap();
+        let table_dir = Path::from("events");
+        assert_eq!(next_sequence(&output, &table_dir).await.unwrap(), 0);
+        std::fs::create_dir(dir.path().join("events")).unwrap();
+        File::create(dir.path().join("events/part-00000.parquet")).unwrap();
+        File::create(dir.path().join("events/part-00007.parquet")).unwrap();
+        File::create(dir.path().join("events/_sync_state.json")).unwrap();
+        assert_eq!(next_sequence(&output, &table_dir).await.unwrap(), 8);
+        let name = part_file_name(8, Uuid::now_v7(), Some(1));
+        File::create(dir.path().join("events").join(name)).unwrap();
+        assert_eq!(next_sequence(&output, &table_dir).await.unwrap(), 9);
     }
 }
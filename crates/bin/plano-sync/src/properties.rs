@@ -0,0 +1,293 @@
+///
+/// How the Parquet files are encoded
+///
+use anyhow::{Result, bail};
+use arrow::datatypes::Schema;
+use clap::ValueEnum;
+use parquet::basic::{BrotliLevel, Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion};
+use parquet::schema::types::ColumnPath;
+use serde::Deserialize;
+
+/// Parquet writer settings, from the command line or a table's `[tables.<name>.parquet]` in
+/// the `--config` file.  Those left unset keep the Parquet library's defaults.
+#[derive(clap::Args, Debug, Default, Clone, Deserialize)]
+#[command(next_help_heading = "Parquet")]
+#[serde(deny_unknown_fields)]
+pub struct ParquetOptions {
+    /// Compression codec (default: uncompressed)
+    #[arg(long, value_enum)]
+    compression: Option<Codec>,
+
+    /// Compression level for gzip (0-9), zstd (1-22) and brotli (0-11)
+    #[arg(long, requires = "compression")]
+    compression_level: Option<u32>,
+
+    /// Maximum number of rows in a row group (default: 1048576)
+    #[arg(long, value_parser = crate::parse_positive)]
+    max_row_group_size: Option<usize>,
+
+    /// Target size of data pages in bytes (default: 1048576)
+    #[arg(long, value_parser = crate::parse_positive)]
+    data_page_size: Option<usize>,
+
+    /// Whether to dictionary-encode columns (default: true)
+    #[arg(long)]
+    dictionary: Option<bool>,
+
+    /// Which min/max statistics to write (default: page)
+    #[arg(long, value_enum)]
+    statistics: Option<Statistics>,
+
+    /// Columns to write bloom filters for, so that `=` filters can skip row groups (can repeat
+    /// or be comma-separated)
+    #[arg(long = "bloom-filter", value_delimiter = ',', action = clap::ArgAction::Append)]
+    #[serde(default)]
+    bloom_filters: Vec<String>,
+
+    /// Expected number of distinct values per row group of each --bloom-filter column, which
+    /// sizes the filters (default: 1000000)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    bloom_filter_ndv: Option<u64>,
+
+    /// Parquet format version of the data pages (default: 1.0)
+    #[arg(long, value_enum)]
+    writer_version: Option<Version>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Codec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    /// LZ4 without the Hadoop framing (`LZ4_RAW`)
+    Lz4,
+    Zstd,
+    Brotli,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Statistics {
+    /// No statistics
+    None,
+    /// For each column chunk
+    Chunk,
+    /// For each column chunk and data page
+    Page,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+enum Version {
+    #[value(name = "1.0")]
+    #[serde(rename = "1.0")]
+    V1,
+    #[value(name = "2.0")]
+    #[serde(rename = "2.0")]
+    V2,
+}
+
+impl ParquetOptions {
+    /// Replaces the settings in `options` with those set here.
+    pub fn apply(&self, options: &mut Self) {
+        options.compression = self.compression.or(options.compression);
+        options.compression_level = self.compression_level.or(options.compression_level);
+        options.max_row_group_size = self.max_row_group_size.or(options.max_row_group_size);
+        options.data_page_size = self.data_page_size.or(options.data_page_size);
+        options.dictionary = self.dictionary.or(options.dictionary);
+        options.statistics = self.statistics.or(options.statistics);
+        if !self.bloom_filters.is_empty() {
+            options.bloom_filters.clone_from(&self.bloom_filters);
+        }
+        options.bloom_filter_ndv = self.bloom_filter_ndv.or(options.bloom_filter_ndv);
+        options.writer_version = self.writer_version.or(options.writer_version);
+    }
+
+    /// The writer properties for files of `schema`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the compression level is out of range for the codec, or a
+    /// --bloom-filter column is not in `schema`.
+    pub fn properties(&self, schema: &Schema) -> Result<WriterProperties> {
+        let mut builder = WriterProperties::builder();
+        if let Some(codec) = self.compression {
+            builder = builder.set_compression(compression(codec, self.compression_level)?);
+        }
+        if let Some(rows) = self.max_row_group_size {
+            builder = builder.set_max_row_group_row_count(Some(rows));
+        }
+        if let Some(bytes) = self.data_page_size {
+            builder = builder.set_data_page_size_limit(bytes);
+        }
+        if let Some(dictionary) = self.dictionary {
+            builder = builder.set_dictionary_enabled(dictionary);
+        }
+        if let Some(statistics) = self.statistics {
+            builder = builder.set_statistics_enabled(match statistics {
+                Statistics::None => EnabledStatistics::None,
+                Statistics::Chunk => EnabledStatistics::Chunk,
+                Statistics::Page => EnabledStatistics::Page,
+            });
+        }
+        for column in &self.bloom_filters {
+            if schema.field_with_name(column).is_err() {
+                bail!("--bloom-filter column `{column}` is not among the extracted columns");
+            }
+            let path = ColumnPath::from(column.as_str());
+            builder = builder.set_column_bloom_filter_enabled(path.clone(), true);
+            if let Some(ndv) = self.bloom_filter_ndv {
+                builder = builder.set_column_bloom_filter_ndv(path, ndv);
+            }
+        }
+        if let Some(version) = self.writer_version {
+            builder = builder.set_writer_version(match version {
+                Version::V1 => WriterVersion::PARQUET_1_0,
+                Version::V2 => WriterVersion::PARQUET_2_0,
+            });
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The compression for `codec` at `level`, or the codec's default level.
+fn compression(codec: Codec, level: Option<u32>) -> Result<Compression> {
+    let compression = match (codec, level) {
+        (Codec::Gzip, level) => {
+            Compression::GZIP(level.map_or_else(|| Ok(GzipLevel::default()), GzipLevel::try_new)?)
+        }
+        (Codec::Zstd, level) => Compression::ZSTD(level.map_or_else(
+            || Ok(ZstdLevel::default()),
+            |level| ZstdLevel::try_new(i32::try_from(level).unwrap_or(i32::MAX)),
+        )?),
+        (Codec::Brotli, level) => Compression::BROTLI(
+            level.map_or_else(|| Ok(BrotliLevel::default()), BrotliLevel::try_new)?,
+        ),
+        (codec, Some(_)) => bail!(
+            "--compression {} does not take a level",
+            format!("{codec:?}").to_lowercase()
+        ),
+        (Codec::Uncompressed, None) => Compression::UNCOMPRESSED,
+        (Codec::Snappy, None) => Compression::SNAPPY,
+        (Codec::Lz4, None) => Compression::LZ4_RAW,
+    };
+    Ok(compression)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::{DataType, Field};
+    use parquet::file::properties::DEFAULT_MAX_ROW_GROUP_ROW_COUNT;
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ])
+    }
+
+    #[test]
+    fn test_properties() {
+        let defaults = ParquetOptions::default().properties(&schema()).unwrap();
+        assert_eq!(
+            defaults.max_row_group_row_count(),
+            Some(DEFAULT_MAX_ROW_GROUP_ROW_COUNT)
+        );
+        let name = ColumnPath::from("name");
+        assert!(defaults.bloom_filter_properties(&name).is_none());
+
+        let options = ParquetOptions {
+            compression: Some(Codec::Zstd),
+            compression_level: Some(9),
+            max_row_group_size: Some(10_000),
+            dictionary: Some(false),
+            statistics: Some(Statistics::Chunk),
+            bloom_filters: vec!["name".to_string()],
+            bloom_filter_ndv: Some(500),
+            writer_version: Some(Version::V2),
+            ..Default::default()
+        };
+        let props = options.properties(&schema()).unwrap();
+        assert_eq!(
+            props.compression(&name),
+            Compression::ZSTD(ZstdLevel::try_new(9).unwrap())
+        );
+        assert_eq!(props.max_row_group_row_count(), Some(10_000));
+        assert!(!props.dictionary_enabled(&name));
+        assert_eq!(props.statistics_enabled(&name), EnabledStatistics::Chunk);
+        assert_eq!(props.bloom_filter_properties(&name).unwrap().ndv, 500);
+        assert!(
+            props
+                .bloom_filter_properties(&ColumnPath::from("id"))
+                .is_none()
+        );
+        assert_eq!(props.writer_version(), WriterVersion::PARQUET_2_0);
+    }
+
+    #[test]
+    fn test_invalid_properties() {
+        let options = |compression, compression_level| ParquetOptions {
+            compression: Some(compression),
+            compression_level,
+            ..Default::default()
+        };
+        assert!(
+            options(Codec::Zstd, Some(23))
+                .properties(&schema())
+                .is_err()
+        );
+        assert!(
+            options(Codec::Gzip, Some(10))
+                .properties(&schema())
+                .is_err()
+        );
+        let error = options(Codec::Snappy, Some(1))
+            .properties(&schema())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--compression snappy does not take a level"
+        );
+        assert_eq!(
+            options(Codec::Lz4, None)
+                .properties(&schema())
+                .unwrap()
+                .compression(&ColumnPath::from("id")),
+            Compression::LZ4_RAW
+        );
+
+        let options = ParquetOptions {
+            bloom_filters: vec!["nmae".to_string()],
+            ..Default::default()
+        };
+        assert!(options.properties(&schema()).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut options = ParquetOptions {
+            compression: Some(Codec::Snappy),
+            dictionary: Some(false),
+            ..Default::default()
+        };
+        let config: ParquetOptions = toml::from_str(
+            r#"
+            compression = "zstd"
+            compression_level = 3
+            bloom_filters = ["name"]
+            writer_version = "2.0"
+            "#,
+        )
+        .unwrap();
+        config.apply(&mut options);
+        assert_eq!(options.compression, Some(Codec::Zstd));
+        assert_eq!(options.compression_level, Some(3));
+        assert_eq!(options.dictionary, Some(false));
+        assert_eq!(options.bloom_filters, ["name"]);
+        assert_eq!(options.writer_version, Some(Version::V2));
+        assert!(toml::from_str::<ParquetOptions>("codec = \"zstd\"").is_err());
+    }
+}
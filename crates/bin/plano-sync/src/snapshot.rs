@@ -0,0 +1,60 @@
+///
+/// Record of the snapshot a multi-table sync was read from
+///
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use object_store::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::output::Output;
+
+/// Name of the record kept in the output directory.
+pub const SNAPSHOT_FILE: &str = "_snapshot.json";
+
+/// The tables written by the last multi-table run into an output directory, all of which were
+/// read from the same snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    /// The id the snapshot was exported under.
+    pub snapshot: String,
+    /// When the snapshot was taken, by the database's clock.
+    pub taken_at: DateTime<Utc>,
+    /// The output names of the tables read from it.
+    pub tables: Vec<String>,
+}
+
+impl SnapshotRecord {
+    /// Writes the record into the output directory, replacing it atomically.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be written.
+    pub async fn save(&self, output: &Output) -> Result<()> {
+        output
+            .write(&Path::from(SNAPSHOT_FILE), serde_json::to_vec_pretty(self)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_save() {
+        let dir = tempdir().unwrap();
+        let record = SnapshotRecord {
+            snapshot: "00000003-0000001B-1".to_string(),
+            taken_at: Utc::now(),
+            tables: vec!["employees".to_string(), "crm".to_string()],
+        };
+        let output = Output::open(dir.path().to_str().unwrap()).unwrap();
+        record.save(&output).await.unwrap();
+        let contents = fs::read_to_string(dir.path().join(SNAPSHOT_FILE)).unwrap();
+        let saved: SnapshotRecord = serde_json::from_str(&contents).unwrap();
+        assert_eq!(saved, record);
+    }
+}
@@ -0,0 +1,438 @@
+///
+/// Sorting the rows of each output file by the `--sort-by` columns
+///
+use anyhow::{Context, Result, bail};
+use arrow::compute::{
+    SortColumn, SortOptions, concat_batches, interleave_record_batch, lexsort_to_indices,
+    take_record_batch,
+};
+use arrow::datatypes::SchemaRef;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use arrow::row::{Row, RowConverter, Rows, SortField};
+use parquet::arrow::ArrowSchemaConverter;
+use parquet::file::metadata::SortingColumn;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, Write};
+
+/// The `--sort-by` columns, resolved against the schema of the files.  Each is `name`, or
+/// `name desc` for descending order; nulls sort last when ascending and first when
+/// descending, as they do by default in Postgres and `DataFusion`.
+#[derive(Debug, Clone)]
+pub struct SortOrder {
+    /// Index in the schema and direction of each column.
+    columns: Vec<(usize, SortOptions)>,
+    schema: SchemaRef,
+}
+
+impl SortOrder {
+    /// The order given by `sort_by` for rows of `schema`, or `None` if it is empty.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a column is not in `schema`, or cannot be sorted.
+    pub fn new(sort_by: &[String], schema: &SchemaRef) -> Result<Option<Self>> {
+        if sort_by.is_empty() {
+            return Ok(None);
+        }
+        let mut columns = Vec::with_capacity(sort_by.len());
+        for key in sort_by {
+            let (name, descending) = match key.trim().rsplit_once(' ') {
+                Some((name, order)) if order.eq_ignore_ascii_case("desc") => (name.trim(), true),
+                Some((name, order)) if order.eq_ignore_ascii_case("asc") => (name.trim(), false),
+                _ => (key.trim(), false),
+            };
+            let Ok(index) = schema.index_of(name) else {
+                bail!("Cannot sort by `{name}`; it is not among the extracted columns");
+            };
+            let options = SortOptions {
+                descending,
+                nulls_first: descending,
+            };
+            columns.push((index, options));
+        }
+        let order = Self {
+            columns,
+            schema: schema.clone(),
+        };
+        if !RowConverter::supports_fields(&order.sort_fields()) {
+            bail!(
+                "Cannot sort by {}; use columns of primitive types",
+                sort_by.join(", ")
+            );
+        }
+        Ok(Some(order))
+    }
+
+    fn sort_fields(&self) -> Vec<SortField> {
+        self.columns
+            .iter()
+            .map(|(index, options)| {
+                SortField::new_with_options(self.schema.field(*index).data_type().clone(), *options)
+            })
+            .collect()
+    }
+
+    /// The order as Parquet records it in each row group, by leaf column.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a column is nested, so has no leaf column of its own.
+    pub fn sorting_columns(&self) -> Result<Vec<SortingColumn>> {
+        let descriptor = ArrowSchemaConverter::new().convert(&self.schema)?;
+        self.columns
+            .iter()
+            .map(|(index, options)| {
+                let name = self.schema.field(*index).name();
+                let leaf = descriptor
+                    .columns()
+                    .iter()
+                    .position(|column| column.path().parts() == [name.as_str()])
+                    .with_context(|| format!("Cannot sort by the nested column `{name}`"))?;
+                Ok(SortingColumn {
+                    column_idx: i32::try_from(leaf)?,
+                    descending: options.descending,
+                    nulls_first: options.nulls_first,
+                })
+            })
+            .collect()
+    }
+
+    /// `props` with this order recorded in every row group.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a column is nested.
+    pub fn record(&self, props: WriterProperties) -> Result<WriterProperties> {
+        Ok(props
+            .into_builder()
+            .set_sorting_columns(Some(self.sorting_columns()?))
+            .build())
+    }
+
+    /// The rows of `batches`, sorted.
+    fn sort(&self, batches: &[RecordBatch]) -> Result<RecordBatch> {
+        let batch = concat_batches(&self.schema, batches)?;
+        let columns: Vec<SortColumn> = self
+            .columns
+            .iter()
+            .map(|(index, options)| SortColumn {
+                values: batch.column(*index).clone(),
+                options: Some(*options),
+            })
+            .collect();
+        let indices = lexsort_to_indices(&columns, None)?;
+        Ok(take_record_batch(&batch, &indices)?)
+    }
+}
+
+/// The rows of one output file, buffered until they have all arrived and then returned in
+/// order.  When the buffer grows too large it is sorted and spilled to a temporary file, and
+/// the spilled runs are merged at the end.
+pub struct Sorter {
+    order: SortOrder,
+    batch_size: usize,
+    buffered: Vec<RecordBatch>,
+    buffered_bytes: usize,
+    runs: Vec<File>,
+}
+
+impl Sorter {
+    pub const fn new(order: SortOrder, batch_size: usize) -> Self {
+        Self {
+            order,
+            batch_size,
+            buffered: Vec::new(),
+            buffered_bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds `batch`, returning the number of bytes it takes up in memory.
+    pub fn push(&mut self, batch: RecordBatch) -> usize {
+        let bytes = batch.get_array_memory_size();
+        self.buffered_bytes += bytes;
+        self.buffered.push(batch);
+        bytes
+    }
+
+    /// The number of bytes held in memory.
+    pub const fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Sorts the rows held in memory and writes them to a temporary file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the temporary file cannot be written.
+    pub fn spill(&mut self) -> Result<()> {
+        if self.buffered.is_empty() {
+            return Ok(());
+        }
+        let sorted = self.order.sort(&self.buffered)?;
+        let mut file = tempfile::tempfile().context("creating a file to sort rows in")?;
+        let mut writer = FileWriter::try_new(BufWriter::new(&mut file), &self.order.schema)?;
+        for batch in chunks(&sorted, self.batch_size) {
+            writer.write(&batch)?;
+        }
+        writer.into_inner()?.flush()?;
+        file.rewind()?;
+        self.runs.push(file);
+        self.buffered.clear();
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Every row pushed, in order, in batches of up to the batch size.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a spilled run cannot be read back.
+    pub fn finish(self) -> Result<Box<dyn Iterator<Item = Result<RecordBatch>> + Send>> {
+        let sorted = self.order.sort(&self.buffered)?;
+        let in_memory = chunks(&sorted, self.batch_size).map(Ok);
+        if self.runs.is_empty() {
+            return Ok(Box::new(in_memory.collect::<Vec<_>>().into_iter()));
+        }
+
+        let mut sources: Vec<Batches> = Vec::with_capacity(self.runs.len() + 1);
+        for file in self.runs {
+            let reader = FileReader::try_new(BufReader::new(file), None)?;
+            sources.push(Box::new(
+                reader.map(|batch| batch.context("reading back sorted rows")),
+            ));
+        }
+        sources.push(Box::new(in_memory.collect::<Vec<_>>().into_iter()));
+        Ok(Box::new(Merge::new(&self.order, sources, self.batch_size)?))
+    }
+}
+
+/// `batch` in slices of up to `size` rows.
+fn chunks(batch: &RecordBatch, size: usize) -> impl Iterator<Item = RecordBatch> {
+    (0..batch.num_rows())
+        .step_by(size.max(1))
+        .map(move |offset| batch.slice(offset, size.min(batch.num_rows() - offset)))
+}
+
+type Batches = Box<dyn Iterator<Item = Result<RecordBatch>> + Send>;
+
+/// One sorted run being merged: its current batch and position in it.
+struct Run {
+    batches: Batches,
+    /// Index of the current batch in `Merge::pending`.
+    current: usize,
+    rows: Rows,
+    position: usize,
+}
+
+impl Run {
+    fn row(&self) -> Row<'_> {
+        self.rows.row(self.position)
+    }
+}
+
+/// Merges sorted runs into batches of up to `batch_size` rows.  There are few runs, so the
+/// next row is found by comparing the current row of each.
+struct Merge {
+    columns: Vec<usize>,
+    converter: RowConverter,
+    runs: Vec<Run>,
+    /// The batches the rows of the next output batch are taken from.
+    pending: Vec<RecordBatch>,
+    batch_size: usize,
+}
+
+impl Merge {
+    fn new(order: &SortOrder, sources: Vec<Batches>, batch_size: usize) -> Result<Self> {
+        let mut merge = Self {
+            columns: order.columns.iter().map(|(index, _)| *index).collect(),
+            converter: RowConverter::new(order.sort_fields())?,
+            runs: Vec::with_capacity(sources.len()),
+            pending: Vec::new(),
+            batch_size,
+        };
+        for mut batches in sources {
+            if let Some((batch, rows)) = merge.next_of(&mut batches)? {
+                merge.pending.push(batch);
+                merge.runs.push(Run {
+                    batches,
+                    current: merge.pending.len() - 1,
+                    rows,
+                    position: 0,
+                });
+            }
+        }
+        Ok(merge)
+    }
+
+    /// The next batch with rows from `batches`, and its sort keys.
+    fn next_of(&self, batches: &mut Batches) -> Result<Option<(RecordBatch, Rows)>> {
+        for batch in batches {
+            let batch = batch?;
+            if batch.num_rows() > 0 {
+                let columns: Vec<_> = self
+                    .columns
+                    .iter()
+                    .map(|index| batch.column(*index).clone())
+                    .collect();
+                let rows = self.converter.convert_columns(&columns)?;
+                return Ok(Some((batch, rows)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let mut indices = Vec::with_capacity(self.batch_size);
+        while indices.len() < self.batch_size {
+            let Some(next) =
+                (0..self.runs.len()).min_by(|a, b| self.runs[*a].row().cmp(&self.runs[*b].row()))
+            else {
+                break;
+            };
+            let run = &mut self.runs[next];
+            indices.push((run.current, run.position));
+            run.position += 1;
+            if run.position == run.rows.num_rows() {
+                let mut run = self.runs.swap_remove(next);
+                if let Some((batch, rows)) = self.next_of(&mut run.batches)? {
+                    self.pending.push(batch);
+                    run.current = self.pending.len() - 1;
+                    run.rows = rows;
+                    run.position = 0;
+                    self.runs.push(run);
+                }
+            }
+        }
+        if indices.is_empty() {
+            return Ok(None);
+        }
+        let pending: Vec<&RecordBatch> = self.pending.iter().collect();
+        let batch = interleave_record_batch(&pending, &indices)?;
+
+        // keep only the batches the runs are still reading from
+        let pending = std::mem::take(&mut self.pending);
+        for run in &mut self.runs {
+            self.pending.push(pending[run.current].clone());
+            run.current = self.pending.len() - 1;
+        }
+        Ok(Some(batch))
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, Int32Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("name", DataType::Utf8, true),
+            Field::new("n", DataType::Int32, false),
+        ]))
+    }
+
+    fn batch(names: &[Option<&str>], n: &[i32]) -> RecordBatch {
+        RecordBatch::try_new(
+            schema(),
+            vec![
+                Arc::new(StringArray::from(names.to_vec())),
+                Arc::new(Int32Array::from(n.to_vec())),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn rows(batches: impl Iterator<Item = Result<RecordBatch>>) -> Vec<(Option<String>, i32)> {
+        let mut rows = Vec::new();
+        for batch in batches {
+            let batch = batch.unwrap();
+            assert!(batch.num_rows() <= 2);
+            let names = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let n = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .unwrap();
+            for i in 0..batch.num_rows() {
+                let name = (!names.is_null(i)).then(|| names.value(i).to_string());
+                rows.push((name, n.value(i)));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_sort_and_spill() {
+        let sort_by = ["name".to_string(), "n desc".to_string()];
+        let order = SortOrder::new(&sort_by, &schema()).unwrap().unwrap();
+        let batches = [
+            batch(&[Some("b"), None, Some("a")], &[1, 2, 3]),
+            batch(&[Some("a"), Some("c")], &[4, 5]),
+            batch(&[Some("b"), None, Some("a")], &[6, 7, 8]),
+        ];
+        let expected = [
+            (Some("a"), 8),
+            (Some("a"), 4),
+            (Some("a"), 3),
+            (Some("b"), 6),
+            (Some("b"), 1),
+            (Some("c"), 5),
+            (None, 7),
+            (None, 2),
+        ]
+        .map(|(name, n)| (name.map(String::from), n));
+
+        // all in memory
+        let mut sorter = Sorter::new(order.clone(), 2);
+        for batch in &batches {
+            sorter.push(batch.clone());
+        }
+        assert_eq!(rows(sorter.finish().unwrap()), expected);
+
+        // spilled after each batch but the last
+        let mut sorter = Sorter::new(order, 2);
+        for batch in &batches {
+            sorter.spill().unwrap();
+            assert!(sorter.push(batch.clone()) > 0);
+        }
+        assert_eq!(sorter.runs.len(), 2);
+        assert_eq!(rows(sorter.finish().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_sort_order() {
+        assert!(SortOrder::new(&[], &schema()).unwrap().is_none());
+        let sort_by = ["n DESC".to_string()];
+        let order = SortOrder::new(&sort_by, &schema()).unwrap().unwrap();
+        let sorting = order.sorting_columns().unwrap();
+        assert_eq!(
+            (
+                sorting[0].column_idx,
+                sorting[0].descending,
+                sorting[0].nulls_first
+            ),
+            (1, true, true)
+        );
+        let error = SortOrder::new(&["id".to_string()], &schema()).unwrap_err();
+        assert!(error.to_string().contains("`id`"), "{error}");
+    }
+}
@@ -0,0 +1,40 @@
+///
+/// Where synced rows are read from
+///
+use arrow::datatypes::SchemaRef;
+use arrow::record_batch::RecordBatch;
+use futures::stream::BoxStream;
+use rds_sync::{SourceConnector, StreamOptions, TableRef};
+use std::fmt::Display;
+
+/// A resolved table, or a query whose result is written under the name given by `--table`.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Table(TableRef),
+    Query { name: String, sql: String },
+}
+
+impl Source {
+    /// Streams the rows of the source from `connector` with the columns of `schema`.
+    pub fn stream(
+        &self,
+        connector: &dyn SourceConnector,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, anyhow::Result<RecordBatch>> {
+        match self {
+            Self::Table(table) => connector.stream_table(table, schema, options),
+            Self::Query { sql, .. } => connector.stream_query(sql, schema, options),
+        }
+    }
+}
+
+/// Names the source in messages and in the incremental state file.
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Table(table) => write!(f, "{table}"),
+            Self::Query { name, .. } => write!(f, "query {name}"),
+        }
+    }
+}
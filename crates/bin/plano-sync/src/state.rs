@@ -0,0 +1,133 @@
+///
+/// Persisted high-water mark for incremental syncs
+///
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use object_store::path::Path;
+use rds_sync::Watermark;
+use serde::{Deserialize, Serialize};
+
+use crate::output::Output;
+
+/// Name of the state file kept in the table's output directory.
+pub const STATE_FILE: &str = "_sync_state.json";
+
+/// What the previous incremental run of a table got up to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// The resolved, quoted table the rows were read from.
+    pub table: String,
+    /// The column the watermark was taken from.
+    pub column: String,
+    /// The largest value of `column` written so far.
+    pub watermark: Watermark,
+    /// When the watermark was last advanced.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SyncState {
+    /// Path of the state file for a table written under `table_dir`.
+    pub fn path(table_dir: &Path) -> Path {
+        table_dir.clone().join(STATE_FILE)
+    }
+
+    /// Loads the state left by the previous run, if there was one.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be read or parsed, or if it was written for another
+    /// table or column.
+    pub async fn load(
+        output: &Output,
+        table_dir: &Path,
+        table: &str,
+        column: &str,
+    ) -> Result<Option<Self>> {
+        let path = Self::path(table_dir);
+        let Some(contents) = output.read(&path).await? else {
+            return Ok(None);
+        };
+        let state: Self = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing {}", output.display(&path)))?;
+        if state.table != table || state.column != column {
+            bail!(
+                "{} tracks {}.{} but this run syncs {table}.{column}; remove it to start over",
+                output.display(&path),
+                state.table,
+                state.column
+            );
+        }
+        Ok(Some(state))
+    }
+
+    /// Writes the state next to the table's files.
+    ///
+    /// The file is replaced atomically so that an interrupted run leaves the previous watermark
+    /// intact.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be written.
+    pub async fn save(&self, output: &Output, table_dir: &Path) -> Result<()> {
+        output
+            .write(&Self::path(table_dir), serde_json::to_vec_pretty(self)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn output(dir: &tempfile::TempDir) -> Output {
+        Output::open(dir.path().to_str().unwrap()).unwrap()
+    }
+
+    fn state(watermark: i64) -> SyncState {
+        SyncState {
+            table: r#""public"."events""#.to_string(),
+            column: "id".to_string(),
+            watermark: Watermark::Int(watermark),
+            updated_at: DateTime::from_timestamp(0, 0).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_missing_state() {
+        let dir = tempdir().unwrap();
+        let loaded = SyncState::load(&output(&dir), &Path::default(), "t", "id").await;
+        assert_eq!(loaded.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let dir = tempdir().unwrap();
+        let output = output(&dir);
+        let table_dir = Path::from("events");
+        state(1).save(&output, &table_dir).await.unwrap();
+        state(42).save(&output, &table_dir).await.unwrap();
+        let loaded = SyncState::load(&output, &table_dir, r#""public"."events""#, "id").await;
+        assert_eq!(loaded.unwrap(), Some(state(42)));
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("events"))
+            .unwrap()
+            .collect();
+        assert_eq!(files.len(), 1, "Expected only the state file");
+    }
+
+    #[tokio::test]
+    async fn test_load_rejects_other_column() {
+        let dir = tempdir().unwrap();
+        let output = output(&dir);
+        state(1).save(&output, &Path::default()).await.unwrap();
+        let loaded = SyncState::load(
+            &output,
+            &Path::default(),
+            r#""public"."events""#,
+            "updated_at",
+        )
+        .await;
+        assert!(loaded.is_err());
+    }
+}
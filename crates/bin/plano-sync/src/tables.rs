@@ -0,0 +1,307 @@
+///
+/// Syncing several tables, listed or discovered, in one run
+///
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use rds_sync::{SourceConnector, TableRef};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::config::SyncConfig;
+use crate::mode::check_mode;
+use crate::output::Output;
+use crate::snapshot::SnapshotRecord;
+use crate::{Args, run};
+
+/// Syncs every table given with `--table` or found in `--schema`, with the `--config` settings
+/// for each.
+///
+/// Several tables are read from one exported snapshot where the source can export one, which is
+/// recorded in the output directory once they have been written.  A table that fails does not
+/// stop the others unless `--fail-fast` is set; the run fails once they have all been tried.
+pub async fn sync_tables(
+    args: &Args,
+    output: &Output,
+    connector: &dyn SourceConnector,
+) -> Result<()> {
+    if args.query.is_some() {
+        let query_args = args.for_table(&args.tables[0]);
+        check_mode(&query_args)?;
+        run(&query_args, output, connector, None).await?;
+        return Ok(());
+    }
+
+    let tables = select_tables(args, connector).await?;
+    let config = args
+        .config
+        .as_deref()
+        .map(SyncConfig::load)
+        .transpose()?
+        .unwrap_or_default();
+    config.check_used(&tables)?;
+
+    let names: Vec<String> = tables.iter().map(ToString::to_string).collect();
+    let mut outputs = HashSet::new();
+    let mut runs = Vec::with_capacity(tables.len());
+    for (table, name) in tables.iter().zip(&names) {
+        let mut table_args = Args {
+            tables: names.clone(),
+            ..args.for_table(name)
+        };
+        config.apply(table, &mut table_args);
+        check_mode(&table_args)?;
+        let output = table_args.output_name();
+        if !outputs.insert(output.clone()) {
+            bail!("More than one table would be written to {output}");
+        }
+        runs.push(table_args);
+    }
+
+    if let [table_args] = runs.as_slice() {
+        run(table_args, output, connector, None).await?;
+        return Ok(());
+    }
+
+    let snapshot = connector.export_snapshot().await?;
+    let id = snapshot.as_ref().map(|snapshot| snapshot.id().to_string());
+    if let Some(id) = &id {
+        info!("Reading {} tables from snapshot {id}", runs.len());
+    } else {
+        warn!(
+            "The source cannot export snapshots; {} tables are read one after another",
+            runs.len()
+        );
+    }
+
+    let mut results = Vec::with_capacity(runs.len());
+    for table_args in &runs {
+        let start = Instant::now();
+        let result = run(table_args, output, connector, id.as_deref()).await;
+        if let Err(e) = &result {
+            error!("Failed to sync {}: {e:#}", table_args.table);
+        }
+        let failed = result.is_err();
+        results.push((table_args, result, start.elapsed()));
+        if failed && args.fail_fast {
+            break;
+        }
+    }
+
+    if let Some(snapshot) = snapshot {
+        SnapshotRecord {
+            snapshot: snapshot.id().to_string(),
+            taken_at: snapshot.taken_at(),
+            tables: results
+                .iter()
+                .filter(|(_, result, _)| result.is_ok())
+                .map(|(table_args, _, _)| table_args.output_name())
+                .collect(),
+        }
+        .save(output)
+        .await?;
+        snapshot.release().await?;
+    }
+    report(&runs, &results)
+}
+
+/// The resolved `--table`s followed by the `--schema` tables matching the filters, each once.
+async fn select_tables(args: &Args, connector: &dyn SourceConnector) -> Result<Vec<TableRef>> {
+    let mut tables = Vec::new();
+    // resolve every table before anything is written
+    for table in &args.tables {
+        tables.push(connector.resolve_table(&TableRef::parse(table)?).await?);
+    }
+    if let Some(schema) = &args.schema {
+        let include = patterns(&args.include)?;
+        let exclude = patterns(&args.exclude)?;
+        let found = connector.list_tables(schema, args.views).await?;
+        let found = filter_tables(found, &include, &exclude);
+        if found.is_empty() {
+            warn!("No tables in schema {schema} match --include and --exclude");
+        }
+        tables.extend(found);
+    }
+    let mut seen = HashSet::new();
+    tables.retain(|table| seen.insert(table.to_string()));
+    if tables.is_empty() {
+        bail!("There are no tables to sync");
+    }
+    Ok(tables)
+}
+
+fn patterns(globs: &[String]) -> Result<Vec<Pattern>> {
+    globs
+        .iter()
+        .map(|glob| Pattern::new(glob).with_context(|| format!("Invalid pattern `{glob}`")))
+        .collect()
+}
+
+/// The tables whose names match one of `include` (or any name, if it is empty) and none of
+/// `exclude`.
+fn filter_tables(tables: Vec<TableRef>, include: &[Pattern], exclude: &[Pattern]) -> Vec<TableRef> {
+    tables
+        .into_iter()
+        .filter(|table| {
+            (include.is_empty() || include.iter().any(|p| p.matches(table.name())))
+                && !exclude.iter().any(|p| p.matches(table.name()))
+        })
+        .collect()
+}
+
+/// Logs how each table went and fails if any of them did.
+fn report(runs: &[Args], results: &[(&Args, Result<usize>, Duration)]) -> Result<()> {
+    let mut failed = Vec::new();
+    for (table_args, result, elapsed) in results {
+        let secs = elapsed.as_secs_f64();
+        if let Ok(rows) = result {
+            info!("{}: {rows} rows in {secs:.1}s", table_args.table);
+        } else {
+            info!("{}: failed after {secs:.1}s", table_args.table);
+            failed.push(table_args.table.as_str());
+        }
+    }
+    let skipped = runs.len() - results.len();
+    if skipped > 0 {
+        info!("{skipped} tables were not synced after the first failure");
+    }
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} tables failed: {}",
+            failed.len(),
+            runs.len(),
+            failed.join(", ")
+        );
+    }
+    info!("Synced {} tables", runs.len());
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::mode::WriteMode;
+    use object_store::path::Path as ObjectPath;
+    use rds_sync::SqliteConnector;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_filter_tables() {
+        let tables = [
+            "orders",
+            "orders_archive",
+            "order_items",
+            "users",
+            "tmp_users",
+        ]
+        .map(|name| TableRef::new(Some("public"), name));
+        let names = |include: &[&str], exclude: &[&str]| -> Vec<String> {
+            let include: Vec<String> = include.iter().map(ToString::to_string).collect();
+            let exclude: Vec<String> = exclude.iter().map(ToString::to_string).collect();
+            filter_tables(
+                tables.to_vec(),
+                &patterns(&include).unwrap(),
+                &patterns(&exclude).unwrap(),
+            )
+            .iter()
+            .map(|table| table.name().to_string())
+            .collect()
+        };
+        assert_eq!(names(&[], &[]).len(), 5);
+        assert_eq!(
+            names(&["order*"], &[]),
+            ["orders", "orders_archive", "order_items"]
+        );
+        assert_eq!(
+            names(&["order*"], &["*_archive"]),
+            ["orders", "order_items"]
+        );
+        assert_eq!(
+            names(&[], &["tmp_*", "order?"]),
+            ["orders_archive", "order_items", "users"]
+        );
+        assert!(patterns(&["[".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_schema() {
+        let dir = tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("edge.db").display());
+        let pool = SqlitePoolOptions::new().connect(&url).await.unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, sensor TEXT, value REAL);
+             CREATE TABLE sensors (name TEXT PRIMARY KEY);
+             CREATE TABLE scratch (x);
+             CREATE VIEW busy AS SELECT sensor, count(*) AS n FROM readings GROUP BY sensor;
+             INSERT INTO readings VALUES (1, 'a', 1.5), (2, 'b', 2.5), (3, 'a', 3.5);
+             INSERT INTO sensors VALUES ('a'), ('b');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let config = dir.path().join("sync.toml");
+        fs::write(&config, "[tables.readings]\npartition_by = [\"sensor\"]\n").unwrap();
+
+        let output_dir = dir.path().join("out");
+        let args = Args {
+            schema: Some("main".to_string()),
+            exclude: vec!["scr*".to_string()],
+            views: true,
+            config: Some(config),
+            output_dir: output_dir.display().to_string(),
+            batch_size: 2,
+            workers: 2,
+            ..Default::default()
+        };
+        let connector = SqliteConnector::connect(&url, 3).await.unwrap();
+        let output = Output::open(&args.output_dir).unwrap();
+        sync_tables(&args, &output, &connector).await.unwrap();
+        let partition = ObjectPath::from("readings/sensor=a");
+        assert_eq!(output.list(&partition).await.unwrap().len(), 1);
+        assert!(output_dir.join("sensors.parquet").exists());
+        assert!(output_dir.join("busy.parquet").exists());
+        assert!(!output_dir.join("scratch.parquet").exists());
+
+        let args = Args {
+            tables: vec!["missing".to_string()],
+            ..args
+        };
+        assert!(sync_tables(&args, &output, &connector).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sync_query_checks() {
+        let dir = tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("edge.db").display());
+        let pool = SqlitePoolOptions::new().connect(&url).await.unwrap();
+        sqlx::raw_sql("CREATE TABLE readings (id INTEGER PRIMARY KEY, value REAL);")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let args = Args {
+            tables: vec!["recent".to_string()],
+            query: Some("SELECT id, value FROM readings".to_string()),
+            incremental_col: Some("id".to_string()),
+            mode: Some(WriteMode::Overwrite),
+            output_dir: dir.path().join("out").display().to_string(),
+            batch_size: 10,
+            workers: 1,
+            ..Default::default()
+        };
+        let connector = SqliteConnector::connect(&url, 2).await.unwrap();
+        let output = Output::open(&args.output_dir).unwrap();
+        // overwriting would delete the rows of earlier runs on every incremental one
+        let error = sync_tables(&args, &output, &connector).await.unwrap_err();
+        assert!(error.to_string().contains("need --mode append"), "{error}");
+        assert!(
+            output
+                .list_all(&ObjectPath::from("recent"))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
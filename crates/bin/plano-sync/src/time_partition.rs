@@ -0,0 +1,314 @@
+///
+/// Partition keys derived from the `--timestamp-col`
+///
+use anyhow::{Context, Result, bail};
+use arrow::array::timezone::Tz;
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Date32Type, TimeUnit, TimestampMicrosecondType};
+use chrono::{Datelike, Months, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+use plano_core::partition::decode_value;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A component of the `--timestamp-col` used as a partition key, named as it is in
+/// `--partition-by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeKey {
+    /// `year=2026`
+    Year,
+    /// `quarter=1` to `quarter=4`
+    Quarter,
+    /// `month=01` to `month=12`
+    Month,
+    /// `week=01` to `week=53`: the ISO 8601 week, so the first days of January can fall in
+    /// week 52 or 53 of the year before
+    Week,
+    /// `day=01` to `day=31`
+    Day,
+    /// `hour=00` to `hour=23`
+    Hour,
+    /// `minute=00` to `minute=59`
+    Minute,
+    /// `date=2026-10-17`, the whole date in one key
+    Date,
+}
+
+impl TimeKey {
+    /// The key named `key`, if it is a time key.
+    pub fn parse(key: &str) -> Option<Self> {
+        Some(match key {
+            "year" => Self::Year,
+            "quarter" => Self::Quarter,
+            "month" => Self::Month,
+            "week" => Self::Week,
+            "day" => Self::Day,
+            "hour" => Self::Hour,
+            "minute" => Self::Minute,
+            "date" => Self::Date,
+            _ => return None,
+        })
+    }
+
+    /// The directory value for `time`.
+    pub fn format(self, time: &NaiveDateTime) -> String {
+        match self {
+            Self::Year => time.year().to_string(),
+            Self::Quarter => time.month0().div_euclid(3).saturating_add(1).to_string(),
+            Self::Month => format!("{:02}", time.month()),
+            Self::Week => format!("{:02}", time.iso_week().week()),
+            Self::Day => format!("{:02}", time.day()),
+            Self::Hour => format!("{:02}", time.hour()),
+            Self::Minute => format!("{:02}", time.minute()),
+            Self::Date => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+
+    /// Whether the key needs a time of day, which a date column does not have.
+    const fn needs_time(self) -> bool {
+        matches!(self, Self::Hour | Self::Minute)
+    }
+}
+
+/// Checks that `key` can be taken from the `--timestamp-col` `column` of type `data_type`.
+///
+/// # Errors
+///
+/// Will return `Err` if the column is not a timestamp or date, or is a date and the key needs
+/// a time of day.
+pub fn check_time_column(key: TimeKey, column: &str, data_type: &DataType) -> Result<()> {
+    match data_type {
+        DataType::Timestamp(..) => Ok(()),
+        DataType::Date32 if !key.needs_time() => Ok(()),
+        DataType::Date32 => {
+            bail!("Cannot partition by {key:?} of `{column}`; it is a date without a time of day")
+        }
+        _ => bail!("--timestamp-col `{column}` is a {data_type}, not a timestamp or date"),
+    }
+}
+
+/// Parses the `--partition-tz`: a name from the tz database, such as `Europe/Amsterdam`, or an
+/// offset such as `+02:00`.
+///
+/// # Errors
+///
+/// Will return `Err` if the time zone is not recognised.
+pub fn parse_tz(tz: &str) -> Result<Tz> {
+    Tz::from_str(tz).with_context(|| format!("Unknown time zone `{tz}`"))
+}
+
+/// The local time at which the time partition holding `file`, a path relative to its table's
+/// directory such as `year=2026/month=10/part-00000-....parquet`, ends: the start of the next
+/// year, quarter, month, day, hour or minute, as its keys are.  `None` if the path has no
+/// year or date, or a time key is null or out of range.
+///
+/// Weeks are left out, as ISO weeks do not always fall in the year beside them.
+pub fn partition_end(file: &str) -> Option<NaiveDateTime> {
+    let mut values = HashMap::new();
+    let mut date = None;
+    for part in file.split('/') {
+        let Some((key, value)) = part.split_once('=') else {
+            continue;
+        };
+        let Some(key) = TimeKey::parse(key) else {
+            continue;
+        };
+        let value = decode_value(value)?;
+        if key == TimeKey::Date {
+            date = Some(NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok()?);
+        } else {
+            values.insert(key, value.parse::<u32>().ok()?);
+        }
+    }
+    let value = |key| values.get(&key).copied();
+    let year = value(TimeKey::Year).and_then(|year| i32::try_from(year).ok());
+    let day = match (date, year, value(TimeKey::Month), value(TimeKey::Day)) {
+        (Some(date), ..) => Some(date),
+        (None, Some(year), Some(month), Some(day)) => NaiveDate::from_ymd_opt(year, month, day),
+        _ => None,
+    };
+    if let Some(day) = day {
+        return match (value(TimeKey::Hour), value(TimeKey::Minute)) {
+            (Some(hour), Some(minute)) => day
+                .and_hms_opt(hour, minute, 0)?
+                .checked_add_signed(TimeDelta::minutes(1)),
+            (Some(hour), None) => day
+                .and_hms_opt(hour, 0, 0)?
+                .checked_add_signed(TimeDelta::hours(1)),
+            _ => day.succ_opt()?.and_hms_opt(0, 0, 0),
+        };
+    }
+    let (month, months) = match (value(TimeKey::Month), value(TimeKey::Quarter)) {
+        (Some(month), _) => (month, 1),
+        (None, Some(quarter)) => (quarter.checked_sub(1)?.checked_mul(3)?.checked_add(1)?, 3),
+        (None, None) => (1, 12),
+    };
+    NaiveDate::from_ymd_opt(year?, month, 1)?
+        .checked_add_months(Months::new(months))?
+        .and_hms_opt(0, 0, 0)
+}
+
+/// The local times of `array`, a timestamp or date column, with `None` for nulls.
+///
+/// Timestamps with a time zone are converted to `tz`; those without one are taken as they are,
+/// as are dates, which start at midnight.
+///
+/// # Errors
+///
+/// Will return `Err` if the column is not a timestamp or date, or a value is out of range.
+pub fn local_times(array: &ArrayRef, tz: Tz) -> Result<Vec<Option<NaiveDateTime>>> {
+    let local = match array.data_type() {
+        DataType::Date32 => array
+            .as_primitive::<Date32Type>()
+            .iter()
+            .map(|days| days.and_then(Date32Type::to_naive_date_opt))
+            .map(|date| date.and_then(|date| date.and_hms_opt(0, 0, 0)))
+            .collect(),
+        DataType::Timestamp(_, zone) => {
+            let micros = cast(
+                array,
+                &DataType::Timestamp(TimeUnit::Microsecond, zone.clone()),
+            )?;
+            let micros = micros.as_primitive::<TimestampMicrosecondType>();
+            (0..micros.len())
+                .map(|row| {
+                    if micros.is_null(row) {
+                        None
+                    } else if zone.is_some() {
+                        micros
+                            .value_as_datetime_with_tz(row, tz)
+                            .map(|time| time.naive_local())
+                    } else {
+                        micros.value_as_datetime(row)
+                    }
+                })
+                .collect()
+        }
+        data_type => bail!("Cannot take partition times from a {data_type} column"),
+    };
+    Ok(local)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Date32Array, TimestampMillisecondArray};
+    use chrono::NaiveDate;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_format() {
+        let time = NaiveDate::from_ymd_opt(2027, 1, 1)
+            .unwrap()
+            .and_hms_opt(7, 5, 0)
+            .unwrap();
+        let keys = [
+            "year", "quarter", "month", "week", "day", "hour", "minute", "date",
+        ];
+        let values: Vec<String> = keys
+            .iter()
+            .map(|key| TimeKey::parse(key).unwrap().format(&time))
+            .collect();
+        assert_eq!(
+            values,
+            ["2027", "1", "01", "53", "01", "07", "05", "2027-01-01"]
+        );
+        let time = NaiveDate::from_ymd_opt(2026, 12, 31)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert_eq!(TimeKey::Quarter.format(&time), "4");
+        assert_eq!(TimeKey::parse("region"), None);
+    }
+
+    #[test]
+    fn test_local_times() {
+        // 2026-10-16T23:30:00Z
+        let millis = 1_792_193_400_000;
+        let array: ArrayRef = Arc::new(
+            TimestampMillisecondArray::from(vec![Some(millis), None]).with_timezone("UTC"),
+        );
+        let utc = local_times(&array, parse_tz("UTC").unwrap()).unwrap();
+        assert_eq!(TimeKey::Date.format(&utc[0].unwrap()), "2026-10-16");
+        assert_eq!(utc[1], None);
+        let amsterdam = local_times(&array, parse_tz("Europe/Amsterdam").unwrap()).unwrap();
+        assert_eq!(TimeKey::Date.format(&amsterdam[0].unwrap()), "2026-10-17");
+        assert_eq!(TimeKey::Hour.format(&amsterdam[0].unwrap()), "01");
+        let offset = local_times(&array, parse_tz("-05:00").unwrap()).unwrap();
+        assert_eq!(TimeKey::Hour.format(&offset[0].unwrap()), "18");
+
+        // without a time zone the wall-clock time is kept
+        let naive: ArrayRef = Arc::new(TimestampMillisecondArray::from(vec![millis]));
+        let kept = local_times(&naive, parse_tz("Europe/Amsterdam").unwrap()).unwrap();
+        assert_eq!(TimeKey::Hour.format(&kept[0].unwrap()), "23");
+
+        let dates: ArrayRef = Arc::new(Date32Array::from(vec![None, Some(20_743)]));
+        let dates = local_times(&dates, parse_tz("UTC").unwrap()).unwrap();
+        assert_eq!(dates[0], None);
+        assert_eq!(TimeKey::Date.format(&dates[1].unwrap()), "2026-10-17");
+        assert!(parse_tz("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_partition_end() {
+        let end = |file: &str| partition_end(file).map(|end| end.to_string());
+        let cases = [
+            ("year=2025/part-00000-a.parquet", "2026-01-01 00:00:00"),
+            ("year=2025/quarter=4/part.parquet", "2026-01-01 00:00:00"),
+            (
+                "year=2025/quarter=2/region=eu/part.parquet",
+                "2025-07-01 00:00:00",
+            ),
+            ("year=2025/month=02/part.parquet", "2025-03-01 00:00:00"),
+            (
+                "year=2025/month=02/day=28/part.parquet",
+                "2025-03-01 00:00:00",
+            ),
+            (
+                "year=2025/month=12/day=31/hour=23/p.parquet",
+                "2026-01-01 00:00:00",
+            ),
+            (
+                "year=2025/month=03/day=01/hour=07/minute=05/p",
+                "2025-03-01 07:06:00",
+            ),
+            (
+                "date=2025-03-01/hour=07/part.parquet",
+                "2025-03-01 08:00:00",
+            ),
+            (
+                "region=eu/date=2025-03-01/part.parquet",
+                "2025-03-02 00:00:00",
+            ),
+            // the day is left out without its month
+            (
+                "year=2025/week=09/day=01/part.parquet",
+                "2026-01-01 00:00:00",
+            ),
+        ];
+        for (file, expected) in cases {
+            assert_eq!(end(file).as_deref(), Some(expected), "{file}");
+        }
+        for file in [
+            "part-00000-a.parquet",
+            "region=eu/part.parquet",
+            "month=01/part.parquet",
+            "year=__HIVE_DEFAULT_PARTITION__/part.parquet",
+            "year=2025/month=13/part.parquet",
+            "year=2025/quarter=0/part.parquet",
+            "date=yesterday/part.parquet",
+        ] {
+            assert_eq!(end(file), None, "{file}");
+        }
+    }
+
+    #[test]
+    fn test_check_time_column() {
+        assert!(check_time_column(TimeKey::Day, "d", &DataType::Date32).is_ok());
+        assert!(check_time_column(TimeKey::Hour, "d", &DataType::Date32).is_err());
+        let ts = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+        assert!(check_time_column(TimeKey::Minute, "ts", &ts).is_ok());
+        assert!(check_time_column(TimeKey::Year, "s", &DataType::Utf8).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+/// The bucket `value` is hashed to, out of `buckets`.
+///
+/// Values are hashed as the strings they are written as in partition directories, with 64-bit
+/// FNV-1a, which is stable across versions and platforms.  Nulls are always in bucket 0.
+#[must_use]
+pub fn bucket_of(value: Option<&str>, buckets: u32) -> u32 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    let Some(value) = value else {
+        return 0;
+    };
+    let hash = value.bytes().fold(OFFSET, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+    // the remainder is less than `buckets`
+    u32::try_from(hash % u64::from(buckets.max(1))).unwrap_or_default()
+}
+
+/// The suffix of a bucket's file names: `_NNNNN.parquet`.
+#[must_use]
+pub fn bucket_suffix(bucket: u32) -> String {
+    format!("_{bucket:05}.parquet")
+}
+
+/// The bucket of the file named `name`, or `None` if the name has no bucket suffix.
+#[must_use]
+pub fn file_bucket(name: &str) -> Option<u32> {
+    let (_, bucket) = name.strip_suffix(".parquet")?.rsplit_once('_')?;
+    if bucket.len() != 5 {
+        return None;
+    }
+    bucket.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_of() {
+        // fixed, so that files written by one version are found by another
+        assert_eq!(bucket_of(Some(""), 1000), 37);
+        assert_eq!(bucket_of(Some("a"), 1000), 996);
+        assert_eq!(bucket_of(Some("foobar"), 1000), 968);
+        assert_eq!(bucket_of(None, 16), 0);
+        assert_eq!(bucket_of(Some("anything"), 1), 0);
+        assert!((0..100).all(|i| bucket_of(Some(&i.to_string()), 8) < 8));
+    }
+
+    #[test]
+    fn test_file_bucket() {
+        assert_eq!(
+            file_bucket(&format!("part-00000-0191e2{}", bucket_suffix(7))),
+            Some(7)
+        );
+        assert_eq!(file_bucket("part-00000-0191e2.parquet"), None);
+        assert_eq!(file_bucket("part-00000.parquet"), None);
+        assert_eq!(file_bucket("a_b.parquet"), None);
+        assert_eq!(file_bucket("part_00003.csv"), None);
+    }
+}
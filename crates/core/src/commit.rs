@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// The name of the marker plano-sync writes to a table's directory once a run's files are all
+/// in place.
+pub const COMMIT_MARKER: &str = "_SUCCESS";
+
+/// The contents of a table's commit marker: its data files as of the last run, so that readers
+/// can leave out the files of runs that are still being written, failed or have been replaced.
+///
+/// A marker without contents, as Hadoop and Spark write, commits every file in the directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The number of the commit, counting from 1, or 0 in markers written before commits were
+    /// numbered.
+    #[serde(default)]
+    pub version: u64,
+    /// The run that committed the files.
+    pub run_id: String,
+    /// The data files, relative to the table's directory, in order.
+    pub files: Vec<String>,
+}
@@ -0,0 +1,597 @@
+use anyhow::{Context, Result, bail};
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema, TimeUnit};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::partition::unescape_value;
+
+/// The directory of a table's transaction log.
+pub const LOG_DIR: &str = "_delta_log";
+
+/// The name of the log file of commit `version`.
+#[must_use]
+pub fn commit_file(version: u64) -> String {
+    format!("{version:020}.json")
+}
+
+/// The version of the commit in the log file `name`, or `None` if it is another file, such as
+/// a checkpoint.
+#[must_use]
+pub fn commit_version(name: &str) -> Option<u64> {
+    let version = name.strip_suffix(".json")?;
+    if version.len() != 20 || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    version.parse().ok()
+}
+
+/// One line of a commit, of which exactly one action is set.  Actions that are not used here,
+/// such as `txn`, are skipped when reading.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Action {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_info: Option<CommitInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_data: Option<Metadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove: Option<Remove>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add: Option<Add>,
+}
+
+/// What a commit did, for people reading the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommitInfo {
+    /// When the commit was made, in milliseconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operation_parameters: Option<BTreeMap<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine_info: Option<String>,
+    /// Identifies the run that made the commit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txn_id: Option<String>,
+}
+
+/// The reader and writer versions of the protocol the table needs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Protocol {
+    pub min_reader_version: u32,
+    pub min_writer_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writer_features: Option<Vec<String>>,
+}
+
+impl Protocol {
+    /// The oldest protocol that can hold `schema`: timestamps without a time zone need the
+    /// `timestampNtz` feature of reader version 3 and writer version 7.
+    #[must_use]
+    pub fn for_schema(schema: &Schema) -> Self {
+        if schema
+            .fields()
+            .iter()
+            .any(|field| has_timestamp_ntz(field.data_type()))
+        {
+            let features = Some(vec!["timestampNtz".to_string()]);
+            Self {
+                min_reader_version: 3,
+                min_writer_version: 7,
+                reader_features: features.clone(),
+                writer_features: features,
+            }
+        } else {
+            Self {
+                min_reader_version: 1,
+                min_writer_version: 2,
+                reader_features: None,
+                writer_features: None,
+            }
+        }
+    }
+}
+
+/// The table's schema and partition columns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub id: String,
+    pub format: Format,
+    /// The schema as Delta's JSON, see [`delta_schema`].
+    pub schema_string: String,
+    pub partition_columns: Vec<String>,
+    #[serde(default)]
+    pub configuration: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_time: Option<i64>,
+}
+
+/// The format of the data files, which is always Parquet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Format {
+    pub provider: String,
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            provider: "parquet".to_string(),
+            options: BTreeMap::new(),
+        }
+    }
+}
+
+/// A file added to the table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Add {
+    /// The file, relative to the table's directory and URI-encoded, see [`encode_path`].
+    pub path: String,
+    /// The values of the partition columns, unescaped, with `None` for null.
+    pub partition_values: BTreeMap<String, Option<String>>,
+    pub size: u64,
+    /// When the file was written, in milliseconds since the epoch.
+    pub modification_time: i64,
+    pub data_change: bool,
+}
+
+/// A file removed from the table, which readers of later versions leave out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Remove {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deletion_timestamp: Option<i64>,
+    pub data_change: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extended_file_metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition_values: Option<BTreeMap<String, Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl Remove {
+    /// Removes the file `add` added.
+    #[must_use]
+    pub fn of(add: &Add, deletion_timestamp: i64) -> Self {
+        Self {
+            path: add.path.clone(),
+            deletion_timestamp: Some(deletion_timestamp),
+            data_change: true,
+            extended_file_metadata: Some(true),
+            partition_values: Some(add.partition_values.clone()),
+            size: Some(add.size),
+        }
+    }
+}
+
+/// Parses a commit file, which has one action per line.
+///
+/// # Errors
+///
+/// Will return `Err` if a line is not an action.
+pub fn parse_commit(contents: &[u8]) -> Result<Vec<Action>> {
+    contents
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|line| serde_json::from_slice(line).context("parsing a Delta log action"))
+        .collect()
+}
+
+/// The contents of a commit file of `actions`.
+///
+/// # Errors
+///
+/// Will return `Err` if an action cannot be serialized.
+pub fn format_commit(actions: &[Action]) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    for action in actions {
+        serde_json::to_writer(&mut contents, action)?;
+        contents.push(b'\n');
+    }
+    Ok(contents)
+}
+
+/// The state of a table after replaying its log up to a version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The last commit applied, or `None` before the first.
+    pub version: Option<u64>,
+    pub protocol: Option<Protocol>,
+    pub metadata: Option<Metadata>,
+    /// The table's files, keyed by their decoded path.
+    pub files: BTreeMap<String, Add>,
+    /// The files removed from the table, which may still be read by older versions.
+    pub removed: BTreeMap<String, Remove>,
+}
+
+impl Snapshot {
+    /// Applies the actions of commit `version`.
+    pub fn apply(&mut self, version: u64, actions: Vec<Action>) {
+        for action in actions {
+            if let Some(protocol) = action.protocol {
+                self.protocol = Some(protocol);
+            }
+            if let Some(metadata) = action.meta_data {
+                self.metadata = Some(metadata);
+            }
+            if let Some(remove) = action.remove {
+                let path = decode_path(&remove.path);
+                self.files.remove(&path);
+                self.removed.insert(path, remove);
+            }
+            if let Some(add) = action.add {
+                let path = decode_path(&add.path);
+                self.removed.remove(&path);
+                self.files.insert(path, add);
+            }
+        }
+        self.version = Some(version);
+    }
+
+    /// Checks that the table's files can be read as they are: tables whose protocol needs
+    /// features such as deletion vectors or column mapping cannot.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` naming what the table needs that is not supported.
+    pub fn check_readable(&self) -> Result<()> {
+        if let Some(protocol) = &self.protocol {
+            if protocol.min_reader_version > 3 {
+                bail!(
+                    "Delta reader version {} is not supported",
+                    protocol.min_reader_version
+                );
+            }
+            let mut features = protocol.reader_features.iter().flatten();
+            if let Some(feature) = features.find(|feature| *feature != "timestampNtz") {
+                bail!("Delta reader feature `{feature}` is not supported");
+            }
+        }
+        let mapping = self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.configuration.get("delta.columnMapping.mode"));
+        if let Some(mode) = mapping.filter(|mode| *mode != "none") {
+            bail!("Delta column mapping (`{mode}`) is not supported");
+        }
+        Ok(())
+    }
+}
+
+/// `path` URI-encoded, as the log records it, so that the `%` of an escaped partition value
+/// is written `%25`.
+#[must_use]
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/=".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            // cannot fail when writing to a String
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+/// Reverses [`encode_path`].
+#[must_use]
+pub fn decode_path(path: &str) -> String {
+    unescape_value(path)
+}
+
+/// `schema` as the JSON of a Delta `schemaString`.
+///
+/// Unsigned integers become the signed type that holds them, as Spark reads them from Parquet.
+///
+/// # Errors
+///
+/// Will return `Err` if a column's type has no Delta equivalent, such as a time of day.
+pub fn delta_schema(schema: &Schema) -> Result<String> {
+    Ok(struct_type(schema.fields())?.to_string())
+}
+
+fn struct_type(fields: &Fields) -> Result<Value> {
+    let fields = fields
+        .iter()
+        .map(|field| {
+            Ok(json!({
+                "name": field.name(),
+                "type": delta_type(field.name(), field.data_type())?,
+                "nullable": field.is_nullable(),
+                "metadata": {},
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({"type": "struct", "fields": fields}))
+}
+
+fn delta_type(column: &str, data_type: &DataType) -> Result<Value> {
+    let name = match data_type {
+        DataType::Boolean => "boolean",
+        DataType::Int8 => "byte",
+        DataType::Int16 | DataType::UInt8 => "short",
+        DataType::Int32 | DataType::UInt16 => "integer",
+        DataType::Int64 | DataType::UInt32 => "long",
+        DataType::UInt64 => "decimal(20,0)",
+        DataType::Float32 => "float",
+        DataType::Float64 => "double",
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => "string",
+        DataType::Binary
+        | DataType::LargeBinary
+        | DataType::BinaryView
+        | DataType::FixedSizeBinary(_) => "binary",
+        DataType::Date32 | DataType::Date64 => "date",
+        DataType::Timestamp(_, Some(_)) => "timestamp",
+        DataType::Timestamp(_, None) => "timestamp_ntz",
+        DataType::Decimal128(precision, scale) | DataType::Decimal256(precision, scale)
+            if *precision <= 38 && *scale >= 0 =>
+        {
+            return Ok(json!(format!("decimal({precision},{scale})")));
+        }
+        DataType::List(element) | DataType::LargeList(element) => {
+            return Ok(json!({
+                "type": "array",
+                "elementType": delta_type(column, element.data_type())?,
+                "containsNull": element.is_nullable(),
+            }));
+        }
+        DataType::Struct(fields) => return struct_type(fields),
+        DataType::Map(entries, _) => {
+            let DataType::Struct(fields) = entries.data_type() else {
+                bail!("Map column `{column}` has no key and value");
+            };
+            let [key, value] = fields.iter().collect::<Vec<_>>()[..] else {
+                bail!("Map column `{column}` has no key and value");
+            };
+            return Ok(json!({
+                "type": "map",
+                "keyType": delta_type(column, key.data_type())?,
+                "valueType": delta_type(column, value.data_type())?,
+                "valueContainsNull": value.is_nullable(),
+            }));
+        }
+        data_type => bail!(
+            "Column `{column}` is a {data_type}, which a Delta table cannot hold; leave it out or cast it"
+        ),
+    };
+    Ok(json!(name))
+}
+
+fn has_timestamp_ntz(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Timestamp(_, None) => true,
+        DataType::List(field) | DataType::LargeList(field) | DataType::Map(field, _) => {
+            has_timestamp_ntz(field.data_type())
+        }
+        DataType::Struct(fields) => fields
+            .iter()
+            .any(|field| has_timestamp_ntz(field.data_type())),
+        _ => false,
+    }
+}
+
+/// The Arrow schema of a Delta `schemaString`.  Timestamps are read as microseconds, in UTC
+/// unless they are `timestamp_ntz`.
+///
+/// # Errors
+///
+/// Will return `Err` if the schema is not valid JSON or has a type that is not known.
+pub fn arrow_schema(schema_string: &str) -> Result<Schema> {
+    let value: Value = serde_json::from_str(schema_string).context("parsing a Delta schema")?;
+    Ok(Schema::new(arrow_fields(&value)?))
+}
+
+fn arrow_fields(value: &Value) -> Result<Fields> {
+    let Some(fields) = value.get("fields").and_then(Value::as_array) else {
+        bail!("Delta struct type without fields: {value}");
+    };
+    fields
+        .iter()
+        .map(|field| {
+            let Some(name) = field.get("name").and_then(Value::as_str) else {
+                bail!("Delta field without a name: {field}");
+            };
+            let data_type = arrow_type(field.get("type").unwrap_or(&Value::Null))?;
+            let nullable = field.get("nullable").and_then(Value::as_bool) != Some(false);
+            Ok(Arc::new(Field::new(name, data_type, nullable)))
+        })
+        .collect::<Result<Vec<FieldRef>>>()
+        .map(Fields::from)
+}
+
+fn arrow_type(value: &Value) -> Result<DataType> {
+    if let Some(name) = value.as_str() {
+        return Ok(match name {
+            "boolean" => DataType::Boolean,
+            "byte" => DataType::Int8,
+            "short" => DataType::Int16,
+            "integer" => DataType::Int32,
+            "long" => DataType::Int64,
+            "float" => DataType::Float32,
+            "double" => DataType::Float64,
+            "string" => DataType::Utf8,
+            "binary" => DataType::Binary,
+            "date" => DataType::Date32,
+            "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            "timestamp_ntz" => DataType::Timestamp(TimeUnit::Microsecond, None),
+            decimal => {
+                let Some((precision, scale)) = decimal
+                    .strip_prefix("decimal(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .and_then(|rest| rest.split_once(','))
+                else {
+                    bail!("Unknown Delta type `{name}`");
+                };
+                DataType::Decimal128(precision.trim().parse()?, scale.trim().parse()?)
+            }
+        });
+    }
+    let object = value.as_object().map_or_else(Map::new, Clone::clone);
+    let nullable = |key: &str| object.get(key).and_then(Value::as_bool) != Some(false);
+    let child = |key: &str| arrow_type(object.get(key).unwrap_or(&Value::Null));
+    Ok(match object.get("type").and_then(Value::as_str) {
+        Some("array") => DataType::List(Arc::new(Field::new_list_field(
+            child("elementType")?,
+            nullable("containsNull"),
+        ))),
+        Some("struct") => DataType::Struct(arrow_fields(value)?),
+        Some("map") => {
+            let entries = Fields::from(vec![
+                Field::new("key", child("keyType")?, false),
+                Field::new("value", child("valueType")?, nullable("valueContainsNull")),
+            ]);
+            DataType::Map(
+                Arc::new(Field::new("entries", DataType::Struct(entries), false)),
+                false,
+            )
+        }
+        _ => bail!("Unknown Delta type {value}"),
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_file() {
+        assert_eq!(commit_file(12), "00000000000000000012.json");
+        assert_eq!(commit_version("00000000000000000012.json"), Some(12));
+        assert_eq!(
+            commit_version("00000000000000000010.checkpoint.parquet"),
+            None
+        );
+        assert_eq!(commit_version("_last_checkpoint"), None);
+        assert_eq!(commit_version("0000000000000000001x.json"), None);
+    }
+
+    #[test]
+    fn test_replay() {
+        // as written by other Delta writers, with actions that are not used here
+        let first = br#"{"commitInfo":{"timestamp":1792195200000,"operation":"WRITE"}}
+{"protocol":{"minReaderVersion":1,"minWriterVersion":2}}
+{"metaData":{"id":"t","format":{"provider":"parquet","options":{}},"schemaString":"{\"type\":\"struct\",\"fields\":[]}","partitionColumns":["region"],"configuration":{},"createdTime":1792195200000}}
+{"add":{"path":"region=eu%252Fwest/a.parquet","partitionValues":{"region":"eu/west"},"size":10,"modificationTime":1,"dataChange":true,"stats":"{}"}}
+{"add":{"path":"region=__HIVE_DEFAULT_PARTITION__/b.parquet","partitionValues":{"region":null},"size":20,"modificationTime":1,"dataChange":true}}
+"#;
+        let second = br#"{"txn":{"appId":"x","version":1}}
+{"remove":{"path":"region=eu%252Fwest/a.parquet","deletionTimestamp":2,"dataChange":true}}
+"#;
+        let mut snapshot = Snapshot::default();
+        snapshot.apply(0, parse_commit(first).unwrap());
+        assert_eq!(
+            snapshot.files.keys().collect::<Vec<_>>(),
+            [
+                "region=__HIVE_DEFAULT_PARTITION__/b.parquet",
+                "region=eu%2Fwest/a.parquet"
+            ]
+        );
+        assert_eq!(
+            snapshot.metadata.as_ref().unwrap().partition_columns,
+            ["region"]
+        );
+        snapshot.apply(1, parse_commit(second).unwrap());
+        assert_eq!(snapshot.version, Some(1));
+        assert_eq!(snapshot.files.len(), 1);
+        assert_eq!(snapshot.files.values().next().unwrap().size, 20);
+        assert_eq!(
+            snapshot.removed["region=eu%2Fwest/a.parquet"].deletion_timestamp,
+            Some(2)
+        );
+        snapshot.check_readable().unwrap();
+        snapshot.protocol = Some(Protocol {
+            min_reader_version: 3,
+            min_writer_version: 7,
+            reader_features: Some(vec!["deletionVectors".to_string()]),
+            writer_features: None,
+        });
+        assert!(snapshot.check_readable().is_err());
+
+        let actions = parse_commit(first).unwrap();
+        let round_trip = parse_commit(&format_commit(&actions).unwrap()).unwrap();
+        assert_eq!(round_trip, actions);
+        assert!(parse_commit(b"{\"add\":").is_err());
+    }
+
+    #[test]
+    fn test_encode_path() {
+        let path = "region=eu%2Fwest/name=caf\u{e9} 1/part-00000.parquet";
+        let encoded = encode_path(path);
+        assert_eq!(
+            encoded,
+            "region=eu%252Fwest/name=caf%C3%A9%201/part-00000.parquet"
+        );
+        assert_eq!(decode_path(&encoded), path);
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("price", DataType::Decimal128(10, 2), true),
+            Field::new(
+                "at",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+                true,
+            ),
+            Field::new("day", DataType::Date32, true),
+            Field::new("tags", DataType::new_list(DataType::Utf8, true), true),
+            Field::new("_lsn", DataType::UInt64, false),
+        ]);
+        let delta = delta_schema(&schema).unwrap();
+        assert!(
+            delta.contains(
+                r#"{"metadata":{},"name":"price","nullable":true,"type":"decimal(10,2)"}"#
+            ),
+            "{delta}"
+        );
+        assert_eq!(Protocol::for_schema(&schema).min_reader_version, 1);
+
+        let read = arrow_schema(&delta).unwrap();
+        assert_eq!(read.field(0), schema.field(0));
+        assert_eq!(read.field(2), schema.field(2));
+        assert_eq!(
+            read.field(3).data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        );
+        assert_eq!(read.field(5), schema.field(5));
+        assert_eq!(read.field(6).data_type(), &DataType::Decimal128(20, 0));
+
+        let local = Schema::new(vec![Field::new(
+            "at",
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            true,
+        )]);
+        let protocol = Protocol::for_schema(&local);
+        assert_eq!(protocol.min_writer_version, 7);
+        assert_eq!(protocol.reader_features.unwrap(), ["timestampNtz"]);
+        let time = Schema::new(vec![Field::new(
+            "t",
+            DataType::Time64(TimeUnit::Microsecond),
+            true,
+        )]);
+        assert!(delta_schema(&time).is_err());
+        assert!(
+            arrow_schema(r#"{"type":"struct","fields":[{"name":"x","type":"interval"}]}"#).is_err()
+        );
+    }
+}
@@ -1,3 +1,15 @@
+/// Hash buckets that split a partition into a fixed number of files by one column, so that
+/// plano-serv can read only the file an equality filter on that column can match.
+pub mod bucket;
+/// The `_SUCCESS` marker through which plano-sync commits a run's files, and plano-serv finds
+/// the committed ones.
+pub mod commit;
+/// The Delta Lake transaction log plano-sync can keep for a table and plano-serv read its files
+/// from: commit actions, replaying them into a snapshot, and Delta's schema types.
+pub mod delta;
 /// Core functionality for the application.
 /// Format module for handling output of record batches in different formats.
 pub mod format;
+/// Hive-style partition directory values, escaped so that a `/` or `=` in a value cannot
+/// change the layout, shared by plano-sync, which writes them, and plano-serv, which reads them.
+pub mod partition;
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+/// The directory name Hive and Spark use for a null partition value.
+pub const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Whether Hive escapes `c` in partition values.
+const fn needs_escape(c: char) -> bool {
+    matches!(
+        c,
+        '\u{01}'
+            ..='\u{1F}'
+                | '"'
+                | '#'
+                | '%'
+                | '\''
+                | '*'
+                | '/'
+                | ':'
+                | '='
+                | '?'
+                | '\\'
+                | '\u{7F}'
+                | '{'
+                | '['
+                | ']'
+                | '^'
+    )
+}
+
+/// Escapes `value` for use in a partition directory name, replacing each special character
+/// with `%XX`.
+#[must_use]
+pub fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if needs_escape(c) {
+            // cannot fail when writing to a String
+            let _ = write!(escaped, "%{:02X}", u32::from(c));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape_value`].  A `%` that does not start an escape is kept as it is.
+#[must_use]
+pub fn unescape_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(byte) = escape {
+            unescaped.push(byte);
+            i += 3;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+    // only ASCII is escaped, so the result is as valid as the input
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// The directory value for a partition value, `None` being null.
+#[must_use]
+pub fn encode_value(value: Option<&str>) -> String {
+    value.map_or_else(|| DEFAULT_PARTITION.to_string(), escape_value)
+}
+
+/// The partition value of a directory value, `None` being null.
+#[must_use]
+pub fn decode_value(value: &str) -> Option<String> {
+    (value != DEFAULT_PARTITION).then(|| unescape_value(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_value() {
+        assert_eq!(escape_value("plain value-1.5"), "plain value-1.5");
+        assert_eq!(escape_value("a/b=c"), "a%2Fb%3Dc");
+        assert_eq!(escape_value("100%"), "100%25");
+        assert_eq!(escape_value("12:30"), "12%3A30");
+        assert_eq!(escape_value("tab\there"), "tab%09here");
+        assert_eq!(escape_value("café"), "café");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for value in [
+            "",
+            "a/b=c",
+            "100%",
+            "%41",
+            "x%",
+            "%zz",
+            "[1, 2]",
+            "café/☕",
+            "'\"#?*",
+        ] {
+            assert_eq!(unescape_value(&escape_value(value)), value);
+            assert_eq!(
+                decode_value(&encode_value(Some(value))).as_deref(),
+                Some(value)
+            );
+        }
+        assert_eq!(encode_value(None), DEFAULT_PARTITION);
+        assert_eq!(decode_value(DEFAULT_PARTITION), None);
+    }
+
+    #[test]
+    fn test_unescape_value() {
+        assert_eq!(unescape_value("a%2fb"), "a/b");
+        assert_eq!(unescape_value("50%"), "50%");
+        assert_eq!(unescape_value("%G1"), "%G1");
+    }
+}
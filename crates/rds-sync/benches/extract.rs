@@ -0,0 +1,93 @@
+///
+/// Extraction throughput of row-by-row decoding against binary `COPY`
+///
+/// Needs a Postgres database in `DATABASE_URL`, where it creates (and then drops) a wide table
+/// of `BENCH_ROWS` rows (default 500000):
+///
+/// ```sh
+/// DATABASE_URL=postgres://localhost/plano_dev cargo bench -p rds-sync --bench extract
+/// ```
+///
+use anyhow::Result;
+use futures::TryStreamExt;
+use rds_sync::{StreamOptions, TableRef, infer_arrow_schema, stream_table};
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+
+const TABLE: &str = "plano_bench_extract";
+const RUNS: usize = 3;
+
+fn main() -> Result<()> {
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        println!("DATABASE_URL is not set; skipping the extraction benchmark");
+        return Ok(());
+    };
+    let rows: u64 = std::env::var("BENCH_ROWS").map_or(Ok(500_000), |rows| rows.parse())?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let pool = PgPool::connect(&url).await?;
+        create_table(&pool, rows).await?;
+        let result = compare(&pool, rows).await;
+        sqlx::query(&format!("DROP TABLE {TABLE}"))
+            .execute(&pool)
+            .await?;
+        result
+    })
+}
+
+async fn create_table(pool: &PgPool, rows: u64) -> Result<()> {
+    sqlx::query(&format!("DROP TABLE IF EXISTS {TABLE}"))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!(
+        "CREATE UNLOGGED TABLE {TABLE} AS
+         SELECT g::int8 AS id, g::int4 AS i4, (g % 100)::int2 AS i2,
+                g * 1.5::float8 AS f8, (g / 7.0)::float4 AS f4,
+                (g % 100000)::numeric(12, 2) AS amount, g % 2 = 0 AS flag,
+                'name ' || g AS name, md5(g::text) AS hash,
+                timestamp '2026-01-01' + g * interval '1 second' AS ts,
+                timestamptz '2026-01-01' + g * interval '1 second' AS tstz,
+                date '2026-01-01' + (g % 365) AS day,
+                ARRAY[g, g + 1, g + 2]::int8[] AS ids
+         FROM generate_series(1, {rows}) g"
+    ))
+    .execute(pool)
+    .await?;
+    sqlx::query(&format!("VACUUM ANALYZE {TABLE}"))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn compare(pool: &PgPool, rows: u64) -> Result<()> {
+    let table = TableRef::new(None, TABLE);
+    let schema = infer_arrow_schema(&table, pool).await?;
+    println!(
+        "Extracting {rows} rows of {} columns, best of {RUNS} runs",
+        schema.fields().len()
+    );
+    for (name, options) in [
+        ("rows", StreamOptions::default()),
+        ("copy binary", StreamOptions::default().with_copy()),
+    ] {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let read: usize = stream_table(&table, schema.clone(), pool, &options)
+                .try_fold(0, |read, batch| async move { Ok(read + batch.num_rows()) })
+                .await?;
+            best = best.min(start.elapsed());
+            assert_eq!(
+                u64::try_from(read)?,
+                rows,
+                "{name} read the wrong number of rows"
+            );
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let throughput = rows as f64 / best.as_secs_f64();
+        println!(
+            "{name:>12}: {:>8.3}s {throughput:>12.0} rows/s",
+            best.as_secs_f64()
+        );
+    }
+    Ok(())
+}
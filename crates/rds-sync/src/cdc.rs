@@ -0,0 +1,540 @@
+///
+/// Change-data-capture from a logical replication slot
+///
+use crate::pgoutput::{Message, Relation, TupleValue};
+use crate::table::{TableRef, quote_ident};
+use crate::text::text_to_array;
+use anyhow::{Result, bail};
+use arrow::{
+    array::{ArrayRef, RecordBatch, StringArray, TimestampMicrosecondArray, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+};
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Column holding the kind of change: `insert`, `update`, `delete` or `truncate`.
+pub const OP_COLUMN: &str = "_op";
+/// Column holding the LSN of the change, which orders the changes of a transaction.
+///
+/// The changes of concurrent transactions interleave in the WAL, so it does not order
+/// transactions: those are written in the order they committed, which `_commit_ts` records.
+pub const LSN_COLUMN: &str = "_lsn";
+/// Column holding the commit time of the change's transaction.
+pub const COMMIT_TS_COLUMN: &str = "_commit_ts";
+
+/// A position in the write-ahead log, written `XXX/XXX` like Postgres' `pg_lsn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Lsn(pub u64);
+
+impl FromStr for Lsn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((high, low)) = s.split_once('/') else {
+            bail!("Invalid LSN `{s}`");
+        };
+        let high = u64::from_str_radix(high, 16)?;
+        let low = u64::from_str_radix(low, 16)?;
+        if high > u64::from(u32::MAX) || low > u64::from(u32::MAX) {
+            bail!("Invalid LSN `{s}`");
+        }
+        Ok(Self((high << 32) | low))
+    }
+}
+
+impl Display for Lsn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & u64::from(u32::MAX))
+    }
+}
+
+/// The schema of a change log: the change metadata columns followed by every column of the
+/// table.  Table columns are nullable since deletes only carry the replica identity.
+///
+/// # Errors
+///
+/// Will return `Err` if the table already has a column named like a metadata column.
+pub fn change_schema(table_schema: &Schema) -> Result<SchemaRef> {
+    let mut fields = vec![
+        Field::new(OP_COLUMN, DataType::Utf8, false),
+        Field::new(LSN_COLUMN, DataType::UInt64, false),
+        Field::new(
+            COMMIT_TS_COLUMN,
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+    ];
+    for field in table_schema.fields() {
+        if fields.iter().any(|f| f.name() == field.name()) {
+            bail!("Column {} clashes with a change log column", field.name());
+        }
+        fields.push(field.as_ref().clone().with_nullable(true));
+    }
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Changes to one table, read from a logical replication slot using the `pgoutput` plugin.
+///
+/// Reading does not consume the changes: call [`ChangeFeed::advance`] once they are safely
+/// stored, so that an interrupted run reads them again rather than losing them.
+#[derive(Debug, Clone)]
+pub struct ChangeFeed {
+    slot: String,
+    publication: String,
+    table: TableRef,
+}
+
+impl ChangeFeed {
+    /// `table` should already be resolved (see [`crate::resolve_table`]).
+    #[must_use]
+    pub fn new(slot: &str, publication: &str, table: TableRef) -> Self {
+        Self {
+            slot: slot.to_string(),
+            publication: publication.to_string(),
+            table,
+        }
+    }
+
+    /// Creates the publication and the replication slot if they do not exist yet.
+    ///
+    /// The slot only sees changes made after it was created, so the first run of a feed is
+    /// usually preceded by a full sync of the table.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the publication does not include the table, the table's replica
+    /// identity does not carry every change, the slot uses another plugin or the server is not
+    /// configured with `wal_level = logical`.
+    pub async fn ensure(&self, pool: &PgPool) -> Result<()> {
+        let publication: Option<bool> = sqlx::query_scalar(
+            r"
+            SELECT EXISTS (
+                SELECT 1 FROM pg_publication_tables
+                WHERE pubname = $1 AND schemaname = $2 AND tablename = $3
+            )
+            FROM pg_publication WHERE pubname = $1
+            ",
+        )
+        .bind(&self.publication)
+        .bind(self.table.schema())
+        .bind(self.table.name())
+        .fetch_optional(pool)
+        .await?;
+        self.check_replica_identity(pool).await?;
+        match publication {
+            None => {
+                let create = format!(
+                    "CREATE PUBLICATION {} FOR TABLE {}",
+                    quote_ident(&self.publication),
+                    self.table
+                );
+                sqlx::query(&create).execute(pool).await?;
+            }
+            Some(false) => bail!(
+                "Publication {} does not include {}; add it with ALTER PUBLICATION ... ADD TABLE",
+                self.publication,
+                self.table
+            ),
+            Some(true) => {}
+        }
+
+        let plugin: Option<String> = sqlx::query_scalar(
+            "SELECT plugin::text FROM pg_replication_slots WHERE slot_name = $1",
+        )
+        .bind(&self.slot)
+        .fetch_optional(pool)
+        .await?;
+        match plugin.as_deref() {
+            None => {
+                sqlx::query("SELECT pg_create_logical_replication_slot($1, 'pgoutput')")
+                    .bind(&self.slot)
+                    .execute(pool)
+                    .await?;
+            }
+            Some("pgoutput") => {}
+            Some(other) => bail!(
+                "Replication slot {} uses the {other} plugin; plano-sync needs pgoutput",
+                self.slot
+            ),
+        }
+        Ok(())
+    }
+
+    /// Refuses to publish a table whose updates and deletes Postgres could not replicate, since
+    /// it would then reject them outright.
+    async fn check_replica_identity(&self, pool: &PgPool) -> Result<()> {
+        let (identity, has_primary_key): (String, bool) = sqlx::query_as(
+            r"
+            SELECT c.relreplident::text,
+                   EXISTS (SELECT 1 FROM pg_index i WHERE i.indrelid = c.oid AND i.indisprimary)
+            FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = $1 AND c.relname = $2
+            ",
+        )
+        .bind(self.table.schema())
+        .bind(self.table.name())
+        .fetch_one(pool)
+        .await?;
+        if identity == "n" || (identity == "d" && !has_primary_key) {
+            bail!(
+                "{} has no replica identity, so publishing it would make updates and deletes \
+                 fail; add a primary key or run ALTER TABLE {} REPLICA IDENTITY FULL",
+                self.table,
+                self.table
+            );
+        }
+        Ok(())
+    }
+
+    /// The position up to which WAL can currently be decoded.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the query fails.
+    pub async fn current_lsn(&self, pool: &PgPool) -> Result<Lsn> {
+        let lsn: String = sqlx::query_scalar("SELECT pg_current_wal_flush_lsn()::text")
+            .fetch_one(pool)
+            .await?;
+        lsn.parse()
+    }
+
+    /// Streams the table's changes committed before `upto` as batches following `schema`,
+    /// which must come from [`change_schema`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    #[must_use]
+    pub fn stream(
+        &self,
+        schema: SchemaRef,
+        pool: &PgPool,
+        upto: Lsn,
+        batch_size: usize,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        assert!(batch_size > 0, "batch_size must be greater than zero");
+        let feed = self.clone();
+        let pool = pool.clone();
+        let (tx, rx) = mpsc::channel::<Result<RecordBatch>>(1);
+
+        tokio::spawn(async move {
+            let query = r"
+                SELECT lsn::text AS lsn, data
+                FROM pg_logical_slot_peek_binary_changes(
+                    $1, $2::pg_lsn, NULL,
+                    'proto_version', '1', 'publication_names', $3)
+            ";
+            let publication_names = quote_ident(&feed.publication);
+            let mut rows = sqlx::query(query)
+                .bind(&feed.slot)
+                .bind(upto.to_string())
+                .bind(&publication_names)
+                .fetch(&pool)
+                .map_err(anyhow::Error::from);
+            let mut changes = Changes::new(&feed.table, schema);
+
+            loop {
+                let batch = match rows.try_next().await {
+                    Ok(Some(row)) => match changes.decode(row.get("lsn"), row.get("data")) {
+                        Ok(()) if changes.len() < batch_size => continue,
+                        Ok(()) => changes.finish(),
+                        Err(e) => Err(e),
+                    },
+                    Ok(None) => {
+                        if changes.len() > 0 {
+                            let _ = tx.send(changes.finish()).await;
+                        }
+                        return;
+                    }
+                    Err(e) => Err(e),
+                };
+                let failed = batch.is_err();
+                // stop when the receiver is gone or after reporting the first error
+                if tx.send(batch).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        })
+        .boxed()
+    }
+
+    /// Marks every change before `upto` as consumed, so the slot stops retaining its WAL.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the slot cannot be advanced.
+    pub async fn advance(&self, pool: &PgPool, upto: Lsn) -> Result<()> {
+        sqlx::query(
+            r"
+            SELECT pg_replication_slot_advance(slot_name, $2::pg_lsn)
+            FROM pg_replication_slots
+            WHERE slot_name = $1 AND confirmed_flush_lsn < $2::pg_lsn
+            ",
+        )
+        .bind(&self.slot)
+        .bind(upto.to_string())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Accumulates decoded changes until there are enough for a batch.
+struct Changes {
+    table: TableRef,
+    schema: SchemaRef,
+    /// For each relation of the table, the schema position of each replicated column.
+    relations: HashMap<u32, Vec<Option<usize>>>,
+    commit_ts: i64,
+    ops: Vec<&'static str>,
+    lsns: Vec<u64>,
+    commit_timestamps: Vec<i64>,
+    /// Column-major values of the table columns, which start at position 3 of the schema.
+    values: Vec<Vec<Option<String>>>,
+}
+
+impl Changes {
+    fn new(table: &TableRef, schema: SchemaRef) -> Self {
+        let columns = schema.fields().len() - 3;
+        Self {
+            table: table.clone(),
+            schema,
+            relations: HashMap::new(),
+            commit_ts: 0,
+            ops: Vec::new(),
+            lsns: Vec::new(),
+            commit_timestamps: Vec::new(),
+            values: vec![Vec::new(); columns],
+        }
+    }
+
+    const fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    fn decode(&mut self, lsn: &str, data: &[u8]) -> Result<()> {
+        let lsn: Lsn = lsn.parse()?;
+        match Message::parse(data)? {
+            Message::Begin { commit_ts } => self.commit_ts = commit_ts,
+            Message::Relation(relation) => self.add_relation(relation),
+            Message::Insert { relation, new } => self.push("insert", lsn, relation, &new),
+            Message::Update { relation, old, new } => {
+                let new = self.fill_unchanged(lsn, relation, old.as_deref(), new)?;
+                self.push("update", lsn, relation, &new);
+            }
+            Message::Delete { relation, key } => self.push("delete", lsn, relation, &key),
+            Message::Truncate { relations } => {
+                for relation in relations {
+                    self.push("truncate", lsn, relation, &[]);
+                }
+            }
+            Message::Commit | Message::Other => {}
+        }
+        Ok(())
+    }
+
+    fn add_relation(&mut self, relation: Relation) {
+        if Some(relation.namespace.as_str()) != self.table.schema()
+            || relation.name != self.table.name()
+        {
+            return;
+        }
+        let positions = relation
+            .columns
+            .iter()
+            .map(|column| {
+                self.schema
+                    .fields()
+                    .iter()
+                    .skip(3)
+                    .position(|f| f.name() == column)
+            })
+            .collect();
+        self.relations.insert(relation.id, positions);
+    }
+
+    /// The `new` row of an update, with the out-of-line values it left unchanged, which are not
+    /// sent, taken from the `old` row.  That is only sent in full with `REPLICA IDENTITY FULL`.
+    fn fill_unchanged(
+        &self,
+        lsn: Lsn,
+        relation: u32,
+        old: Option<&[TupleValue]>,
+        mut new: Vec<TupleValue>,
+    ) -> Result<Vec<TupleValue>> {
+        if !self.relations.contains_key(&relation) {
+            return Ok(new);
+        }
+        for (i, value) in new.iter_mut().enumerate() {
+            if *value != TupleValue::Unchanged {
+                continue;
+            }
+            match old.and_then(|old| old.get(i)) {
+                Some(old @ TupleValue::Text(_)) => *value = old.clone(),
+                _ => bail!(
+                    "The update of {} at {lsn} leaves out a large value it did not change; \
+                     run ALTER TABLE {} REPLICA IDENTITY FULL so that updates carry it, then \
+                     recreate the slot and sync the table again",
+                    self.table,
+                    self.table
+                ),
+            }
+        }
+        Ok(new)
+    }
+
+    fn push(&mut self, op: &'static str, lsn: Lsn, relation: u32, tuple: &[TupleValue]) {
+        let Some(positions) = self.relations.get(&relation) else {
+            return;
+        };
+        self.ops.push(op);
+        self.lsns.push(lsn.0);
+        self.commit_timestamps.push(self.commit_ts);
+        for column in &mut self.values {
+            column.push(None);
+        }
+        for (position, value) in positions.iter().zip(tuple) {
+            if let (Some(position), TupleValue::Text(text)) = (position, value)
+                && let Some(slot) = self.values[*position].last_mut()
+            {
+                *slot = Some(text.clone());
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<RecordBatch> {
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(std::mem::take(&mut self.ops))),
+            Arc::new(UInt64Array::from(std::mem::take(&mut self.lsns))),
+            Arc::new(
+                TimestampMicrosecondArray::from(std::mem::take(&mut self.commit_timestamps))
+                    .with_timezone("UTC"),
+            ),
+        ];
+        for (field, values) in self.schema.fields().iter().skip(3).zip(&mut self.values) {
+            let values = std::mem::take(values);
+            let values: Vec<Option<&str>> = values.iter().map(Option::as_deref).collect();
+            columns.push(text_to_array(&values, field)?);
+        }
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::Int32Type;
+
+    #[test]
+    fn test_lsn_round_trip() {
+        let lsn: Lsn = "16/B374D848".parse().unwrap();
+        assert_eq!(lsn.0, 0x16_B374_D848);
+        assert_eq!(lsn.to_string(), "16/B374D848");
+        assert!("16B374D848".parse::<Lsn>().is_err());
+        assert!("1/G".parse::<Lsn>().is_err());
+    }
+
+    #[test]
+    fn test_change_schema() {
+        let table = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let schema = change_schema(&table).unwrap();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["_op", "_lsn", "_commit_ts", "id"]);
+        assert!(schema.field(3).is_nullable());
+
+        let clash = Schema::new(vec![Field::new("_op", DataType::Utf8, false)]);
+        assert!(change_schema(&clash).is_err());
+    }
+
+    #[test]
+    fn test_changes_follow_table_relation() {
+        let table = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let schema = change_schema(&table).unwrap();
+        let mut changes = Changes::new(&TableRef::new(Some("public"), "events"), schema);
+        changes.add_relation(Relation {
+            id: 1,
+            namespace: "public".to_string(),
+            name: "events".to_string(),
+            columns: vec!["name".to_string(), "id".to_string()],
+        });
+        changes.add_relation(Relation {
+            id: 2,
+            namespace: "public".to_string(),
+            name: "other".to_string(),
+            columns: vec!["id".to_string()],
+        });
+        let text = |s: &str| TupleValue::Text(s.to_string());
+        changes.commit_ts = 5;
+        changes.push("insert", Lsn(10), 1, &[text("a"), text("1")]);
+        changes.push("insert", Lsn(11), 2, &[text("9")]);
+        changes.push("delete", Lsn(12), 1, &[TupleValue::Null, text("1")]);
+        assert_eq!(changes.len(), 2);
+
+        let batch = changes.finish().unwrap();
+        assert_eq!(changes.len(), 0);
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column(0).as_string::<i32>().value(1), "delete");
+        assert_eq!(
+            batch
+                .column(1)
+                .as_primitive::<arrow::datatypes::UInt64Type>()
+                .value(1),
+            12
+        );
+        assert_eq!(batch.column(3).as_primitive::<Int32Type>().value(0), 1);
+        assert_eq!(batch.column(4).as_string::<i32>().value(0), "a");
+        assert!(batch.column(4).is_null(1));
+    }
+
+    #[test]
+    fn test_unchanged_values() {
+        let table = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("doc", DataType::Utf8, true),
+        ]);
+        let schema = change_schema(&table).unwrap();
+        let mut changes = Changes::new(&TableRef::new(Some("public"), "events"), schema);
+        changes.add_relation(Relation {
+            id: 1,
+            namespace: "public".to_string(),
+            name: "events".to_string(),
+            columns: vec!["id".to_string(), "doc".to_string()],
+        });
+        let text = |s: &str| TupleValue::Text(s.to_string());
+        let new = vec![text("1"), TupleValue::Unchanged];
+
+        // with REPLICA IDENTITY FULL the old row carries the value
+        let old = [text("0"), text("large")];
+        let filled = changes
+            .fill_unchanged(Lsn(10), 1, Some(&old), new.clone())
+            .unwrap();
+        changes.push("update", Lsn(10), 1, &filled);
+        let batch = changes.finish().unwrap();
+        assert_eq!(batch.column(4).as_string::<i32>().value(0), "large");
+
+        // otherwise it is lost, rather than written as null
+        let error = changes
+            .fill_unchanged(Lsn(11), 1, None, new.clone())
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("REPLICA IDENTITY FULL"),
+            "{error}"
+        );
+        let key = [text("1"), TupleValue::Null];
+        assert!(changes.fill_unchanged(Lsn(11), 1, Some(&key), new).is_err());
+    }
+}
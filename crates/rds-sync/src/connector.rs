@@ -0,0 +1,133 @@
+///
+/// Databases that tables can be synced from
+///
+use crate::{
+    ExportedSnapshot, StreamOptions, TableRef, detect_split_column, infer_arrow_schema,
+    infer_query_schema, list_tables, resolve_table, stream_query, stream_table,
+};
+use anyhow::Result;
+use arrow::{
+    array::RecordBatch,
+    datatypes::{Schema, SchemaRef},
+};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use sqlx::PgPool;
+
+/// Schema inference and batch streaming for one kind of source database.
+///
+/// `plano-sync` is written against this trait, so the same extraction, partitioning and
+/// incremental logic applies to every source.
+#[async_trait]
+pub trait SourceConnector: Send + Sync {
+    /// Looks `table` up, returning it as it should be read (qualified with its schema, with
+    /// the name's stored case, ...).
+    async fn resolve_table(&self, table: &TableRef) -> Result<TableRef>;
+
+    /// The resolved tables in `schema`, optionally with its views and materialized views.
+    async fn list_tables(&self, schema: &str, views: bool) -> Result<Vec<TableRef>>;
+
+    /// Infers the Arrow schema of a resolved table.
+    async fn infer_schema(&self, table: &TableRef) -> Result<SchemaRef>;
+
+    /// Infers the Arrow schema of the rows returned by `sql`, without running it.
+    async fn infer_query_schema(&self, sql: &str) -> Result<SchemaRef>;
+
+    /// Picks a column to split a resolved table on for parallel extraction, if it has a
+    /// suitable one.
+    async fn detect_split_column(
+        &self,
+        table: &TableRef,
+        schema: &Schema,
+    ) -> Result<Option<String>>;
+
+    /// Streams the `schema` columns of a resolved table.
+    fn stream_table(
+        &self,
+        table: &TableRef,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>>;
+
+    /// Streams the `schema` columns of the rows returned by `sql`.
+    fn stream_query(
+        &self,
+        sql: &str,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>>;
+
+    /// Exports a snapshot that several tables can then be read from consistently, or returns
+    /// `None` if the source cannot.
+    async fn export_snapshot(&self) -> Result<Option<ExportedSnapshot>> {
+        Ok(None)
+    }
+}
+
+/// A Postgres database, read with the functions at the root of this crate.
+#[derive(Debug, Clone)]
+pub struct PostgresConnector {
+    pool: PgPool,
+}
+
+impl PostgresConnector {
+    #[must_use]
+    pub const fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// The pool, for Postgres-only features such as [`crate::cdc`].
+    #[must_use]
+    pub const fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl SourceConnector for PostgresConnector {
+    async fn resolve_table(&self, table: &TableRef) -> Result<TableRef> {
+        resolve_table(table, &self.pool).await
+    }
+
+    async fn list_tables(&self, schema: &str, views: bool) -> Result<Vec<TableRef>> {
+        list_tables(schema, views, &self.pool).await
+    }
+
+    async fn infer_schema(&self, table: &TableRef) -> Result<SchemaRef> {
+        infer_arrow_schema(table, &self.pool).await
+    }
+
+    async fn infer_query_schema(&self, sql: &str) -> Result<SchemaRef> {
+        infer_query_schema(sql, &self.pool).await
+    }
+
+    async fn detect_split_column(
+        &self,
+        table: &TableRef,
+        schema: &Schema,
+    ) -> Result<Option<String>> {
+        detect_split_column(table, schema, &self.pool).await
+    }
+
+    fn stream_table(
+        &self,
+        table: &TableRef,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        stream_table(table, schema, &self.pool, options)
+    }
+
+    fn stream_query(
+        &self,
+        sql: &str,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        stream_query(sql, schema, &self.pool, options)
+    }
+
+    async fn export_snapshot(&self) -> Result<Option<ExportedSnapshot>> {
+        ExportedSnapshot::export(&self.pool).await.map(Some)
+    }
+}
@@ -0,0 +1,533 @@
+///
+/// Decoding of `COPY ... TO STDOUT (FORMAT binary)` output
+///
+/// See <https://www.postgresql.org/docs/current/sql-copy.html> ("Binary Format").
+///
+use crate::pgoutput::PG_EPOCH_OFFSET_MICROS;
+use crate::types::{parse_decimal128, parse_decimal256};
+use anyhow::{Context, Result, bail};
+use arrow::{
+    array::{
+        ArrayRef, ArrowPrimitiveType, BinaryArray, BooleanBuilder, ListArray, PrimitiveArray,
+        PrimitiveBuilder, RecordBatch, StringArray,
+    },
+    buffer::{NullBuffer, OffsetBuffer},
+    datatypes::{
+        DataType, Date32Type, Decimal128Type, Decimal256Type, Field, Float32Type, Float64Type,
+        Int16Type, Int32Type, Int64Type, SchemaRef, Time64MicrosecondType, TimeUnit,
+        TimestampMicrosecondType,
+    },
+};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Signature at the start of every binary `COPY` stream.
+const SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+
+/// Flag set in the header when each tuple starts with its OID.
+const HAS_OIDS: i32 = 1 << 16;
+
+/// Days between the Unix epoch and the Postgres epoch (2000-01-01).
+const PG_EPOCH_OFFSET_DAYS: i32 = 10_957;
+
+/// Splits a binary `COPY` stream into tuples and converts them into `RecordBatch`es.
+///
+/// The stream may be pushed in chunks of any size; tuples split across chunks are kept until
+/// they are complete.  Values are only copied once, into the Arrow arrays.
+pub struct CopyDecoder {
+    schema: SchemaRef,
+    buffer: Vec<u8>,
+    /// Offset in `buffer` of the first byte not parsed yet.
+    pos: usize,
+    header_read: bool,
+    trailer_read: bool,
+    rows: usize,
+    /// For each column, the position of each parsed value in `buffer`, or `None` for nulls.
+    values: Vec<Vec<Option<Range<usize>>>>,
+}
+
+impl CopyDecoder {
+    pub fn new(schema: SchemaRef) -> Self {
+        let values = vec![Vec::new(); schema.fields().len()];
+        Self {
+            schema,
+            buffer: Vec::new(),
+            pos: 0,
+            header_read: false,
+            trailer_read: false,
+            rows: 0,
+            values,
+        }
+    }
+
+    /// Appends the next chunk of the stream.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Parses buffered tuples and returns a batch once `batch_size` rows are available.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream is malformed or a value cannot be converted.
+    pub fn next_batch(&mut self, batch_size: usize) -> Result<Option<RecordBatch>> {
+        if !self.header_read && !self.read_header()? {
+            return Ok(None);
+        }
+        while self.rows < batch_size && !self.trailer_read && self.read_tuple()? {}
+        if self.rows < batch_size {
+            return Ok(None);
+        }
+        self.flush().map(Some)
+    }
+
+    /// Parses the rest of the stream, which must end with its trailer, and returns any rows
+    /// not returned yet.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the stream is truncated or malformed.
+    pub fn finish(mut self) -> Result<Option<RecordBatch>> {
+        if !self.header_read && !self.read_header()? {
+            bail!("Truncated COPY stream: incomplete header");
+        }
+        while !self.trailer_read && self.read_tuple()? {}
+        if !self.trailer_read {
+            bail!("Truncated COPY stream: missing trailer");
+        }
+        if self.pos != self.buffer.len() {
+            bail!("Unexpected data after the end of the COPY stream");
+        }
+        if self.rows == 0 {
+            return Ok(None);
+        }
+        self.flush().map(Some)
+    }
+
+    /// Reads the header, returning whether it was complete.
+    fn read_header(&mut self) -> Result<bool> {
+        let mut pos = SIGNATURE.len();
+        if self.buffer.len() < pos {
+            return Ok(false);
+        }
+        if !self.buffer.starts_with(SIGNATURE) {
+            bail!("Not a binary COPY stream");
+        }
+        let (Some(flags), Some(extension)) = (
+            read_i32(&self.buffer, &mut pos),
+            read_i32(&self.buffer, &mut pos),
+        ) else {
+            return Ok(false);
+        };
+        if flags & HAS_OIDS != 0 {
+            bail!("COPY streams with OIDs are not supported");
+        }
+        pos += usize::try_from(extension)?;
+        if self.buffer.len() < pos {
+            return Ok(false);
+        }
+        self.pos = pos;
+        self.header_read = true;
+        Ok(true)
+    }
+
+    /// Reads the tuple or trailer at `pos`, returning whether it was complete.
+    fn read_tuple(&mut self) -> Result<bool> {
+        let mut pos = self.pos;
+        let Some(count) = read_i16(&self.buffer, &mut pos) else {
+            return Ok(false);
+        };
+        if count == -1 {
+            self.pos = pos;
+            self.trailer_read = true;
+            return Ok(true);
+        }
+        if usize::try_from(count).ok() != Some(self.values.len()) {
+            bail!(
+                "Expected tuples of {} columns, got {count}",
+                self.values.len()
+            );
+        }
+        for column in 0..self.values.len() {
+            let Some(len) = read_i32(&self.buffer, &mut pos) else {
+                self.discard_partial_tuple();
+                return Ok(false);
+            };
+            let value = if len == -1 {
+                None
+            } else {
+                let end = pos + usize::try_from(len)?;
+                if self.buffer.len() < end {
+                    self.discard_partial_tuple();
+                    return Ok(false);
+                }
+                let start = pos;
+                pos = end;
+                Some(start..end)
+            };
+            self.values[column].push(value);
+        }
+        self.pos = pos;
+        self.rows += 1;
+        Ok(true)
+    }
+
+    fn discard_partial_tuple(&mut self) {
+        for values in &mut self.values {
+            values.truncate(self.rows);
+        }
+    }
+
+    /// Converts the parsed rows into a batch and drops their bytes from the buffer.
+    fn flush(&mut self) -> Result<RecordBatch> {
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .zip(&self.values)
+            .map(|(field, ranges)| {
+                let values: Vec<Option<&[u8]>> = ranges
+                    .iter()
+                    .map(|range| range.clone().map(|range| &self.buffer[range]))
+                    .collect();
+                binary_to_array(&values, field)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        self.rows = 0;
+        for values in &mut self.values {
+            values.clear();
+        }
+        Ok(batch)
+    }
+}
+
+/// Builds an array of `field`'s type from values in Postgres' binary format.
+///
+/// Text-decoded types (see [`crate::types::select_expr`]) are expected as text.
+///
+/// # Errors
+///
+/// Will return `Err` if a value does not have the expected size or cannot be converted.
+pub fn binary_to_array(values: &[Option<&[u8]>], field: &Field) -> Result<ArrayRef> {
+    let name = field.name();
+    let array: ArrayRef = match field.data_type() {
+        DataType::Utf8 => Arc::new(StringArray::from(
+            values
+                .iter()
+                .map(|value| value.map(std::str::from_utf8).transpose())
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(values.len());
+            for value in values {
+                let value = value.map(fixed::<1>).transpose()?;
+                builder.append_option(value.map(|[byte]| byte != 0));
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => Arc::new(BinaryArray::from(values.to_vec())),
+        DataType::Int16 => Arc::new(primitive::<Int16Type>(values, field, |v| {
+            Ok(i16::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Int32 => Arc::new(primitive::<Int32Type>(values, field, |v| {
+            Ok(i32::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Int64 => Arc::new(primitive::<Int64Type>(values, field, |v| {
+            Ok(i64::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Float32 => Arc::new(primitive::<Float32Type>(values, field, |v| {
+            Ok(f32::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Float64 => Arc::new(primitive::<Float64Type>(values, field, |v| {
+            Ok(f64::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Date32 => Arc::new(primitive::<Date32Type>(values, field, |v| {
+            unix_days(i32::from_be_bytes(fixed(v)?))
+        })?),
+        DataType::Time64(TimeUnit::Microsecond) => {
+            Arc::new(primitive::<Time64MicrosecondType>(values, field, |v| {
+                Ok(i64::from_be_bytes(fixed(v)?))
+            })?)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            Arc::new(primitive::<TimestampMicrosecondType>(values, field, |v| {
+                unix_micros(i64::from_be_bytes(fixed(v)?))
+            })?)
+        }
+        DataType::Decimal128(precision, scale) => {
+            let array = primitive::<Decimal128Type>(values, field, |v| {
+                parse_decimal128(std::str::from_utf8(v)?, *scale)
+            })?;
+            array.validate_decimal_precision(*precision)?;
+            Arc::new(array)
+        }
+        DataType::Decimal256(precision, scale) => {
+            let array = primitive::<Decimal256Type>(values, field, |v| {
+                parse_decimal256(std::str::from_utf8(v)?, *scale)
+            })?;
+            array.validate_decimal_precision(*precision)?;
+            Arc::new(array)
+        }
+        DataType::List(item) => list(values, item)?,
+        other => bail!("Unsupported data type for '{name}': {other:?}"),
+    };
+    Ok(array)
+}
+
+fn primitive<T: ArrowPrimitiveType>(
+    values: &[Option<&[u8]>],
+    field: &Field,
+    convert: impl Fn(&[u8]) -> Result<T::Native>,
+) -> Result<PrimitiveArray<T>> {
+    let mut builder = PrimitiveBuilder::<T>::with_capacity(values.len())
+        .with_data_type(field.data_type().clone());
+    for value in values {
+        let converted = value
+            .map(|v| convert(v).with_context(|| format!("Invalid value for '{}'", field.name())))
+            .transpose()?;
+        builder.append_option(converted);
+    }
+    Ok(builder.finish())
+}
+
+/// Decodes one-dimensional arrays into a `List`.
+fn list(values: &[Option<&[u8]>], item: &Arc<Field>) -> Result<ArrayRef> {
+    let mut elements = Vec::new();
+    let mut lengths = Vec::with_capacity(values.len());
+    for value in values {
+        let before = elements.len();
+        if let Some(bytes) = value {
+            read_array(bytes, &mut elements)?;
+        }
+        lengths.push(elements.len() - before);
+    }
+    let child = binary_to_array(&elements, item)?;
+    let nulls = NullBuffer::from(values.iter().map(Option::is_some).collect::<Vec<_>>());
+    Ok(Arc::new(ListArray::try_new(
+        item.clone(),
+        OffsetBuffer::from_lengths(lengths),
+        child,
+        Some(nulls),
+    )?))
+}
+
+/// Appends the elements of an array in binary format to `elements`.
+fn read_array<'a>(bytes: &'a [u8], elements: &mut Vec<Option<&'a [u8]>>) -> Result<()> {
+    const TRUNCATED: &str = "Truncated array value";
+    let mut pos = 0;
+    let dimensions = read_i32(bytes, &mut pos).context(TRUNCATED)?;
+    read_i32(bytes, &mut pos).context(TRUNCATED)?; // whether there are nulls
+    read_i32(bytes, &mut pos).context(TRUNCATED)?; // element type oid
+    match dimensions {
+        0 => return Ok(()),
+        1 => {}
+        _ => bail!("Multi-dimensional arrays are not supported"),
+    }
+    let len = read_i32(bytes, &mut pos).context(TRUNCATED)?;
+    read_i32(bytes, &mut pos).context(TRUNCATED)?; // lower bound
+    for _ in 0..len {
+        let element_len = read_i32(bytes, &mut pos).context(TRUNCATED)?;
+        if element_len == -1 {
+            elements.push(None);
+            continue;
+        }
+        let end = pos + usize::try_from(element_len)?;
+        elements.push(Some(bytes.get(pos..end).context(TRUNCATED)?));
+        pos = end;
+    }
+    if pos != bytes.len() {
+        bail!("Unexpected data after array elements");
+    }
+    Ok(())
+}
+
+fn read_i16(bytes: &[u8], pos: &mut usize) -> Option<i16> {
+    let value = bytes.get(*pos..*pos + 2)?;
+    *pos += 2;
+    Some(i16::from_be_bytes(value.try_into().ok()?))
+}
+
+fn read_i32(bytes: &[u8], pos: &mut usize) -> Option<i32> {
+    let value = bytes.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(i32::from_be_bytes(value.try_into().ok()?))
+}
+
+fn fixed<const N: usize>(value: &[u8]) -> Result<[u8; N]> {
+    value
+        .try_into()
+        .with_context(|| format!("Expected {N} bytes, got {}", value.len()))
+}
+
+/// Converts days since the Postgres epoch to days since the Unix epoch.
+fn unix_days(days: i32) -> Result<i32> {
+    if days == i32::MAX || days == i32::MIN {
+        bail!("Infinite dates cannot be converted");
+    }
+    days.checked_add(PG_EPOCH_OFFSET_DAYS)
+        .context("Date out of range")
+}
+
+/// Converts microseconds since the Postgres epoch to microseconds since the Unix epoch.
+fn unix_micros(micros: i64) -> Result<i64> {
+    if micros == i64::MAX || micros == i64::MIN {
+        bail!("Infinite timestamps cannot be converted");
+    }
+    micros
+        .checked_add(PG_EPOCH_OFFSET_MICROS)
+        .context("Timestamp out of range")
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::Schema;
+
+    fn stream(tuples: &[Vec<Option<Vec<u8>>>]) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(0i32.to_be_bytes());
+        bytes.extend(0i32.to_be_bytes());
+        for tuple in tuples {
+            bytes.extend(i16::try_from(tuple.len()).unwrap().to_be_bytes());
+            for value in tuple {
+                match value {
+                    Some(value) => {
+                        bytes.extend(i32::try_from(value.len()).unwrap().to_be_bytes());
+                        bytes.extend(value);
+                    }
+                    None => bytes.extend((-1i32).to_be_bytes()),
+                }
+            }
+        }
+        bytes.extend((-1i16).to_be_bytes());
+        bytes
+    }
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]))
+    }
+
+    fn rows(count: i64) -> Vec<Vec<Option<Vec<u8>>>> {
+        (0..count)
+            .map(|id| {
+                let name = (id % 2 == 0).then(|| format!("row {id}").into_bytes());
+                vec![Some(id.to_be_bytes().to_vec()), name]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_in_batches() {
+        let mut decoder = CopyDecoder::new(schema());
+        // one byte at a time, so that every header, length and value is split
+        let mut batches = Vec::new();
+        for byte in stream(&rows(5)) {
+            decoder.push(&[byte]);
+            batches.extend(decoder.next_batch(2).unwrap());
+        }
+        batches.extend(decoder.finish().unwrap());
+
+        let sizes: Vec<usize> = batches.iter().map(RecordBatch::num_rows).collect();
+        assert_eq!(sizes, [2, 2, 1]);
+        let ids = batches[1].column(0).as_primitive::<Int64Type>();
+        assert_eq!(ids.values(), &[2, 3]);
+        let names = batches[1].column(1).as_string::<i32>();
+        assert_eq!(names.value(0), "row 2");
+        assert!(names.is_null(1));
+    }
+
+    #[test]
+    fn test_decode_empty() {
+        let mut decoder = CopyDecoder::new(schema());
+        decoder.push(&stream(&[]));
+        assert!(decoder.next_batch(2).unwrap().is_none());
+        assert!(decoder.finish().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        let mut decoder = CopyDecoder::new(schema());
+        decoder.push(b"PGCOPY\n\xff\r\n\x01");
+        assert!(decoder.next_batch(2).is_err());
+
+        let mut truncated = stream(&rows(3));
+        truncated.truncate(truncated.len() - 5);
+        let mut decoder = CopyDecoder::new(schema());
+        decoder.push(&truncated);
+        assert!(decoder.finish().is_err());
+
+        let mut decoder = CopyDecoder::new(schema());
+        decoder.push(&stream(&[vec![Some(vec![0; 8])]]));
+        assert!(decoder.next_batch(2).is_err());
+    }
+
+    #[test]
+    fn test_binary_to_array_temporal() {
+        let field = Field::new("d", DataType::Date32, true);
+        let epoch = (-PG_EPOCH_OFFSET_DAYS).to_be_bytes();
+        let array = binary_to_array(&[Some(&epoch), None], &field).unwrap();
+        assert_eq!(array.as_primitive::<Date32Type>().value(0), 0);
+        assert!(array.is_null(1));
+
+        let field = Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        );
+        let array = binary_to_array(&[Some(&1i64.to_be_bytes())], &field).unwrap();
+        assert_eq!(
+            array.as_primitive::<TimestampMicrosecondType>().value(0),
+            PG_EPOCH_OFFSET_MICROS + 1
+        );
+        assert!(binary_to_array(&[Some(&i64::MAX.to_be_bytes())], &field).is_err());
+    }
+
+    #[test]
+    fn test_binary_to_array_decimal() {
+        let field = Field::new("n", DataType::Decimal128(5, 2), true);
+        let array = binary_to_array(&[Some(b"-12.345")], &field).unwrap();
+        assert_eq!(array.as_primitive::<Decimal128Type>().value(0), -1235);
+        assert!(binary_to_array(&[Some(b"12345.6")], &field).is_err());
+    }
+
+    #[test]
+    fn test_binary_to_array_list() {
+        let mut array = Vec::new();
+        for header in [1i32, 1, 23, 3, 1] {
+            array.extend(header.to_be_bytes());
+        }
+        for element in [Some(7i32), None, Some(-1)] {
+            match element {
+                Some(value) => {
+                    array.extend(4i32.to_be_bytes());
+                    array.extend(value.to_be_bytes());
+                }
+                None => array.extend((-1i32).to_be_bytes()),
+            }
+        }
+        let mut empty = Vec::new();
+        for header in [0i32, 0, 23] {
+            empty.extend(header.to_be_bytes());
+        }
+
+        let item = Arc::new(Field::new_list_field(DataType::Int32, true));
+        let field = Field::new("a", DataType::List(item), true);
+        let list = binary_to_array(&[Some(&array), None, Some(&empty)], &field).unwrap();
+        let list = list.as_list::<i32>();
+        assert_eq!(list.value_offsets(), &[0, 3, 3, 3]);
+        assert!(list.is_null(1));
+        let values = list.values().as_primitive::<Int32Type>();
+        assert_eq!(values.value(0), 7);
+        assert!(values.is_null(1));
+        assert_eq!(values.value(2), -1);
+    }
+}
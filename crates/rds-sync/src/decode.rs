@@ -0,0 +1,242 @@
+///
+/// Conversion of Postgres rows into Arrow arrays
+///
+use crate::types::{parse_decimal128, parse_decimal256};
+use anyhow::{Result, bail};
+use arrow::{
+    array::{
+        ArrayRef, ArrowPrimitiveType, BinaryBuilder, BooleanBuilder, ListBuilder, PrimitiveBuilder,
+        RecordBatch, StringBuilder,
+    },
+    datatypes::{
+        DataType, Date32Type, Decimal128Type, Decimal256Type, FieldRef, Float32Type, Float64Type,
+        Int16Type, Int32Type, Int64Type, SchemaRef, Time64MicrosecondType, TimeUnit,
+        TimestampMicrosecondType,
+    },
+};
+use chrono::Timelike;
+use sqlx::{
+    Decode, Postgres, Row, Type,
+    postgres::{PgHasArrayType, PgRow},
+    types::chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc},
+};
+use std::sync::Arc;
+
+/// Converts a slice of Postgres rows into a `RecordBatch` following `schema`.
+///
+/// Columns must have been selected with [`crate::types::select_expr`] so that text-decoded
+/// types arrive as text.
+pub fn rows_to_batch(rows: &[PgRow], schema: &SchemaRef) -> Result<RecordBatch> {
+    let mut columns: Vec<ArrayRef> = Vec::with_capacity(schema.fields().len());
+
+    for field in schema.fields() {
+        let name = field.name().as_str();
+        let data_type = field.data_type();
+
+        let array: ArrayRef = match data_type {
+            DataType::List(item) => list_array(rows, name, item)?,
+            DataType::Utf8 => {
+                let mut builder = StringBuilder::with_capacity(rows.len(), rows.len() * 16);
+                for row in rows {
+                    let value = row.try_get::<Option<String>, _>(name)?;
+                    builder.append_option(value.as_deref());
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Boolean => {
+                let mut builder = BooleanBuilder::with_capacity(rows.len());
+                for row in rows {
+                    let value = row.try_get::<Option<bool>, _>(name)?;
+                    builder.append_option(value);
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Binary => {
+                let mut builder = BinaryBuilder::with_capacity(rows.len(), rows.len() * 16);
+                for row in rows {
+                    let value = row.try_get::<Option<Vec<u8>>, _>(name)?;
+                    builder.append_option(value);
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Decimal128(precision, scale) => {
+                let array = primitive_array::<Decimal128Type, String>(rows, field, |v| {
+                    parse_decimal128(&v, *scale)
+                })?;
+                array.validate_decimal_precision(*precision)?;
+                Arc::new(array)
+            }
+            DataType::Decimal256(precision, scale) => {
+                let array = primitive_array::<Decimal256Type, String>(rows, field, |v| {
+                    parse_decimal256(&v, *scale)
+                })?;
+                array.validate_decimal_precision(*precision)?;
+                Arc::new(array)
+            }
+            _ => scalar_array(rows, field)?,
+        };
+
+        columns.push(array);
+    }
+
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    Ok(batch)
+}
+
+/// Builds arrays for the primitive types that have a list counterpart.
+fn scalar_array(rows: &[PgRow], field: &FieldRef) -> Result<ArrayRef> {
+    Ok(match field.data_type() {
+        DataType::Int16 => Arc::new(primitive_array::<Int16Type, i16>(rows, field, Ok)?),
+        DataType::Int32 => Arc::new(primitive_array::<Int32Type, i32>(rows, field, Ok)?),
+        DataType::Int64 => Arc::new(primitive_array::<Int64Type, i64>(rows, field, Ok)?),
+        DataType::Float32 => Arc::new(primitive_array::<Float32Type, f32>(rows, field, Ok)?),
+        DataType::Float64 => Arc::new(primitive_array::<Float64Type, f64>(rows, field, Ok)?),
+        DataType::Date32 => Arc::new(primitive_array::<Date32Type, NaiveDate>(
+            rows,
+            field,
+            |v| Ok(date_to_days(v)),
+        )?),
+        DataType::Time64(TimeUnit::Microsecond) => Arc::new(primitive_array::<
+            Time64MicrosecondType,
+            NaiveTime,
+        >(rows, field, |v| {
+            Ok(time_to_micros(v))
+        })?),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            Arc::new(primitive_array::<TimestampMicrosecondType, NaiveDateTime>(
+                rows,
+                field,
+                |v| Ok(v.and_utc().timestamp_micros()),
+            )?)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => {
+            Arc::new(primitive_array::<TimestampMicrosecondType, DateTime<Utc>>(
+                rows,
+                field,
+                |v| Ok(v.timestamp_micros()),
+            )?)
+        }
+        other => bail!("Unsupported data type for '{}': {other:?}", field.name()),
+    })
+}
+
+/// Builds a `List` array from a Postgres array column.
+fn list_array(rows: &[PgRow], name: &str, item: &FieldRef) -> Result<ArrayRef> {
+    Ok(match item.data_type() {
+        DataType::Int16 => primitive_list::<Int16Type, i16>(rows, name, item, Ok)?,
+        DataType::Int32 => primitive_list::<Int32Type, i32>(rows, name, item, Ok)?,
+        DataType::Int64 => primitive_list::<Int64Type, i64>(rows, name, item, Ok)?,
+        DataType::Float32 => primitive_list::<Float32Type, f32>(rows, name, item, Ok)?,
+        DataType::Float64 => primitive_list::<Float64Type, f64>(rows, name, item, Ok)?,
+        DataType::Date32 => {
+            primitive_list::<Date32Type, NaiveDate>(rows, name, item, |v| Ok(date_to_days(v)))?
+        }
+        DataType::Time64(TimeUnit::Microsecond) => {
+            primitive_list::<Time64MicrosecondType, NaiveTime>(rows, name, item, |v| {
+                Ok(time_to_micros(v))
+            })?
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            primitive_list::<TimestampMicrosecondType, NaiveDateTime>(rows, name, item, |v| {
+                Ok(v.and_utc().timestamp_micros())
+            })?
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => {
+            primitive_list::<TimestampMicrosecondType, DateTime<Utc>>(rows, name, item, |v| {
+                Ok(v.timestamp_micros())
+            })?
+        }
+        DataType::Decimal128(_, scale) => {
+            primitive_list::<Decimal128Type, String>(rows, name, item, |v| {
+                parse_decimal128(&v, *scale)
+            })?
+        }
+        DataType::Decimal256(_, scale) => {
+            primitive_list::<Decimal256Type, String>(rows, name, item, |v| {
+                parse_decimal256(&v, *scale)
+            })?
+        }
+        DataType::Boolean => {
+            let mut builder = ListBuilder::new(BooleanBuilder::new()).with_field(item.clone());
+            for row in rows {
+                let value = row.try_get::<Option<Vec<Option<bool>>>, _>(name)?;
+                builder.append_option(value);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Utf8 => {
+            let mut builder = ListBuilder::new(StringBuilder::new()).with_field(item.clone());
+            for row in rows {
+                let value = row.try_get::<Option<Vec<Option<String>>>, _>(name)?;
+                builder.append_option(value);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => {
+            let mut builder = ListBuilder::new(BinaryBuilder::new()).with_field(item.clone());
+            for row in rows {
+                let value = row.try_get::<Option<Vec<Option<Vec<u8>>>>, _>(name)?;
+                builder.append_option(value);
+            }
+            Arc::new(builder.finish())
+        }
+        other => bail!("Unsupported array element type for '{name}': {other:?}"),
+    })
+}
+
+/// Decodes column `field` as `V` and converts each value into the native Arrow type.
+fn primitive_array<T, V>(
+    rows: &[PgRow],
+    field: &FieldRef,
+    convert: impl Fn(V) -> Result<T::Native>,
+) -> Result<arrow::array::PrimitiveArray<T>>
+where
+    T: ArrowPrimitiveType,
+    V: for<'r> Decode<'r, Postgres> + Type<Postgres>,
+{
+    let name = field.name().as_str();
+    let mut builder =
+        PrimitiveBuilder::<T>::with_capacity(rows.len()).with_data_type(field.data_type().clone());
+    for row in rows {
+        let value = row.try_get::<Option<V>, _>(name)?;
+        builder.append_option(value.map(&convert).transpose()?);
+    }
+    Ok(builder.finish())
+}
+
+/// Decodes array column `name` as `V[]` into a `List` of the native Arrow type.
+fn primitive_list<T, V>(
+    rows: &[PgRow],
+    name: &str,
+    item: &FieldRef,
+    convert: impl Fn(V) -> Result<T::Native>,
+) -> Result<ArrayRef>
+where
+    T: ArrowPrimitiveType,
+    V: for<'r> Decode<'r, Postgres> + Type<Postgres> + PgHasArrayType,
+{
+    let values = PrimitiveBuilder::<T>::new().with_data_type(item.data_type().clone());
+    let mut builder = ListBuilder::new(values).with_field(item.clone());
+    for row in rows {
+        match row.try_get::<Option<Vec<Option<V>>>, _>(name)? {
+            Some(elements) => {
+                for element in elements {
+                    builder
+                        .values()
+                        .append_option(element.map(&convert).transpose()?);
+                }
+                builder.append(true);
+            }
+            None => builder.append_null(),
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+pub fn date_to_days(date: NaiveDate) -> i32 {
+    Date32Type::from_naive_date(date)
+}
+
+pub fn time_to_micros(time: NaiveTime) -> i64 {
+    i64::from(time.num_seconds_from_midnight()) * 1_000_000 + i64::from(time.nanosecond() / 1_000)
+}
@@ -1,27 +1,73 @@
 /// RDS Sync Library
 use anyhow::{Result, bail};
 use arrow::{
-    array::{ArrayRef, BooleanBuilder, PrimitiveBuilder, RecordBatch, StringBuilder},
-    datatypes::{
-        DataType, Field, Float64Type, Int32Type, Int64Type, Schema, TimeUnit,
-        TimestampMicrosecondType,
-    },
+    array::RecordBatch,
+    compute::concat_batches,
+    datatypes::{Field, Schema, SchemaRef},
 };
-use sqlx::{PgPool, Row, types::chrono};
+pub use connector::{PostgresConnector, SourceConnector};
+use copy::CopyDecoder;
+use decode::rows_to_batch;
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+pub use predicate::Predicate;
+pub use query::infer_query_schema;
+use query::subquery;
+pub use snapshot::ExportedSnapshot;
+pub use sqlite::SqliteConnector;
+use sqlx::{PgPool, Row, postgres::PgRow};
 use std::sync::Arc;
+use table::quote_ident;
+pub use table::{TableRef, list_tables, primary_key, resolve_table};
+use tokio::{sync::mpsc, task::JoinSet};
+use types::{pg_to_arrow_type, select_expr};
+pub use watermark::Watermark;
 
+pub mod cdc;
+mod connector;
+mod copy;
+mod decode;
+mod pgoutput;
+mod predicate;
+mod query;
+mod snapshot;
+mod sqlite;
+mod table;
+mod text;
+pub mod types;
+mod watermark;
+
+/// Number of rows per `RecordBatch` when the caller has no preference.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Infers the Arrow schema of `table` from `information_schema.columns`.
+///
+/// An unqualified `table` is first resolved with [`resolve_table`].
 /// # Errors
 ///
-/// Will return `Err` if the table does not exist or if the schema cannot be inferred.
-pub async fn infer_arrow_schema(table: &str, pool: &PgPool) -> Result<Arc<Schema>> {
+/// Will return `Err` if the table does not exist, is ambiguous or if the schema cannot be inferred.
+pub async fn infer_arrow_schema(table: &TableRef, pool: &PgPool) -> Result<Arc<Schema>> {
+    let table = resolve_table(table, pool).await?;
     let query = r"
-        SELECT column_name, data_type, is_nullable
+        SELECT column_name, data_type, udt_name, is_nullable,
+               numeric_precision::int4 AS numeric_precision,
+               numeric_scale::int4 AS numeric_scale
         FROM information_schema.columns
-        WHERE table_name = $1
+        WHERE table_schema = $1 AND table_name = $2
         ORDER BY ordinal_position
     ";
 
-    let rows = sqlx::query(query).bind(table).fetch_all(pool).await?;
+    let rows = sqlx::query(query)
+        .bind(table.schema())
+        .bind(table.name())
+        .fetch_all(pool)
+        .await?;
+    if rows.is_empty() {
+        if is_matview(&table, pool).await? {
+            // the columns of a materialized view are only described by its query
+            return infer_query_schema(&format!("SELECT * FROM {table}"), pool).await;
+        }
+        bail!("Table {table} has no columns visible to the current user");
+    }
 
     let mut fields = Vec::with_capacity(rows.len());
 
@@ -30,17 +76,12 @@ pub async fn infer_arrow_schema(table: &str, pool: &PgPool) -> Result<Arc<Schema
         let sql_type: String = row.get("data_type");
         let nullable: bool = row.get::<String, _>("is_nullable") == "YES";
 
-        let arrow_type = match sql_type.as_str() {
-            "integer" | "int4" => DataType::Int32,
-            "bigint" | "int8" => DataType::Int64,
-            "smallint" | "int2" => DataType::Int16,
-            "text" | "character varying" | "varchar" => DataType::Utf8,
-            "boolean" => DataType::Boolean,
-            "timestamp without time zone" => DataType::Timestamp(TimeUnit::Microsecond, None),
-            "date" => DataType::Date32,
-            "numeric" | "decimal" | "double precision" => DataType::Float64, // lossy fallback
-            other => bail!("Unsupported SQL type: {other}"),
-        };
+        let arrow_type = pg_to_arrow_type(
+            &sql_type,
+            row.get("udt_name"),
+            row.get("numeric_precision"),
+            row.get("numeric_scale"),
+        );
 
         fields.push(Field::new(&name, arrow_type, nullable));
     }
@@ -48,79 +89,528 @@ pub async fn infer_arrow_schema(table: &str, pool: &PgPool) -> Result<Arc<Schema
     Ok(Arc::new(Schema::new(fields)))
 }
 
-/// Synchronizes a table from Postgres into an Arrow `RecordBatch`
+async fn is_matview(table: &TableRef, pool: &PgPool) -> Result<bool> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM pg_matviews WHERE schemaname = $1 AND matviewname = $2)",
+    )
+    .bind(table.schema())
+    .bind(table.name())
+    .fetch_one(pool)
+    .await?)
+}
+
+/// Synchronizes a table from Postgres into a single Arrow `RecordBatch`.
+///
+/// The whole table is held in memory; prefer [`stream_table`] for anything large.
 /// # Errors
 ///
 /// Will return `Err` if the table does not exist or if the schema cannot be inferred.
-pub async fn sync_table(table: &str, schema: &Schema, pool: &PgPool) -> Result<RecordBatch> {
-    let column_names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
-    let select_clause = column_names.join(", ");
-    let query = format!("SELECT {select_clause} FROM {table}");
-
-    let rows = sqlx::query(&query).fetch_all(pool).await?;
-    let mut columns: Vec<ArrayRef> = Vec::with_capacity(schema.fields().len());
-
-    for field in schema.fields() {
-        let name = field.name().as_str();
-        let data_type = field.data_type();
-
-        let array: ArrayRef = match data_type {
-            DataType::Float64 => {
-                let mut builder = PrimitiveBuilder::<Float64Type>::with_capacity(rows.len());
-                for row in &rows {
-                    let value = row.try_get::<Option<f64>, _>(name)?;
-                    builder.append_option(value);
-                }
-                Arc::new(builder.finish())
-            }
-            DataType::Int64 => {
-                let mut builder = PrimitiveBuilder::<Int64Type>::with_capacity(rows.len());
-                for row in &rows {
-                    let value = row.try_get::<Option<i64>, _>(name)?;
-                    builder.append_option(value);
-                }
-                Arc::new(builder.finish())
-            }
-            DataType::Utf8 => {
-                let mut builder = StringBuilder::with_capacity(rows.len(), rows.len() * 16);
-                for row in &rows {
-                    let value = row.try_get::<Option<String>, _>(name)?;
-                    builder.append_option(value.as_deref());
-                }
-                Arc::new(builder.finish())
+pub async fn sync_table(table: &TableRef, schema: &Schema, pool: &PgPool) -> Result<RecordBatch> {
+    let schema_ref = Arc::new(schema.clone());
+    let batches: Vec<RecordBatch> =
+        stream_table(table, schema_ref.clone(), pool, &StreamOptions::default())
+            .try_collect()
+            .await?;
+    Ok(concat_batches(&schema_ref, &batches)?)
+}
+
+/// Narrows `schema` to the columns to extract.
+///
+/// A non-empty `columns` selects those columns, in that order; `exclude` then removes columns.
+/// Names must match the table's column names exactly.
+///
+/// # Errors
+///
+/// Will return `Err` if a name is not a column of `schema` or if no column is left.
+pub fn project_schema(
+    schema: &Schema,
+    columns: &[String],
+    exclude: &[String],
+) -> Result<SchemaRef> {
+    for name in columns.iter().chain(exclude) {
+        if schema.field_with_name(name).is_err() {
+            bail!("Unknown column `{name}`");
+        }
+    }
+    let selected: Vec<Field> = if columns.is_empty() {
+        schema.fields().iter().map(|f| f.as_ref().clone()).collect()
+    } else {
+        columns
+            .iter()
+            .filter_map(|name| schema.field_with_name(name).ok().cloned())
+            .collect()
+    };
+    let fields: Vec<Field> = selected
+        .into_iter()
+        .filter(|field| !exclude.contains(field.name()))
+        .collect();
+    if fields.is_empty() {
+        bail!("No columns left to extract");
+    }
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Picks a column to split `table` on for parallel extraction: the leading column of its
+/// primary key, provided it is an integer, date or timestamp.
+///
+/// # Errors
+///
+/// Will return `Err` if the primary key cannot be looked up.
+pub async fn detect_split_column(
+    table: &TableRef,
+    schema: &Schema,
+    pool: &PgPool,
+) -> Result<Option<String>> {
+    let key = primary_key(table, pool).await?;
+    Ok(key.into_iter().next().filter(|column| {
+        schema
+            .field_with_name(column)
+            .is_ok_and(|field| Watermark::supports(field.data_type()))
+    }))
+}
+
+/// Options controlling how [`stream_table`] reads a table.
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+    batch_size: usize,
+    watermark: Option<(String, Watermark)>,
+    split: Option<(String, usize)>,
+    predicate: Option<Predicate>,
+    copy: bool,
+    snapshot: Option<String>,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_BATCH_SIZE)
+    }
+}
+
+impl StreamOptions {
+    /// Reads batches of at most `batch_size` rows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    #[must_use]
+    pub fn new(batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch_size must be greater than zero");
+        Self {
+            batch_size,
+            watermark: None,
+            split: None,
+            predicate: None,
+            copy: false,
+            snapshot: None,
+        }
+    }
+
+    /// Only reads rows whose `column` is strictly greater than `watermark`.
+    #[must_use]
+    pub fn with_watermark(mut self, column: &str, watermark: Watermark) -> Self {
+        self.watermark = Some((column.to_string(), watermark));
+        self
+    }
+
+    /// Only reads rows matching `predicate`.
+    #[must_use]
+    pub fn with_predicate(mut self, predicate: Predicate) -> Self {
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Reads rows with `COPY (SELECT ...) TO STDOUT (FORMAT binary)` and decodes the binary
+    /// tuples directly, rather than fetching and decoding them one row at a time.  This is
+    /// usually much faster, especially for wide tables.
+    #[must_use]
+    pub const fn with_copy(mut self) -> Self {
+        self.copy = true;
+        self
+    }
+
+    /// Reads from a snapshot exported by another transaction (see [`ExportedSnapshot`]), so that
+    /// the rows are consistent with everything else read from it.
+    #[must_use]
+    pub fn with_snapshot(mut self, id: &str) -> Self {
+        self.snapshot = Some(id.to_string());
+        self
+    }
+
+    /// Reads the table on `workers` connections at once, each taking one range of `column`'s
+    /// values.  `column` must be an integer, date or timestamp column of the streamed schema.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    #[must_use]
+    pub fn with_split(mut self, column: &str, workers: usize) -> Self {
+        assert!(workers > 0, "workers must be greater than zero");
+        self.split = Some((column.to_string(), workers));
+        self
+    }
+}
+
+impl StreamOptions {
+    /// Number of ranges read at once.
+    fn workers(&self) -> usize {
+        self.split.as_ref().map_or(1, |(_, workers)| *workers)
+    }
+
+    /// The conditions every row must meet: the predicate and the watermark.
+    fn filter(&self, inline: bool) -> Filter {
+        let mut filter = Filter {
+            inline,
+            ..Filter::default()
+        };
+        if let Some(predicate) = &self.predicate {
+            filter.and(predicate.to_string());
+        }
+        if let Some((column, watermark)) = &self.watermark {
+            let param = filter.param(watermark.clone());
+            filter.and(format!("{} > {param}", quote_ident(column)));
+        }
+        filter
+    }
+}
+
+/// Looks up the split column in the streamed `schema`, checking that it can be split on.
+fn split_field<'a>(schema: &'a Schema, column: &str) -> Result<&'a Field> {
+    let Ok(field) = schema.field_with_name(column) else {
+        bail!("Split column `{column}` is not one of the selected columns");
+    };
+    if !Watermark::supports(field.data_type()) {
+        bail!(
+            "Cannot split on `{column}` of type {}; use an integer, date or timestamp column",
+            field.data_type()
+        );
+    }
+    Ok(field)
+}
+
+/// The `WHERE` clause of a query and the values bound to its parameters.
+#[derive(Debug, Clone, Default)]
+struct Filter {
+    conditions: Vec<String>,
+    params: Vec<Watermark>,
+    /// Writes values as literals instead of parameters, for `COPY`.
+    inline: bool,
+}
+
+impl Filter {
+    /// Adds `value` as the next parameter and returns its placeholder, or returns `value` as a
+    /// literal if the filter is inlined.
+    fn param(&mut self, value: Watermark) -> String {
+        if self.inline {
+            return value.literal();
+        }
+        self.params.push(value);
+        format!("${}", self.params.len())
+    }
+
+    fn and(&mut self, condition: String) {
+        self.conditions.push(format!("({condition})"));
+    }
+
+    fn sql(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+}
+
+/// Streams a table from Postgres as a sequence of `RecordBatch`es.
+///
+/// `table` should already be resolved (see [`resolve_table`]) so that the rows come from the
+/// same table the schema was inferred from.
+///
+/// Rows are read incrementally from the server and converted on a background task, so peak
+/// memory is proportional to the batch size rather than to the size of the table.  With
+/// [`StreamOptions::with_split`], each worker reads and converts its own range concurrently
+/// and batches arrive in no particular order.
+#[must_use]
+pub fn stream_table(
+    table: &TableRef,
+    schema: SchemaRef,
+    pool: &PgPool,
+    options: &StreamOptions,
+) -> BoxStream<'static, Result<RecordBatch>> {
+    stream_from(table.to_string(), schema, pool, options)
+}
+
+/// Streams the rows returned by `sql` as a sequence of `RecordBatch`es.
+///
+/// The query is read as a subquery, so `schema` (see [`infer_query_schema`]) may select a
+/// subset of its columns and every [`StreamOptions`] applies as it does to a table.
+#[must_use]
+pub fn stream_query(
+    sql: &str,
+    schema: SchemaRef,
+    pool: &PgPool,
+    options: &StreamOptions,
+) -> BoxStream<'static, Result<RecordBatch>> {
+    stream_from(subquery(sql), schema, pool, options)
+}
+
+/// Streams the rows of `from`, a table name or an aliased subquery.
+fn stream_from(
+    from: String,
+    schema: SchemaRef,
+    pool: &PgPool,
+    options: &StreamOptions,
+) -> BoxStream<'static, Result<RecordBatch>> {
+    let pool = pool.clone();
+    let options = options.clone();
+    channel_stream(options.workers(), move |tx| async move {
+        read_ranges(&from, schema, &pool, &options, &tx).await
+    })
+}
+
+/// Runs `read` on a background task and streams the batches it sends, followed by the error
+/// it returns, if any.
+fn channel_stream<F, Fut>(workers: usize, read: F) -> BoxStream<'static, Result<RecordBatch>>
+where
+    F: FnOnce(mpsc::Sender<Result<RecordBatch>>) -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    // One slot per worker keeps each producer at most one batch ahead of the consumer.
+    let (tx, rx) = mpsc::channel::<Result<RecordBatch>>(workers);
+
+    let read = read(tx.clone());
+    tokio::spawn(async move {
+        if let Err(e) = read.await {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    })
+    .boxed()
+}
+
+/// Reads `from` with one query per range of the split column, or a single query.
+async fn read_ranges(
+    from: &str,
+    schema: SchemaRef,
+    pool: &PgPool,
+    options: &StreamOptions,
+    tx: &mpsc::Sender<Result<RecordBatch>>,
+) -> Result<()> {
+    let filter = options.filter(options.copy);
+    let mut ranges = vec![filter.clone()];
+    if let Some((column, workers)) = &options.split {
+        let field = split_field(&schema, column)?;
+        let column = quote_ident(column);
+        let bounds = format!(
+            "SELECT min({column}) AS lo, max({column}) AS hi FROM {from}{}",
+            filter.sql()
+        );
+        let mut query = sqlx::query(&bounds);
+        for param in &filter.params {
+            query = param.bind(query);
+        }
+        let mut conn = snapshot::begin(pool, options.snapshot.as_deref()).await?;
+        let row = query.fetch_one(&mut *conn).await?;
+        let lo = Watermark::decode(&row, "lo", field.data_type())?;
+        let hi = Watermark::decode(&row, "hi", field.data_type())?;
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            ranges = split_ranges(&filter, &column, &lo, &hi, *workers);
+        }
+    }
+
+    let select_clause = schema
+        .fields()
+        .iter()
+        .map(|f| select_expr(f.name(), f.data_type()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut workers = JoinSet::new();
+    for range in ranges {
+        let query = format!("SELECT {select_clause} FROM {from}{}", range.sql());
+        let read = Read {
+            schema: schema.clone(),
+            pool: pool.clone(),
+            snapshot: options.snapshot.clone(),
+            batch_size: options.batch_size,
+            tx: tx.clone(),
+        };
+        if options.copy {
+            workers.spawn(read.copy_batches(query));
+        } else {
+            workers.spawn(read.fetch_batches(query, range.params));
+        }
+    }
+    while let Some(finished) = workers.join_next().await {
+        finished?;
+    }
+    Ok(())
+}
+
+/// One filter per range of `column` between `lo` and `hi`.  The outer ranges are open-ended
+/// so that rows outside `[lo, hi]` by the time they are read are not lost, and the first one
+/// also takes the nulls.
+fn split_ranges(
+    filter: &Filter,
+    column: &str,
+    lo: &Watermark,
+    hi: &Watermark,
+    workers: usize,
+) -> Vec<Filter> {
+    let bounds = Watermark::split(lo, hi, workers);
+    let mut ranges = Vec::with_capacity(bounds.len() + 1);
+    for i in 0..=bounds.len() {
+        let mut range = filter.clone();
+        let lower = i
+            .checked_sub(1)
+            .map(|j| format!("{column} >= {}", range.param(bounds[j].clone())));
+        let upper = bounds
+            .get(i)
+            .map(|bound| format!("{column} < {}", range.param(bound.clone())));
+        match (lower, upper) {
+            (None, None) => {}
+            (None, Some(upper)) => range.and(format!("{upper} OR {column} IS NULL")),
+            (Some(lower), None) => range.and(lower),
+            (Some(lower), Some(upper)) => range.and(format!("{lower} AND {upper}")),
+        }
+        ranges.push(range);
+    }
+    ranges
+}
+
+/// Where one range is read from and its batches sent to.
+struct Read {
+    schema: SchemaRef,
+    pool: PgPool,
+    snapshot: Option<String>,
+    batch_size: usize,
+    tx: mpsc::Sender<Result<RecordBatch>>,
+}
+
+impl Read {
+    /// Runs `query`, sending its rows as batches of at most `batch_size` rows.
+    async fn fetch_batches(self, query: String, params: Vec<Watermark>) {
+        let mut conn = match snapshot::begin(&self.pool, self.snapshot.as_deref()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                let _ = self.tx.send(Err(e)).await;
+                return;
             }
-            DataType::Boolean => {
-                let mut builder = BooleanBuilder::with_capacity(rows.len());
-                for row in &rows {
-                    let value = row.try_get::<Option<bool>, _>(name)?;
-                    builder.append_option(value);
-                }
-                Arc::new(builder.finish())
+        };
+        let mut query = sqlx::query(&query);
+        for param in &params {
+            query = param.bind(query);
+        }
+        let mut chunks = query.fetch(&mut *conn).chunks(self.batch_size);
+        while let Some(chunk) = chunks.next().await {
+            let batch = chunk
+                .into_iter()
+                .collect::<sqlx::Result<Vec<PgRow>>>()
+                .map_err(anyhow::Error::from)
+                .and_then(|rows| rows_to_batch(&rows, &self.schema));
+            let failed = batch.is_err();
+            // stop when the receiver is gone or after reporting the first error
+            if self.tx.send(batch).await.is_err() || failed {
+                return;
             }
-            DataType::Int32 => {
-                let mut builder = PrimitiveBuilder::<Int32Type>::with_capacity(rows.len());
-                for row in &rows {
-                    let value = row.try_get::<Option<i32>, _>(name)?;
-                    builder.append_option(value);
+        }
+    }
+
+    /// Runs `query` through a binary `COPY`, sending its rows as batches of at most
+    /// `batch_size` rows.
+    async fn copy_batches(self, query: String) {
+        let copy = async {
+            let mut conn = snapshot::begin(&self.pool, self.snapshot.as_deref()).await?;
+            let statement = format!("COPY ({query}) TO STDOUT (FORMAT binary)");
+            let mut chunks = conn.copy_out_raw(&statement).await?;
+            let mut decoder = CopyDecoder::new(self.schema.clone());
+            while let Some(chunk) = chunks.try_next().await? {
+                decoder.push(&chunk);
+                while let Some(batch) = decoder.next_batch(self.batch_size)? {
+                    if self.tx.send(Ok(batch)).await.is_err() {
+                        return Ok(());
+                    }
                 }
-                Arc::new(builder.finish())
             }
-            DataType::Timestamp(TimeUnit::Microsecond, _) => {
-                let mut builder =
-                    PrimitiveBuilder::<TimestampMicrosecondType>::with_capacity(rows.len());
-                for row in &rows {
-                    let dt = row.try_get::<Option<chrono::NaiveDateTime>, _>(name)?;
-                    let ts = dt.map(|v| v.and_utc().timestamp_micros());
-                    builder.append_option(ts);
-                }
-                Arc::new(builder.finish())
+            if let Some(batch) = decoder.finish()? {
+                let _ = self.tx.send(Ok(batch)).await;
             }
-            other => bail!("Unsupported data type for '{name}': {other:?}"),
+            Ok(())
         };
+        if let Err(e) = copy.await {
+            let _ = self.tx.send(Err(e)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::DataType;
 
-        columns.push(array);
+    #[test]
+    fn test_project_schema() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("blob", DataType::Binary, true),
+        ]);
+        let names = |schema: &Schema| -> Vec<String> {
+            schema.fields().iter().map(|f| f.name().clone()).collect()
+        };
+        let strings =
+            |names: &[&str]| -> Vec<String> { names.iter().map(ToString::to_string).collect() };
+
+        let projected = project_schema(&schema, &strings(&["name", "id"]), &[]).unwrap();
+        assert_eq!(names(&projected), ["name", "id"]);
+        let projected = project_schema(&schema, &[], &strings(&["blob"])).unwrap();
+        assert_eq!(names(&projected), ["id", "name"]);
+
+        assert!(project_schema(&schema, &strings(&["nope"]), &[]).is_err());
+        assert!(project_schema(&schema, &[], &strings(&["nope"])).is_err());
+        assert!(project_schema(&schema, &strings(&["id"]), &strings(&["id"])).is_err());
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        let mut filter = Filter::default();
+        let param = filter.param(Watermark::Int(10));
+        filter.and(format!("\"id\" > {param}"));
+
+        let ranges = split_ranges(
+            &filter,
+            "\"id\"",
+            &Watermark::Int(10),
+            &Watermark::Int(40),
+            3,
+        );
+        let sql: Vec<String> = ranges.iter().map(Filter::sql).collect();
+        assert_eq!(
+            sql,
+            [
+                r#" WHERE ("id" > $1) AND ("id" < $2 OR "id" IS NULL)"#,
+                r#" WHERE ("id" > $1) AND ("id" >= $2 AND "id" < $3)"#,
+                r#" WHERE ("id" > $1) AND ("id" >= $2)"#,
+            ]
+        );
+        assert_eq!(
+            ranges[1].params,
+            [Watermark::Int(10), Watermark::Int(20), Watermark::Int(30)]
+        );
     }
 
-    let batch = RecordBatch::try_new(Arc::new(schema.clone()), columns)?;
-    Ok(batch)
+    #[test]
+    fn test_split_ranges_single_value() {
+        let ranges = split_ranges(
+            &Filter::default(),
+            "\"id\"",
+            &Watermark::Int(7),
+            &Watermark::Int(7),
+            4,
+        );
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].sql(), "");
+    }
 }
@@ -0,0 +1,348 @@
+///
+/// Decoding of the `pgoutput` logical replication protocol (version 1)
+///
+/// See <https://www.postgresql.org/docs/current/protocol-logicalrep-message-formats.html>.
+///
+use anyhow::{Result, bail};
+
+/// Microseconds between the Unix epoch and the Postgres epoch (2000-01-01).
+pub const PG_EPOCH_OFFSET_MICROS: i64 = 946_684_800_000_000;
+
+/// One value of a replicated row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TupleValue {
+    Null,
+    /// An out-of-line (TOAST) value that the update did not change, so it is not sent.
+    Unchanged,
+    Text(String),
+}
+
+/// A table as described by the publisher before its first change in a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relation {
+    pub id: u32,
+    pub namespace: String,
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+/// The subset of `pgoutput` messages needed to build a change log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Start of a transaction, with its commit time in microseconds since the Unix epoch.
+    Begin {
+        commit_ts: i64,
+    },
+    Commit,
+    Relation(Relation),
+    Insert {
+        relation: u32,
+        new: Vec<TupleValue>,
+    },
+    /// `old` holds the replica identity columns if the update changed them, or the whole row
+    /// with `REPLICA IDENTITY FULL`.
+    Update {
+        relation: u32,
+        old: Option<Vec<TupleValue>>,
+        new: Vec<TupleValue>,
+    },
+    /// `key` holds the replica identity columns, or the whole row with `REPLICA IDENTITY FULL`.
+    Delete {
+        relation: u32,
+        key: Vec<TupleValue>,
+    },
+    Truncate {
+        relations: Vec<u32>,
+    },
+    /// Origin, type and logical decoding messages, which carry no row data.
+    Other,
+}
+
+impl Message {
+    /// Parses a single message as returned by `pg_logical_slot_peek_binary_changes`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the message is truncated or malformed.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        Ok(match reader.u8()? {
+            b'B' => {
+                reader.i64()?; // final LSN
+                let commit_ts = reader.i64()? + PG_EPOCH_OFFSET_MICROS;
+                Self::Begin { commit_ts }
+            }
+            b'C' => Self::Commit,
+            b'R' => {
+                let id = reader.u32()?;
+                let namespace = reader.cstring()?;
+                let name = reader.cstring()?;
+                reader.u8()?; // replica identity setting
+                let count = reader.i16()?;
+                let mut columns = Vec::new();
+                for _ in 0..count {
+                    reader.u8()?; // flags
+                    columns.push(reader.cstring()?);
+                    reader.u32()?; // type oid
+                    reader.i32()?; // type modifier
+                }
+                Self::Relation(Relation {
+                    id,
+                    // the empty namespace stands for pg_catalog
+                    namespace: if namespace.is_empty() {
+                        "pg_catalog".to_string()
+                    } else {
+                        namespace
+                    },
+                    name,
+                    columns,
+                })
+            }
+            b'I' => {
+                let relation = reader.u32()?;
+                reader.expect(b'N')?;
+                Self::Insert {
+                    relation,
+                    new: reader.tuple()?,
+                }
+            }
+            b'U' => {
+                let relation = reader.u32()?;
+                let mut kind = reader.u8()?;
+                let mut old = None;
+                if kind == b'K' || kind == b'O' {
+                    old = Some(reader.tuple()?);
+                    kind = reader.u8()?;
+                }
+                if kind != b'N' {
+                    bail!("Malformed update message: unexpected tuple kind {kind}");
+                }
+                Self::Update {
+                    relation,
+                    old,
+                    new: reader.tuple()?,
+                }
+            }
+            b'D' => {
+                let relation = reader.u32()?;
+                let kind = reader.u8()?;
+                if kind != b'K' && kind != b'O' {
+                    bail!("Malformed delete message: unexpected tuple kind {kind}");
+                }
+                Self::Delete {
+                    relation,
+                    key: reader.tuple()?,
+                }
+            }
+            b'T' => {
+                let count = reader.i32()?;
+                reader.u8()?; // options
+                let relations = (0..count)
+                    .map(|_| reader.u32())
+                    .collect::<Result<Vec<_>>>()?;
+                Self::Truncate { relations }
+            }
+            b'O' | b'Y' | b'M' => Self::Other,
+            other => bail!("Unsupported pgoutput message type {:?}", char::from(other)),
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let Some((head, rest)) = self.bytes.split_first_chunk::<N>() else {
+            bail!("Truncated pgoutput message");
+        };
+        self.bytes = rest;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.take()?))
+    }
+
+    fn expect(&mut self, kind: u8) -> Result<()> {
+        let found = self.u8()?;
+        if found != kind {
+            bail!(
+                "Malformed pgoutput message: expected {:?}, found {:?}",
+                char::from(kind),
+                char::from(found)
+            );
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8]> {
+        if self.bytes.len() < len {
+            bail!("Truncated pgoutput message");
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn cstring(&mut self) -> Result<String> {
+        let Some(end) = self.bytes.iter().position(|b| *b == 0) else {
+            bail!("Unterminated string in pgoutput message");
+        };
+        let s = String::from_utf8(self.bytes(end)?.to_vec())?;
+        self.bytes = &self.bytes[1..];
+        Ok(s)
+    }
+
+    fn tuple(&mut self) -> Result<Vec<TupleValue>> {
+        let count = self.i16()?;
+        (0..count)
+            .map(|_| {
+                Ok(match self.u8()? {
+                    b'n' => TupleValue::Null,
+                    b'u' => TupleValue::Unchanged,
+                    b't' => {
+                        let len = usize::try_from(self.i32()?)?;
+                        TupleValue::Text(String::from_utf8(self.bytes(len)?.to_vec())?)
+                    }
+                    other => bail!("Unsupported tuple value kind {:?}", char::from(other)),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn tuple(values: &[Option<&str>]) -> Vec<u8> {
+        let mut bytes = i16::try_from(values.len()).unwrap().to_be_bytes().to_vec();
+        for value in values {
+            match value {
+                None => bytes.push(b'n'),
+                Some(text) => {
+                    bytes.push(b't');
+                    bytes.extend(i32::try_from(text.len()).unwrap().to_be_bytes());
+                    bytes.extend(text.as_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse_begin() {
+        let mut bytes = vec![b'B'];
+        bytes.extend(7i64.to_be_bytes());
+        bytes.extend(1_000_000i64.to_be_bytes());
+        bytes.extend(42i32.to_be_bytes());
+        assert_eq!(
+            Message::parse(&bytes).unwrap(),
+            Message::Begin {
+                commit_ts: PG_EPOCH_OFFSET_MICROS + 1_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_relation() {
+        let mut bytes = vec![b'R'];
+        bytes.extend(16384u32.to_be_bytes());
+        bytes.extend(b"public\0events\0d");
+        bytes.extend(2i16.to_be_bytes());
+        for name in ["id", "Name"] {
+            bytes.push(1);
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            bytes.extend(23u32.to_be_bytes());
+            bytes.extend((-1i32).to_be_bytes());
+        }
+        assert_eq!(
+            Message::parse(&bytes).unwrap(),
+            Message::Relation(Relation {
+                id: 16384,
+                namespace: "public".to_string(),
+                name: "events".to_string(),
+                columns: vec!["id".to_string(), "Name".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_row_changes() {
+        let mut insert = vec![b'I'];
+        insert.extend(5u32.to_be_bytes());
+        insert.push(b'N');
+        insert.extend(tuple(&[Some("1"), None]));
+        assert_eq!(
+            Message::parse(&insert).unwrap(),
+            Message::Insert {
+                relation: 5,
+                new: vec![TupleValue::Text("1".to_string()), TupleValue::Null],
+            }
+        );
+
+        let mut update = vec![b'U'];
+        update.extend(5u32.to_be_bytes());
+        update.push(b'O');
+        update.extend(tuple(&[Some("1"), Some("old")]));
+        update.push(b'N');
+        update.extend(tuple(&[Some("1"), Some("new")]));
+        assert_eq!(
+            Message::parse(&update).unwrap(),
+            Message::Update {
+                relation: 5,
+                old: Some(vec![
+                    TupleValue::Text("1".to_string()),
+                    TupleValue::Text("old".to_string())
+                ]),
+                new: vec![
+                    TupleValue::Text("1".to_string()),
+                    TupleValue::Text("new".to_string())
+                ],
+            }
+        );
+
+        let mut delete = vec![b'D'];
+        delete.extend(5u32.to_be_bytes());
+        delete.push(b'K');
+        delete.extend(tuple(&[Some("1"), None]));
+        assert!(matches!(
+            Message::parse(&delete).unwrap(),
+            Message::Delete { relation: 5, .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert!(Message::parse(&[]).is_err());
+        assert!(Message::parse(&[b'I', 0, 0]).is_err());
+        let mut insert = vec![b'I'];
+        insert.extend(5u32.to_be_bytes());
+        insert.push(b'N');
+        insert.extend(1i16.to_be_bytes());
+        insert.push(b't');
+        insert.extend(10i32.to_be_bytes());
+        insert.extend(b"short");
+        assert!(Message::parse(&insert).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+///
+/// Validation of user-supplied row filters
+///
+use anyhow::{Result, bail};
+use arrow::datatypes::Schema;
+use sqlparser::{
+    ast::{
+        BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, FunctionArguments, Ident,
+        ObjectNamePart, UnaryOperator, Value,
+    },
+    dialect::PostgreSqlDialect,
+    parser::Parser,
+    tokenizer::Token,
+};
+use std::fmt::Display;
+
+/// Functions a filter may call: deterministic or time-based scalar functions without side
+/// effects.
+const ALLOWED_FUNCTIONS: &[&str] = &[
+    "abs",
+    "ceil",
+    "char_length",
+    "coalesce",
+    "current_date",
+    "current_timestamp",
+    "date_part",
+    "date_trunc",
+    "floor",
+    "greatest",
+    "least",
+    "length",
+    "localtimestamp",
+    "lower",
+    "now",
+    "nullif",
+    "round",
+    "to_date",
+    "to_timestamp",
+    "trim",
+    "upper",
+];
+
+/// A `WHERE` condition that has been checked to be a plain expression over the table's columns.
+///
+/// The condition is parsed rather than interpolated: only column references, literals,
+/// operators, casts and a small set of scalar functions are accepted, and the query is built
+/// from the parsed expression.  Subqueries, parameters and anything after the expression are
+/// rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Predicate {
+    sql: String,
+}
+
+impl Predicate {
+    /// Parses `sql` as a condition on the columns of `schema`.
+    ///
+    /// Unquoted column names are folded to lower case, as Postgres does.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `sql` is not a single expression, references an unknown column or
+    /// uses a construct that is not allowed.
+    pub fn parse(sql: &str, schema: &Schema) -> Result<Self> {
+        let dialect = PostgreSqlDialect {};
+        let mut parser = Parser::new(&dialect).try_with_sql(sql)?;
+        let expr = parser.parse_expr()?;
+        parser.expect_token(&Token::EOF)?;
+        check(&expr, schema)?;
+        Ok(Self {
+            sql: expr.to_string(),
+        })
+    }
+}
+
+/// Formats the validated condition as SQL.
+impl Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.sql)
+    }
+}
+
+fn check(expr: &Expr, schema: &Schema) -> Result<()> {
+    match expr {
+        Expr::Identifier(ident) => check_column(ident, schema),
+        Expr::Value(value) => match &value.value {
+            Value::Placeholder(p) => bail!("Parameters such as {p} are not allowed in a filter"),
+            _ => Ok(()),
+        },
+        Expr::TypedString(_) => Ok(()),
+        Expr::Nested(inner)
+        | Expr::IsNull(inner)
+        | Expr::IsNotNull(inner)
+        | Expr::IsTrue(inner)
+        | Expr::IsNotTrue(inner)
+        | Expr::IsFalse(inner)
+        | Expr::IsNotFalse(inner)
+        | Expr::Cast { expr: inner, .. }
+        | Expr::Extract { expr: inner, .. } => check(inner, schema),
+        Expr::IsDistinctFrom(left, right) | Expr::IsNotDistinctFrom(left, right) => {
+            check(left, schema)?;
+            check(right, schema)
+        }
+        Expr::AtTimeZone {
+            timestamp,
+            time_zone,
+        } => {
+            check(timestamp, schema)?;
+            check(time_zone, schema)
+        }
+        Expr::UnaryOp { op, expr } => match op {
+            UnaryOperator::Not | UnaryOperator::Minus | UnaryOperator::Plus => check(expr, schema),
+            other => bail!("Operator {other} is not allowed in a filter"),
+        },
+        Expr::BinaryOp { left, op, right } => {
+            if !matches!(
+                op,
+                BinaryOperator::And
+                    | BinaryOperator::Or
+                    | BinaryOperator::Eq
+                    | BinaryOperator::NotEq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::LtEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::GtEq
+                    | BinaryOperator::Plus
+                    | BinaryOperator::Minus
+                    | BinaryOperator::Multiply
+                    | BinaryOperator::Divide
+                    | BinaryOperator::Modulo
+                    | BinaryOperator::StringConcat
+                    | BinaryOperator::PGRegexMatch
+                    | BinaryOperator::PGRegexIMatch
+                    | BinaryOperator::PGRegexNotMatch
+                    | BinaryOperator::PGRegexNotIMatch
+            ) {
+                bail!("Operator {op} is not allowed in a filter");
+            }
+            check(left, schema)?;
+            check(right, schema)
+        }
+        Expr::InList { expr, list, .. } => {
+            check(expr, schema)?;
+            list.iter().try_for_each(|item| check(item, schema))
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            check(expr, schema)?;
+            check(low, schema)?;
+            check(high, schema)
+        }
+        Expr::Like {
+            expr, pattern, any, ..
+        }
+        | Expr::ILike {
+            expr, pattern, any, ..
+        } => {
+            if *any {
+                bail!("LIKE ANY is not allowed in a filter");
+            }
+            check(expr, schema)?;
+            check(pattern, schema)
+        }
+        Expr::Interval(interval) => check(&interval.value, schema),
+        Expr::Function(function) => check_function(function, schema),
+        other => bail!("`{other}` is not allowed in a filter"),
+    }
+}
+
+fn check_function(function: &Function, schema: &Schema) -> Result<()> {
+    let name = match function.name.0.as_slice() {
+        [ObjectNamePart::Identifier(name)] => name.value.to_lowercase(),
+        _ => bail!("Function {} is not allowed in a filter", function.name),
+    };
+    if !ALLOWED_FUNCTIONS.contains(&name.as_str())
+        || function.filter.is_some()
+        || function.over.is_some()
+        || !function.within_group.is_empty()
+        || !matches!(function.parameters, FunctionArguments::None)
+    {
+        bail!("Function {} is not allowed in a filter", function.name);
+    }
+    match &function.args {
+        FunctionArguments::None => Ok(()),
+        FunctionArguments::List(list) if list.clauses.is_empty() => {
+            list.args.iter().try_for_each(|arg| match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) => check(arg, schema),
+                _ => bail!("Unsupported argument to {} in a filter", function.name),
+            })
+        }
+        _ => bail!("Unsupported arguments to {} in a filter", function.name),
+    }
+}
+
+fn check_column(ident: &Ident, schema: &Schema) -> Result<()> {
+    let name = if ident.quote_style.is_some() {
+        ident.value.clone()
+    } else {
+        ident.value.to_lowercase()
+    };
+    if schema.field_with_name(&name).is_err() {
+        bail!("Unknown column `{name}` in filter");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::datatypes::{DataType, Field, TimeUnit};
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                true,
+            ),
+            Field::new("Order", DataType::Int32, true),
+        ])
+    }
+
+    #[test]
+    fn test_parse_accepts_plain_conditions() {
+        for sql in [
+            "id > 10",
+            "created_at >= now() - interval '90 days'",
+            r#"name ILIKE 'a%' AND "Order" IN (1, 2, 3)"#,
+            "ID BETWEEN 1 AND 5 OR name IS NULL",
+            "date_trunc('day', created_at) = DATE '2026-01-01'",
+            "lower(name) ~ '^x' AND id::text <> '7'",
+            "created_at AT TIME ZONE 'UTC' > current_date",
+        ] {
+            let predicate = Predicate::parse(sql, &schema());
+            assert!(predicate.is_ok(), "{sql}: {predicate:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_normalizes_sql() {
+        let predicate = Predicate::parse("id>1   and name='x'", &schema()).unwrap();
+        assert_eq!(predicate.to_string(), "id > 1 AND name = 'x'");
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_columns() {
+        assert!(Predicate::parse("missing = 1", &schema()).is_err());
+        // unquoted names fold to lower case, so this is not the "Order" column
+        assert!(Predicate::parse("Order = 1", &schema()).is_err());
+        assert!(Predicate::parse("t.id = 1", &schema()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_injection() {
+        for sql in [
+            "id = 1; DROP TABLE users",
+            "id = 1) OR (1 = 1",
+            "id IN (SELECT id FROM users)",
+            "EXISTS (SELECT 1)",
+            "pg_sleep(10) IS NULL",
+            "id = $1",
+            "count(*) > 1",
+            "now() OVER () IS NULL",
+            "",
+        ] {
+            assert!(Predicate::parse(sql, &schema()).is_err(), "{sql}");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+///
+/// Schema inference for the result of an arbitrary `SELECT`
+///
+use anyhow::{Context, Result, bail};
+use arrow::datatypes::{Field, Schema, SchemaRef};
+use sqlx::{Column, Executor, PgPool, Row, TypeInfo};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::types::pg_to_arrow_type;
+
+/// Type modifier offset Postgres adds to the packed precision and scale of a `numeric`.
+const VARHDRSZ: i32 = 4;
+
+/// Wraps `sql` so that it can be selected from, dropping a trailing `;`.
+///
+/// Postgres only accepts a single `SELECT`, `VALUES` or `TABLE` in a subquery, so statements
+/// that modify data are rejected.
+pub fn subquery(sql: &str) -> String {
+    let sql = sql.trim();
+    let sql = sql.strip_suffix(';').unwrap_or(sql);
+    // the newline ends any trailing `--` comment
+    format!("({sql}\n) AS q")
+}
+
+/// Infers the Arrow schema of the rows returned by `sql` by preparing it, without running it.
+///
+/// The column types are the ones Postgres reports for the prepared statement, which selects
+/// from `sql` as a subquery just as [`crate::stream_query`] does.  Columns read
+/// straight from a table keep the precision and scale of `numeric` columns; computed `numeric`
+/// values are treated as unconstrained.  Every column is nullable, since nullability cannot be
+/// relied upon across joins and expressions.
+///
+/// # Errors
+///
+/// Will return `Err` if `sql` cannot be prepared, takes parameters, or returns no columns or
+/// two columns with the same name.
+pub async fn infer_query_schema(sql: &str, pool: &PgPool) -> Result<SchemaRef> {
+    let describe = pool
+        .describe(&format!("SELECT * FROM {}", subquery(sql)))
+        .await
+        .context("Cannot prepare the query; it must be a single SELECT")?;
+    if describe
+        .parameters()
+        .is_some_and(|params| params.either(<[_]>::len, |count| count) > 0)
+    {
+        bail!("Queries with parameters cannot be synced; inline the values instead");
+    }
+    let columns = describe.columns();
+    check_column_names(columns.iter().map(Column::name))?;
+
+    let oids: Vec<i64> = columns
+        .iter()
+        .filter_map(|column| column.type_info().oid())
+        .map(|oid| i64::from(oid.0))
+        .collect();
+    let types: HashMap<i64, (String, bool)> = sqlx::query(
+        "SELECT oid::int8 AS oid, typname::text AS typname, typcategory = 'A' AS is_array
+         FROM pg_type WHERE oid::int8 = ANY($1)",
+    )
+    .bind(&oids)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.get("oid"), (row.get("typname"), row.get("is_array"))))
+    .collect();
+
+    let mut fields = Vec::with_capacity(columns.len());
+    for column in columns {
+        let oid = column.type_info().oid().map(|oid| i64::from(oid.0));
+        let Some((udt_name, is_array)) = oid.and_then(|oid| types.get(&oid)) else {
+            bail!(
+                "Cannot map column `{}` of type {}",
+                column.name(),
+                column.type_info().name()
+            );
+        };
+        let (precision, scale) = match (column.relation_id(), column.relation_attribute_no()) {
+            (Some(relation), Some(attnum)) if udt_name == "numeric" => {
+                numeric_modifiers(column_typmod(relation.0, attnum, pool).await?)
+            }
+            _ => (None, None),
+        };
+        let data_type = pg_to_arrow_type(
+            if *is_array { "ARRAY" } else { "" },
+            udt_name,
+            precision,
+            scale,
+        );
+        fields.push(Field::new(column.name(), data_type, true));
+    }
+    Ok(Arc::new(Schema::new(fields)))
+}
+
+/// Checks that a query returns columns, with distinct names so that they can be selected.
+pub fn check_column_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            bail!(
+                "The query returns more than one column named `{name}`; give them distinct aliases"
+            );
+        }
+    }
+    if seen.is_empty() {
+        bail!("The query returns no columns");
+    }
+    Ok(())
+}
+
+/// The declared type modifier of a table column, or -1 if it has none.
+async fn column_typmod(relation: u32, attnum: i16, pool: &PgPool) -> Result<i32> {
+    let typmod = sqlx::query_scalar(
+        "SELECT atttypmod FROM pg_attribute WHERE attrelid::int8 = $1 AND attnum = $2",
+    )
+    .bind(i64::from(relation))
+    .bind(attnum)
+    .fetch_optional(pool)
+    .await?;
+    Ok(typmod.unwrap_or(-1))
+}
+
+/// Unpacks the precision and scale of a `numeric` type modifier; -1 means unconstrained.
+const fn numeric_modifiers(typmod: i32) -> (Option<i32>, Option<i32>) {
+    if typmod < VARHDRSZ {
+        return (None, None);
+    }
+    let packed = typmod - VARHDRSZ;
+    // the scale is an 11-bit signed value since Postgres 15
+    let scale = ((packed & 0x7ff) ^ 0x400) - 0x400;
+    (Some((packed >> 16) & 0xffff), Some(scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subquery() {
+        assert_eq!(subquery("  SELECT 1;\n"), "(SELECT 1\n) AS q");
+        assert_eq!(subquery("SELECT ';' -- x"), "(SELECT ';' -- x\n) AS q");
+    }
+
+    #[test]
+    fn test_numeric_modifiers() {
+        // numeric(10, 2), numeric(5) and numeric(3, -2)
+        assert_eq!(
+            numeric_modifiers((10 << 16) + 2 + VARHDRSZ),
+            (Some(10), Some(2))
+        );
+        assert_eq!(numeric_modifiers((5 << 16) + VARHDRSZ), (Some(5), Some(0)));
+        assert_eq!(
+            numeric_modifiers((3 << 16) + (-2 & 0x7ff) + VARHDRSZ),
+            (Some(3), Some(-2))
+        );
+        assert_eq!(numeric_modifiers(-1), (None, None));
+    }
+}
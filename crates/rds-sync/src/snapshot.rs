@@ -0,0 +1,104 @@
+///
+/// Reading several tables from one consistent snapshot
+///
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// A snapshot exported from an open `REPEATABLE READ` transaction.
+///
+/// Connections that import it (see [`crate::StreamOptions::with_snapshot`]) see exactly the
+/// data the exporting transaction sees, so tables read one after another, or on several
+/// workers, are consistent with each other.  The snapshot can only be imported while it is
+/// held, so call [`ExportedSnapshot::release`] once every table has been read.
+#[derive(Debug)]
+pub struct ExportedSnapshot {
+    tx: Transaction<'static, Postgres>,
+    id: String,
+    taken_at: DateTime<Utc>,
+}
+
+impl ExportedSnapshot {
+    /// Opens a transaction on one of `pool`'s connections and exports its snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the transaction cannot be opened or the snapshot exported, e.g. on a
+    /// hot standby, which cannot export snapshots.
+    pub async fn export(pool: &PgPool) -> Result<Self> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        let (id, taken_at): (String, DateTime<Utc>) =
+            sqlx::query_as("SELECT pg_export_snapshot(), now()")
+                .fetch_one(&mut *tx)
+                .await
+                .context("Cannot export a snapshot")?;
+        Ok(Self { tx, id, taken_at })
+    }
+
+    /// The identifier other transactions import the snapshot with.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// When the snapshot was taken, by the server's clock.
+    #[must_use]
+    pub const fn taken_at(&self) -> DateTime<Utc> {
+        self.taken_at
+    }
+
+    /// Ends the exporting transaction; the snapshot can no longer be imported.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the transaction cannot be ended.
+    pub async fn release(self) -> Result<()> {
+        self.tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Opens a transaction on one of `pool`'s connections, reading from `snapshot` if one is
+/// given.  The transaction is rolled back when dropped, which ends it without side effects
+/// since it only reads.
+pub async fn begin(
+    pool: &PgPool,
+    snapshot: Option<&str>,
+) -> Result<Transaction<'static, Postgres>> {
+    let mut tx = pool.begin().await?;
+    if let Some(id) = snapshot {
+        check_snapshot_id(id)?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("SET TRANSACTION SNAPSHOT '{id}'"))
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Cannot import snapshot {id}; is it still exported?"))?;
+    }
+    Ok(tx)
+}
+
+/// Snapshot ids are hexadecimal numbers separated by dashes, which is checked because
+/// `SET TRANSACTION SNAPSHOT` takes no parameters.
+fn check_snapshot_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        bail!("Invalid snapshot id {id:?}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_snapshot_id() {
+        assert!(check_snapshot_id("00000003-0000001B-1").is_ok());
+        assert!(check_snapshot_id("").is_err());
+        assert!(check_snapshot_id("1'; DROP TABLE users; --").is_err());
+    }
+}
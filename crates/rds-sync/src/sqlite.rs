@@ -0,0 +1,591 @@
+///
+/// Extraction from `SQLite` databases, such as those kept on edge devices
+///
+use crate::connector::SourceConnector;
+use crate::decode::date_to_days;
+use crate::query::{check_column_names, subquery};
+use crate::table::quote_ident;
+use crate::{
+    Filter, StreamOptions, TableRef, Watermark, channel_stream, split_field, split_ranges,
+};
+use anyhow::{Context, Result, bail};
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, BooleanArray, Date32Array, Float64Array, Int64Array, RecordBatch,
+        StringArray, TimestampMicrosecondArray,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+};
+use async_trait::async_trait;
+use futures::{StreamExt, stream::BoxStream};
+use sqlx::{
+    Column, Executor, Row, Sqlite, SqlitePool, TypeInfo,
+    query::Query,
+    sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    types::chrono::{NaiveDate, NaiveDateTime},
+};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::{sync::mpsc, task::JoinSet};
+
+/// Maps a column's declared `SQLite` type to an Arrow data type, following `SQLite`'s rules for
+/// the column's type affinity (<https://www.sqlite.org/datatype3.html>).
+///
+/// Within the `NUMERIC` affinity, the conventional `BOOLEAN`, `DATE` and `DATETIME` or
+/// `TIMESTAMP` declarations map to their Arrow types.  Columns without a declared type, which
+/// can hold anything, are read as text.
+#[must_use]
+pub fn sqlite_to_arrow_type(declared: &str) -> DataType {
+    let declared = declared.to_ascii_uppercase();
+    let has = |part: &str| declared.contains(part);
+    if has("INT") {
+        DataType::Int64
+    } else if has("CHAR") || has("CLOB") || has("TEXT") || declared.is_empty() {
+        DataType::Utf8
+    } else if has("BLOB") {
+        DataType::Binary
+    } else if has("REAL") || has("FLOA") || has("DOUB") {
+        DataType::Float64
+    } else if has("BOOL") {
+        DataType::Boolean
+    } else if has("DATETIME") || has("TIMESTAMP") {
+        DataType::Timestamp(TimeUnit::Microsecond, None)
+    } else if has("DATE") {
+        DataType::Date32
+    } else if has("NULL") {
+        // the type of an expression whose result type is unknown
+        DataType::Utf8
+    } else {
+        DataType::Float64
+    }
+}
+
+/// Returns the expression used to select column `name` so that it decodes as `data_type`.
+///
+/// `SQLite` does not enforce column types, so values are converted as `CAST` does; dates and
+/// timestamps are decoded from their stored text or number.
+fn select_expr(name: &str, data_type: &DataType) -> String {
+    let name = quote_ident(name);
+    let cast = match data_type {
+        DataType::Int64 | DataType::Boolean => "INTEGER",
+        DataType::Float64 => "REAL",
+        DataType::Utf8 => "TEXT",
+        DataType::Binary => "BLOB",
+        _ => return name,
+    };
+    format!("CAST({name} AS {cast}) AS {name}")
+}
+
+/// A `SQLite` database, opened read-only.
+#[derive(Debug, Clone)]
+pub struct SqliteConnector {
+    pool: SqlitePool,
+}
+
+impl SqliteConnector {
+    #[must_use]
+    pub const fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Opens the database at `url` (`sqlite://path/to/file.db`) read-only, with up to
+    /// `max_connections` connections.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the URL is invalid or the database cannot be opened.
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.read_only(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        Ok(Self::new(pool))
+    }
+
+    fn stream_from(
+        &self,
+        from: String,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        let pool = self.pool.clone();
+        let options = options.clone();
+        channel_stream(options.workers(), move |tx| async move {
+            read_ranges(&from, schema, &pool, &options, &tx).await
+        })
+    }
+}
+
+#[async_trait]
+impl SourceConnector for SqliteConnector {
+    async fn resolve_table(&self, table: &TableRef) -> Result<TableRef> {
+        let schema = table.schema().unwrap_or("main");
+        let name: Option<String> = sqlx::query_scalar(&format!(
+            "SELECT name FROM {}.sqlite_master
+             WHERE type IN ('table', 'view') AND name = ?1 COLLATE NOCASE",
+            quote_ident(schema)
+        ))
+        .bind(table.name())
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(name) = name else {
+            bail!("Table {table} does not exist");
+        };
+        Ok(TableRef::new(Some(schema), &name))
+    }
+
+    async fn list_tables(&self, schema: &str, views: bool) -> Result<Vec<TableRef>> {
+        let types = if views { "'table', 'view'" } else { "'table'" };
+        let names: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT name FROM {}.sqlite_master
+             WHERE type IN ({types}) AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\'
+             ORDER BY name",
+            quote_ident(schema)
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .with_context(|| format!("Cannot list the tables of database {schema}"))?;
+        Ok(names
+            .iter()
+            .map(|name| TableRef::new(Some(schema), name))
+            .collect())
+    }
+
+    async fn infer_schema(&self, table: &TableRef) -> Result<SchemaRef> {
+        let rows = sqlx::query(
+            r#"SELECT name, type, "notnull" FROM pragma_table_info(?1, ?2) ORDER BY cid"#,
+        )
+        .bind(table.name())
+        .bind(table.schema().unwrap_or("main"))
+        .fetch_all(&self.pool)
+        .await?;
+        if rows.is_empty() {
+            bail!("Table {table} has no columns");
+        }
+        let fields: Vec<Field> = rows
+            .iter()
+            .map(|row| {
+                let declared: String = row.get("type");
+                let not_null: bool = row.get("notnull");
+                Field::new(
+                    row.get::<String, _>("name"),
+                    sqlite_to_arrow_type(&declared),
+                    !not_null,
+                )
+            })
+            .collect();
+        Ok(Arc::new(Schema::new(fields)))
+    }
+
+    async fn infer_query_schema(&self, sql: &str) -> Result<SchemaRef> {
+        let describe = self
+            .pool
+            .describe(&format!("SELECT * FROM {}", subquery(sql)))
+            .await
+            .context("Cannot prepare the query; it must be a single SELECT")?;
+        // the subquery renames duplicate columns ("id:1"), so check the names the query gives
+        let names = self.pool.describe(sql.trim().trim_end_matches(';')).await?;
+        check_column_names(names.columns().iter().map(Column::name))?;
+        let columns = describe.columns();
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|column| {
+                let data_type = sqlite_to_arrow_type(column.type_info().name());
+                Field::new(column.name(), data_type, true)
+            })
+            .collect();
+        Ok(Arc::new(Schema::new(fields)))
+    }
+
+    async fn detect_split_column(
+        &self,
+        table: &TableRef,
+        schema: &Schema,
+    ) -> Result<Option<String>> {
+        let key: Option<String> = sqlx::query_scalar(
+            "SELECT name FROM pragma_table_info(?1, ?2) WHERE pk > 0 ORDER BY pk LIMIT 1",
+        )
+        .bind(table.name())
+        .bind(table.schema().unwrap_or("main"))
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(key.filter(|column| {
+            schema
+                .field_with_name(column)
+                .is_ok_and(|field| Watermark::supports(field.data_type()))
+        }))
+    }
+
+    fn stream_table(
+        &self,
+        table: &TableRef,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        self.stream_from(table.to_string(), schema, options)
+    }
+
+    fn stream_query(
+        &self,
+        sql: &str,
+        schema: SchemaRef,
+        options: &StreamOptions,
+    ) -> BoxStream<'static, Result<RecordBatch>> {
+        self.stream_from(subquery(sql), schema, options)
+    }
+}
+
+/// Reads `from` with one query per range of the split column, or a single query.
+async fn read_ranges(
+    from: &str,
+    schema: SchemaRef,
+    pool: &SqlitePool,
+    options: &StreamOptions,
+    tx: &mpsc::Sender<Result<RecordBatch>>,
+) -> Result<()> {
+    if options.copy {
+        bail!("COPY is only available for Postgres sources");
+    }
+    if options.snapshot.is_some() {
+        bail!("Exported snapshots are only available for Postgres sources");
+    }
+    let filter = options.filter(false);
+    let mut ranges = vec![filter.clone()];
+    if let Some((column, workers)) = &options.split {
+        let field = split_field(&schema, column)?;
+        let column = quote_ident(column);
+        let bounds = format!(
+            "SELECT min({column}) AS lo, max({column}) AS hi FROM {from}{}",
+            filter.sql()
+        );
+        let row = bind(sqlx::query(&bounds), &filter).fetch_one(pool).await?;
+        let lo = decode_watermark(&row, "lo", field.data_type())?;
+        let hi = decode_watermark(&row, "hi", field.data_type())?;
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            ranges = split_ranges(&filter, &column, &lo, &hi, *workers);
+        }
+    }
+
+    let select_clause = schema
+        .fields()
+        .iter()
+        .map(|f| select_expr(f.name(), f.data_type()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut workers = JoinSet::new();
+    for range in ranges {
+        let query = format!("SELECT {select_clause} FROM {from}{}", range.sql());
+        workers.spawn(fetch_batches(
+            query,
+            range,
+            schema.clone(),
+            pool.clone(),
+            options.batch_size,
+            tx.clone(),
+        ));
+    }
+    while let Some(finished) = workers.join_next().await {
+        finished?;
+    }
+    Ok(())
+}
+
+/// Runs `query`, sending its rows as batches of at most `batch_size` rows.
+async fn fetch_batches(
+    query: String,
+    filter: Filter,
+    schema: SchemaRef,
+    pool: SqlitePool,
+    batch_size: usize,
+    tx: mpsc::Sender<Result<RecordBatch>>,
+) {
+    let mut chunks = bind(sqlx::query(&query), &filter)
+        .fetch(&pool)
+        .chunks(batch_size);
+    while let Some(chunk) = chunks.next().await {
+        let batch = chunk
+            .into_iter()
+            .collect::<sqlx::Result<Vec<SqliteRow>>>()
+            .map_err(anyhow::Error::from)
+            .and_then(|rows| rows_to_batch(&rows, &schema));
+        let failed = batch.is_err();
+        // stop when the receiver is gone or after reporting the first error
+        if tx.send(batch).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Binds the filter's parameters in order.
+fn bind<'q>(
+    mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    filter: &Filter,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    for param in &filter.params {
+        query = match param.clone() {
+            Watermark::Int(v) => query.bind(v),
+            Watermark::Timestamp(v) => query.bind(v),
+            Watermark::TimestampTz(v) => query.bind(v),
+            Watermark::Date(v) => query.bind(v),
+        };
+    }
+    query
+}
+
+fn decode_watermark(
+    row: &SqliteRow,
+    name: &str,
+    data_type: &DataType,
+) -> Result<Option<Watermark>> {
+    Ok(match data_type {
+        DataType::Int64 => row.try_get::<Option<i64>, _>(name)?.map(Watermark::Int),
+        DataType::Timestamp(_, None) => row
+            .try_get_unchecked::<Option<NaiveDateTime>, _>(name)?
+            .map(Watermark::Timestamp),
+        DataType::Date32 => row
+            .try_get_unchecked::<Option<NaiveDate>, _>(name)?
+            .map(Watermark::Date),
+        other => bail!("Cannot use a column of type {other} as a watermark"),
+    })
+}
+
+/// Converts a slice of `SQLite` rows into a `RecordBatch` following `schema`.
+fn rows_to_batch(rows: &[SqliteRow], schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| -> Result<ArrayRef> {
+            Ok(match field.data_type() {
+                DataType::Int64 => Arc::new(
+                    rows.iter()
+                        .map(|row| row.try_get::<Option<i64>, _>(i))
+                        .collect::<sqlx::Result<Int64Array>>()?,
+                ),
+                DataType::Float64 => Arc::new(
+                    rows.iter()
+                        .map(|row| row.try_get::<Option<f64>, _>(i))
+                        .collect::<sqlx::Result<Float64Array>>()?,
+                ),
+                DataType::Boolean => Arc::new(
+                    rows.iter()
+                        .map(|row| row.try_get::<Option<bool>, _>(i))
+                        .collect::<sqlx::Result<BooleanArray>>()?,
+                ),
+                DataType::Utf8 => Arc::new(
+                    rows.iter()
+                        .map(|row| row.try_get::<Option<String>, _>(i))
+                        .collect::<sqlx::Result<StringArray>>()?,
+                ),
+                DataType::Binary => Arc::new(
+                    rows.iter()
+                        .map(|row| row.try_get::<Option<Vec<u8>>, _>(i))
+                        .collect::<sqlx::Result<BinaryArray>>()?,
+                ),
+                DataType::Timestamp(TimeUnit::Microsecond, None) => Arc::new(
+                    rows.iter()
+                        .map(|row| {
+                            let value = row.try_get_unchecked::<Option<NaiveDateTime>, _>(i)?;
+                            Ok(value.map(|v| v.and_utc().timestamp_micros()))
+                        })
+                        .collect::<sqlx::Result<TimestampMicrosecondArray>>()?,
+                ),
+                DataType::Date32 => Arc::new(
+                    rows.iter()
+                        .map(|row| {
+                            let value = row.try_get_unchecked::<Option<NaiveDate>, _>(i)?;
+                            Ok(value.map(date_to_days))
+                        })
+                        .collect::<sqlx::Result<Date32Array>>()?,
+                ),
+                other => bail!("Unsupported data type for '{}': {other:?}", field.name()),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{Float64Type, Int64Type, TimestampMicrosecondType};
+    use futures::TryStreamExt;
+
+    async fn connector() -> SqliteConnector {
+        // a single connection, since every in-memory connection has its own database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE Readings (id INTEGER PRIMARY KEY, sensor TEXT NOT NULL, value REAL,
+                 ok BOOLEAN, taken_at DATETIME, payload BLOB, note);
+             INSERT INTO Readings VALUES
+                 (1, 'a', 1.5, 1, '2026-10-01 12:00:00', x'00ff', NULL),
+                 (2, 'b', NULL, 0, '2026-10-01T13:30:00.25', NULL, 7),
+                 (3, 'a', 3, NULL, NULL, NULL, 'x'),
+                 (4, 'c', '4.5', 1, 1790000000, NULL, NULL);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        SqliteConnector::new(pool)
+    }
+
+    #[test]
+    fn test_sqlite_to_arrow_type() {
+        assert_eq!(sqlite_to_arrow_type("INTEGER"), DataType::Int64);
+        assert_eq!(sqlite_to_arrow_type("unsigned big int"), DataType::Int64);
+        assert_eq!(sqlite_to_arrow_type("VARCHAR(20)"), DataType::Utf8);
+        assert_eq!(sqlite_to_arrow_type(""), DataType::Utf8);
+        assert_eq!(sqlite_to_arrow_type("BLOB"), DataType::Binary);
+        assert_eq!(sqlite_to_arrow_type("double precision"), DataType::Float64);
+        assert_eq!(sqlite_to_arrow_type("DECIMAL(10,5)"), DataType::Float64);
+        assert_eq!(sqlite_to_arrow_type("BOOLEAN"), DataType::Boolean);
+        assert_eq!(sqlite_to_arrow_type("DATE"), DataType::Date32);
+        assert_eq!(
+            sqlite_to_arrow_type("timestamp"),
+            DataType::Timestamp(TimeUnit::Microsecond, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_tables() {
+        let connector = connector().await;
+        sqlx::raw_sql(
+            "CREATE TABLE Sensors (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT);
+             CREATE VIEW latest AS SELECT sensor, max(taken_at) AS taken_at FROM Readings GROUP BY sensor;",
+        )
+        .execute(&connector.pool)
+        .await
+        .unwrap();
+        let names = |tables: Vec<TableRef>| -> Vec<String> {
+            tables.iter().map(|t| t.name().to_string()).collect()
+        };
+        // AUTOINCREMENT adds sqlite_sequence, which is left out
+        let tables = connector.list_tables("main", false).await.unwrap();
+        assert_eq!(names(tables), ["Readings", "Sensors"]);
+        let tables = connector.list_tables("main", true).await.unwrap();
+        assert_eq!(names(tables), ["Readings", "Sensors", "latest"]);
+        assert!(connector.list_tables("nope", false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_infer_schema() {
+        let connector = connector().await;
+        let table = connector
+            .resolve_table(&TableRef::parse("readings").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(table.to_string(), r#""main"."Readings""#);
+        let schema = connector.infer_schema(&table).await.unwrap();
+        let types: Vec<(&str, &DataType, bool)> = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type(), f.is_nullable()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("id", &DataType::Int64, true),
+                ("sensor", &DataType::Utf8, false),
+                ("value", &DataType::Float64, true),
+                ("ok", &DataType::Boolean, true),
+                (
+                    "taken_at",
+                    &DataType::Timestamp(TimeUnit::Microsecond, None),
+                    true
+                ),
+                ("payload", &DataType::Binary, true),
+                ("note", &DataType::Utf8, true),
+            ]
+        );
+        assert_eq!(
+            connector
+                .detect_split_column(&table, &schema)
+                .await
+                .unwrap(),
+            Some("id".to_string())
+        );
+        assert!(
+            connector
+                .resolve_table(&TableRef::parse("missing").unwrap())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_table() {
+        let connector = connector().await;
+        let table = TableRef::new(Some("main"), "Readings");
+        let schema = connector.infer_schema(&table).await.unwrap();
+        let batches: Vec<RecordBatch> = connector
+            .stream_table(&table, schema, &StreamOptions::new(3))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        let batch = &batches[0];
+        let values = batch.column(2).as_primitive::<Float64Type>();
+        assert!(values.is_null(1));
+        assert!((values.value(2) - 3.0).abs() < f64::EPSILON);
+        let taken_at = batch.column(4).as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(taken_at.value(0), 1_790_856_000_000_000);
+        assert_eq!(taken_at.value(1), 1_790_861_400_250_000);
+        assert_eq!(batch.column(5).as_binary::<i32>().value(0), [0, 255]);
+        assert_eq!(batch.column(6).as_string::<i32>().value(1), "7");
+        assert!(
+            (batches[1].column(2).as_primitive::<Float64Type>().value(0) - 4.5).abs()
+                < f64::EPSILON
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_options() {
+        let connector = connector().await;
+        let table = TableRef::new(Some("main"), "Readings");
+        let table_schema = connector.infer_schema(&table).await.unwrap();
+        let schema = crate::project_schema(&table_schema, &["id".to_string()], &[]).unwrap();
+        let predicate = crate::Predicate::parse("sensor <> 'b'", &table_schema).unwrap();
+        let options = StreamOptions::default()
+            .with_predicate(predicate)
+            .with_watermark("id", Watermark::Int(1))
+            .with_split("id", 2);
+        let batches: Vec<RecordBatch> = connector
+            .stream_table(&table, schema, &options)
+            .try_collect()
+            .await
+            .unwrap();
+        let mut ids: Vec<i64> = batches
+            .iter()
+            .flat_map(|b| b.column(0).as_primitive::<Int64Type>().values().to_vec())
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, [3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_query() {
+        let connector = connector().await;
+        let sql = "SELECT sensor, count(*) AS readings FROM Readings GROUP BY sensor;";
+        let schema = connector.infer_query_schema(sql).await.unwrap();
+        assert_eq!(schema.field(0).data_type(), &DataType::Utf8);
+        let batches: Vec<RecordBatch> = connector
+            .stream_query(sql, schema, &StreamOptions::default())
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(batches[0].num_rows(), 3);
+        assert!(
+            connector
+                .infer_query_schema("SELECT id, id FROM Readings")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,260 @@
+///
+/// Parsing, quoting and resolution of Postgres table names
+///
+use anyhow::{Result, bail};
+use sqlx::{PgPool, Row};
+use std::fmt::Display;
+
+/// A table name, optionally qualified with the Postgres schema it lives in.
+///
+/// Names follow Postgres identifier rules: unquoted parts are folded to lower case and
+/// double-quoted parts are kept verbatim, so `Sales."Orders"` names the `Orders` table in the
+/// `sales` schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRef {
+    schema: Option<String>,
+    name: String,
+}
+
+impl TableRef {
+    /// Creates a reference from already-unquoted identifiers.
+    #[must_use]
+    pub fn new(schema: Option<&str>, name: &str) -> Self {
+        Self {
+            schema: schema.map(ToString::to_string),
+            name: name.to_string(),
+        }
+    }
+
+    /// Parses `table`, `schema.table` or their double-quoted forms.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the name is empty, has unbalanced quotes or more than two parts.
+    pub fn parse(s: &str) -> Result<Self> {
+        let parts = split_identifiers(s)?;
+        match parts.as_slice() {
+            [name] => Ok(Self::new(None, name)),
+            [schema, name] => Ok(Self::new(Some(schema), name)),
+            _ => bail!("Invalid table name `{s}`: expected `table` or `schema.table`"),
+        }
+    }
+
+    /// The schema, if the reference is qualified.
+    #[must_use]
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    /// The unquoted table name, without its schema.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Formats the reference as a quoted SQL identifier, safe to interpolate into a query.
+impl Display for TableRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(schema) = &self.schema {
+            write!(f, "{}.", quote_ident(schema))?;
+        }
+        write!(f, "{}", quote_ident(&self.name))
+    }
+}
+
+/// Quotes an identifier so that mixed-case, reserved or otherwise unusual names survive
+/// being interpolated into SQL.
+#[must_use]
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+fn split_identifiers(s: &str) -> Result<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut chars = s.trim().chars().peekable();
+
+    loop {
+        let mut part = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        part.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => part.push(c),
+                    None => bail!("Invalid table name `{s}`: unterminated quoted identifier"),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != '.') {
+                if c == '"' {
+                    bail!("Invalid table name `{s}`: unexpected quote");
+                }
+                part.push(c);
+            }
+            part = part.to_lowercase();
+        }
+        if part.is_empty() {
+            bail!("Invalid table name `{s}`: empty identifier");
+        }
+        parts.push(part);
+
+        match chars.next() {
+            None => return Ok(parts),
+            Some('.') => {}
+            Some(_) => bail!("Invalid table name `{s}`: expected `.` after quoted identifier"),
+        }
+    }
+}
+
+/// Resolves `table` to the single schema that contains it.
+///
+/// An unqualified name must exist in exactly one schema visible to the current user.
+///
+/// # Errors
+///
+/// Will return `Err` if the table does not exist or, when unqualified, exists in more than one
+/// schema.
+pub async fn resolve_table(table: &TableRef, pool: &PgPool) -> Result<TableRef> {
+    // materialized views are not part of the information schema
+    let query = r"
+        SELECT table_schema::text
+        FROM information_schema.tables
+        WHERE table_name = $1 AND ($2::text IS NULL OR table_schema = $2)
+        UNION ALL
+        SELECT schemaname::text
+        FROM pg_matviews
+        WHERE matviewname = $1 AND ($2::text IS NULL OR schemaname = $2)
+        ORDER BY 1
+    ";
+
+    let schemas: Vec<String> = sqlx::query(query)
+        .bind(table.name())
+        .bind(table.schema())
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("table_schema"))
+        .collect();
+
+    match schemas.as_slice() {
+        [] => bail!("Table {table} does not exist or is not visible to the current user"),
+        [schema] => Ok(TableRef::new(Some(schema), table.name())),
+        _ => bail!(
+            "Table {table} is ambiguous: it exists in schemas {}; qualify it as schema.table",
+            schemas.join(", ")
+        ),
+    }
+}
+
+/// The tables in `schema` the current user can read, by name, optionally with its views and
+/// materialized views.  Partitions are left out since their partitioned table reads them.
+///
+/// # Errors
+///
+/// Will return `Err` if the schema does not exist.
+pub async fn list_tables(schema: &str, views: bool, pool: &PgPool) -> Result<Vec<TableRef>> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)")
+            .bind(schema)
+            .fetch_one(pool)
+            .await?;
+    if !exists {
+        bail!("Schema {} does not exist", quote_ident(schema));
+    }
+    let query = r"
+        SELECT c.relname::text
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE n.nspname = $1
+          AND (c.relkind IN ('r', 'p') OR ($2 AND c.relkind IN ('v', 'm')))
+          AND NOT c.relispartition
+          AND has_table_privilege(c.oid, 'SELECT')
+        ORDER BY c.relname
+    ";
+    let names: Vec<String> = sqlx::query_scalar(query)
+        .bind(schema)
+        .bind(views)
+        .fetch_all(pool)
+        .await?;
+    Ok(names
+        .iter()
+        .map(|name| TableRef::new(Some(schema), name))
+        .collect())
+}
+
+/// The columns of `table`'s primary key, in key order, or nothing if it has none.
+///
+/// # Errors
+///
+/// Will return `Err` if the table does not exist.
+pub async fn primary_key(table: &TableRef, pool: &PgPool) -> Result<Vec<String>> {
+    let query = r"
+        SELECT a.attname::text AS column_name
+        FROM pg_index i
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY (i.indkey)
+        WHERE i.indrelid = $1::text::regclass AND i.indisprimary
+        ORDER BY array_position(i.indkey::int2[], a.attnum)
+    ";
+
+    Ok(sqlx::query(query)
+        .bind(table.to_string())
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.get("column_name"))
+        .collect())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unqualified() {
+        let table = TableRef::parse("Users").unwrap();
+        assert_eq!(table.schema(), None);
+        assert_eq!(table.name(), "users");
+    }
+
+    #[test]
+    fn test_parse_qualified() {
+        let table = TableRef::parse("audit.users").unwrap();
+        assert_eq!(table.schema(), Some("audit"));
+        assert_eq!(table.name(), "users");
+    }
+
+    #[test]
+    fn test_parse_quoted() {
+        let table = TableRef::parse(r#""My Schema"."Order.Items""#).unwrap();
+        assert_eq!(table.schema(), Some("My Schema"));
+        assert_eq!(table.name(), "Order.Items");
+
+        let table = TableRef::parse(r#"sales."Say ""hi""""#).unwrap();
+        assert_eq!(table.schema(), Some("sales"));
+        assert_eq!(table.name(), r#"Say "hi""#);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(TableRef::parse("").is_err());
+        assert!(TableRef::parse("a..b").is_err());
+        assert!(TableRef::parse("db.schema.table").is_err());
+        assert!(TableRef::parse(r#""unterminated"#).is_err());
+        assert!(TableRef::parse(r#""a"b"#).is_err());
+    }
+
+    #[test]
+    fn test_display_quotes_identifiers() {
+        assert_eq!(TableRef::new(None, "users").to_string(), r#""users""#);
+        assert_eq!(
+            TableRef::new(Some("My Schema"), r#"Say "hi""#).to_string(),
+            r#""My Schema"."Say ""hi""""#
+        );
+    }
+}
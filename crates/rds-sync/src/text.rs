@@ -0,0 +1,301 @@
+///
+/// Conversion of Postgres text-format values into Arrow arrays
+///
+use crate::decode::{date_to_days, time_to_micros};
+use crate::types::{parse_decimal128, parse_decimal256};
+use anyhow::{Context, Result, bail};
+use arrow::{
+    array::{
+        ArrayRef, ArrowPrimitiveType, BinaryBuilder, BooleanBuilder, ListArray, PrimitiveArray,
+        PrimitiveBuilder, StringArray,
+    },
+    buffer::{NullBuffer, OffsetBuffer},
+    datatypes::{
+        DataType, Date32Type, Decimal128Type, Decimal256Type, Field, Float32Type, Float64Type,
+        Int16Type, Int32Type, Int64Type, Time64MicrosecondType, TimeUnit, TimestampMicrosecondType,
+    },
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use std::sync::Arc;
+
+/// Builds an array of `field`'s type from values in Postgres' text output format, as sent by
+/// logical replication.
+///
+/// # Errors
+///
+/// Will return `Err` if a value cannot be parsed as the field's type.
+pub fn text_to_array(values: &[Option<&str>], field: &Field) -> Result<ArrayRef> {
+    let name = field.name();
+    let array: ArrayRef = match field.data_type() {
+        DataType::Utf8 => Arc::new(StringArray::from(values.to_vec())),
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::with_capacity(values.len());
+            for value in values {
+                builder.append_option(value.map(parse_bool).transpose()?);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => {
+            let mut builder = BinaryBuilder::with_capacity(values.len(), values.len() * 16);
+            for value in values {
+                builder.append_option(value.map(parse_bytea).transpose()?);
+            }
+            Arc::new(builder.finish())
+        }
+        DataType::Int16 => Arc::new(primitive::<Int16Type>(values, field, |v| Ok(v.parse()?))?),
+        DataType::Int32 => Arc::new(primitive::<Int32Type>(values, field, |v| Ok(v.parse()?))?),
+        DataType::Int64 => Arc::new(primitive::<Int64Type>(values, field, |v| Ok(v.parse()?))?),
+        DataType::Float32 => Arc::new(primitive::<Float32Type>(values, field, |v| Ok(v.parse()?))?),
+        DataType::Float64 => Arc::new(primitive::<Float64Type>(values, field, |v| Ok(v.parse()?))?),
+        DataType::Date32 => Arc::new(primitive::<Date32Type>(values, field, |v| {
+            Ok(date_to_days(NaiveDate::parse_from_str(v, "%Y-%m-%d")?))
+        })?),
+        DataType::Time64(TimeUnit::Microsecond) => {
+            Arc::new(primitive::<Time64MicrosecondType>(values, field, |v| {
+                Ok(time_to_micros(NaiveTime::parse_from_str(v, "%H:%M:%S%.f")?))
+            })?)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            Arc::new(primitive::<TimestampMicrosecondType>(values, field, |v| {
+                Ok(NaiveDateTime::parse_from_str(v, "%Y-%m-%d %H:%M:%S%.f")?
+                    .and_utc()
+                    .timestamp_micros())
+            })?)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => {
+            Arc::new(primitive::<TimestampMicrosecondType>(values, field, |v| {
+                Ok(DateTime::parse_from_str(v, "%Y-%m-%d %H:%M:%S%.f%#z")?.timestamp_micros())
+            })?)
+        }
+        DataType::Decimal128(precision, scale) => {
+            let array =
+                primitive::<Decimal128Type>(values, field, |v| parse_decimal128(v, *scale))?;
+            array.validate_decimal_precision(*precision)?;
+            Arc::new(array)
+        }
+        DataType::Decimal256(precision, scale) => {
+            let array =
+                primitive::<Decimal256Type>(values, field, |v| parse_decimal256(v, *scale))?;
+            array.validate_decimal_precision(*precision)?;
+            Arc::new(array)
+        }
+        DataType::List(item) => list(values, item)?,
+        other => bail!("Unsupported data type for '{name}': {other:?}"),
+    };
+    Ok(array)
+}
+
+fn primitive<T: ArrowPrimitiveType>(
+    values: &[Option<&str>],
+    field: &Field,
+    parse: impl Fn(&str) -> Result<T::Native>,
+) -> Result<PrimitiveArray<T>> {
+    let mut builder = PrimitiveBuilder::<T>::with_capacity(values.len())
+        .with_data_type(field.data_type().clone());
+    for value in values {
+        let parsed = value
+            .map(|v| {
+                parse(v).with_context(|| format!("Invalid value {v:?} for '{}'", field.name()))
+            })
+            .transpose()?;
+        builder.append_option(parsed);
+    }
+    Ok(builder.finish())
+}
+
+/// Parses one-dimensional array literals such as `{1,NULL,"a b"}` into a `List`.
+fn list(values: &[Option<&str>], item: &Arc<Field>) -> Result<ArrayRef> {
+    let mut elements: Vec<Option<String>> = Vec::new();
+    let mut lengths = Vec::with_capacity(values.len());
+    for value in values {
+        match value {
+            Some(literal) => {
+                let parsed = parse_array_literal(literal)?;
+                lengths.push(parsed.len());
+                elements.extend(parsed);
+            }
+            None => lengths.push(0),
+        }
+    }
+    let elements: Vec<Option<&str>> = elements.iter().map(Option::as_deref).collect();
+    let child = text_to_array(&elements, item)?;
+    let nulls = NullBuffer::from(values.iter().map(Option::is_some).collect::<Vec<_>>());
+    Ok(Arc::new(ListArray::try_new(
+        item.clone(),
+        OffsetBuffer::from_lengths(lengths),
+        child,
+        Some(nulls),
+    )?))
+}
+
+fn parse_array_literal(literal: &str) -> Result<Vec<Option<String>>> {
+    let Some(body) = literal
+        .strip_prefix('{')
+        .and_then(|rest| rest.strip_suffix('}'))
+    else {
+        bail!("Invalid array literal {literal:?}");
+    };
+    let mut elements = Vec::new();
+    if body.is_empty() {
+        return Ok(elements);
+    }
+    let mut chars = body.chars().peekable();
+    loop {
+        let element = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut element = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => element.extend(chars.next()),
+                    Some('"') => break,
+                    Some(c) => element.push(c),
+                    None => bail!("Unterminated element in array literal {literal:?}"),
+                }
+            }
+            Some(element)
+        } else {
+            let mut element = String::new();
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                if c == '{' {
+                    bail!("Multi-dimensional arrays are not supported: {literal:?}");
+                }
+                element.push(c);
+            }
+            (!element.eq_ignore_ascii_case("NULL")).then_some(element)
+        };
+        elements.push(element);
+        match chars.next() {
+            None => return Ok(elements),
+            Some(',') => {}
+            Some(c) => bail!("Unexpected {c:?} in array literal {literal:?}"),
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "t" => Ok(true),
+        "f" => Ok(false),
+        other => bail!("Invalid boolean {other:?}"),
+    }
+}
+
+/// Decodes `bytea` in the default `hex` output format.
+fn parse_bytea(value: &str) -> Result<Vec<u8>> {
+    let Some(hex) = value.strip_prefix("\\x") else {
+        bail!("Unsupported bytea format; set bytea_output = 'hex'");
+    };
+    if hex.len() % 2 != 0 {
+        bail!("Invalid hex bytea {value:?}");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .with_context(|| format!("Invalid hex bytea {value:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::i256;
+
+    fn field(data_type: DataType) -> Field {
+        Field::new("c", data_type, true)
+    }
+
+    #[test]
+    fn test_scalars() {
+        let array = text_to_array(&[Some("-7"), None], &field(DataType::Int32)).unwrap();
+        assert_eq!(array.as_primitive::<Int32Type>().value(0), -7);
+        assert!(array.is_null(1));
+
+        let array = text_to_array(&[Some("t"), Some("f")], &field(DataType::Boolean)).unwrap();
+        assert!(array.as_boolean().value(0));
+        assert!(!array.as_boolean().value(1));
+
+        let array = text_to_array(&[Some("NaN")], &field(DataType::Float64)).unwrap();
+        assert!(array.as_primitive::<Float64Type>().value(0).is_nan());
+
+        let array = text_to_array(&[Some(r"\x00ff")], &field(DataType::Binary)).unwrap();
+        assert_eq!(array.as_binary::<i32>().value(0), &[0, 255]);
+
+        assert!(text_to_array(&[Some("x")], &field(DataType::Int64)).is_err());
+    }
+
+    #[test]
+    fn test_dates_and_times() {
+        let array = text_to_array(&[Some("1970-01-11")], &field(DataType::Date32)).unwrap();
+        assert_eq!(array.as_primitive::<Date32Type>().value(0), 10);
+
+        let array = text_to_array(
+            &[Some("00:00:01.5")],
+            &field(DataType::Time64(TimeUnit::Microsecond)),
+        )
+        .unwrap();
+        assert_eq!(
+            array.as_primitive::<Time64MicrosecondType>().value(0),
+            1_500_000
+        );
+
+        let array = text_to_array(
+            &[Some("1970-01-01 00:00:02")],
+            &field(DataType::Timestamp(TimeUnit::Microsecond, None)),
+        )
+        .unwrap();
+        assert_eq!(
+            array.as_primitive::<TimestampMicrosecondType>().value(0),
+            2_000_000
+        );
+
+        let tz = DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()));
+        let array =
+            text_to_array(&[Some("1970-01-01 02:00:00.25+02")], &field(tz.clone())).unwrap();
+        assert_eq!(array.data_type(), &tz);
+        assert_eq!(
+            array.as_primitive::<TimestampMicrosecondType>().value(0),
+            250_000
+        );
+    }
+
+    #[test]
+    fn test_decimals() {
+        let array = text_to_array(&[Some("12.345")], &field(DataType::Decimal128(10, 2))).unwrap();
+        assert_eq!(array.as_primitive::<Decimal128Type>().value(0), 1235);
+
+        let array = text_to_array(&[Some("1")], &field(DataType::Decimal256(50, 0))).unwrap();
+        assert_eq!(
+            array.as_primitive::<Decimal256Type>().value(0),
+            i256::from(1)
+        );
+
+        assert!(text_to_array(&[Some("1000")], &field(DataType::Decimal128(3, 0))).is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let item = Arc::new(Field::new("item", DataType::Utf8, true));
+        let array = text_to_array(
+            &[Some(r#"{a,NULL,"b,c","d\"e"}"#), None, Some("{}")],
+            &field(DataType::List(item)),
+        )
+        .unwrap();
+        let list = array.as_list::<i32>();
+        assert!(list.is_null(1));
+        assert_eq!(list.value_length(2), 0);
+        let first = list.value(0);
+        let first = first.as_string::<i32>();
+        assert_eq!(first.value(0), "a");
+        assert!(first.is_null(1));
+        assert_eq!(first.value(2), "b,c");
+        assert_eq!(first.value(3), "d\"e");
+
+        let ints = Arc::new(Field::new("item", DataType::Int32, true));
+        assert!(text_to_array(&[Some("{{1},{2}}")], &field(DataType::List(ints))).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::table::quote_ident;
+///
+/// Mapping between Postgres column types and Arrow data types
+///
+use anyhow::{Result, bail};
+use arrow::datatypes::{
+    DECIMAL128_MAX_PRECISION, DECIMAL256_MAX_PRECISION, DataType, Field, TimeUnit, i256,
+};
+use std::sync::Arc;
+
+/// Precision used for `numeric` columns declared without a precision.
+///
+/// They are read as `Decimal256`, so that values below 10^56 fit.  Larger values fail the
+/// sync; declare such columns with a precision over 76 to keep them as text.
+pub const UNCONSTRAINED_NUMERIC_PRECISION: u8 = DECIMAL256_MAX_PRECISION;
+
+/// Scale used for `numeric` columns declared without a scale.  Values with more fractional
+/// digits are rounded half away from zero, as Postgres itself does when casting.
+pub const UNCONSTRAINED_NUMERIC_SCALE: i8 = 20;
+
+/// Time zone attached to `timestamp with time zone` columns.  Postgres stores these as UTC
+/// instants, so no conversion is needed when decoding.
+pub const TIMESTAMPTZ_TIME_ZONE: &str = "UTC";
+
+/// Maps a column from `information_schema.columns` to an Arrow data type.
+///
+/// `data_type` is the SQL standard type name (used to detect arrays and user-defined types),
+/// `udt_name` is the Postgres internal type name (`int4`, `_text`, an enum's name, ...) and
+/// `precision`/`scale` are the declared `numeric` modifiers, if any.
+///
+/// Types without a native Arrow counterpart that Parquet can store (`uuid`, `json`, `interval`,
+/// enums, `inet`, ...) are mapped to `Utf8` and selected through a `::text` cast, so any column
+/// can be synced.
+#[must_use]
+pub fn pg_to_arrow_type(
+    data_type: &str,
+    udt_name: &str,
+    precision: Option<i32>,
+    scale: Option<i32>,
+) -> DataType {
+    if data_type == "ARRAY" {
+        let element_udt = udt_name.strip_prefix('_').unwrap_or(udt_name);
+        let element = pg_to_arrow_type("", element_udt, None, None);
+        return DataType::List(Arc::new(Field::new_list_field(element, true)));
+    }
+
+    match udt_name {
+        "int2" => DataType::Int16,
+        "int4" => DataType::Int32,
+        "int8" => DataType::Int64,
+        "float4" => DataType::Float32,
+        "float8" => DataType::Float64,
+        "numeric" => numeric_type(precision, scale),
+        "bool" => DataType::Boolean,
+        "bytea" => DataType::Binary,
+        "date" => DataType::Date32,
+        "time" => DataType::Time64(TimeUnit::Microsecond),
+        "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, None),
+        "timestamptz" => {
+            DataType::Timestamp(TimeUnit::Microsecond, Some(TIMESTAMPTZ_TIME_ZONE.into()))
+        }
+        // text, varchar, bpchar (char(n)), name, uuid, json, jsonb, timetz, enums, ...
+        // `interval` is kept as text too: Parquet can only store millisecond intervals.
+        _ => DataType::Utf8,
+    }
+}
+
+fn numeric_type(precision: Option<i32>, scale: Option<i32>) -> DataType {
+    let Some(precision) = precision.and_then(|p| u8::try_from(p).ok()) else {
+        return DataType::Decimal256(UNCONSTRAINED_NUMERIC_PRECISION, UNCONSTRAINED_NUMERIC_SCALE);
+    };
+    let scale = scale.and_then(|s| i8::try_from(s).ok()).unwrap_or(0);
+    if precision <= DECIMAL128_MAX_PRECISION {
+        DataType::Decimal128(precision, scale)
+    } else if precision <= DECIMAL256_MAX_PRECISION {
+        DataType::Decimal256(precision, scale)
+    } else {
+        // wider than any Arrow decimal; keep every digit as text
+        DataType::Utf8
+    }
+}
+
+/// Returns the expression used to select column `name` so that it decodes as `data_type`.
+///
+/// Text-like and decimal columns are read through their text representation, which works for
+/// every Postgres type and keeps `numeric` values exact.  The column name is quoted.
+#[must_use]
+pub fn select_expr(name: &str, data_type: &DataType) -> String {
+    let name = quote_ident(name);
+    match data_type {
+        DataType::Utf8 | DataType::Decimal128(..) | DataType::Decimal256(..) => {
+            format!("{name}::text AS {name}")
+        }
+        DataType::List(field) if is_text_decoded(field.data_type()) => {
+            format!("{name}::text[] AS {name}")
+        }
+        _ => name,
+    }
+}
+
+const fn is_text_decoded(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Utf8 | DataType::Decimal128(..) | DataType::Decimal256(..)
+    )
+}
+
+/// Splits the text form of a Postgres `numeric` into its sign and the unscaled digits at
+/// `scale`, rounding half away from zero when digits have to be dropped.
+fn unscaled_digits(text: &str, scale: i8) -> Result<(bool, String, bool)> {
+    let negative = text.starts_with('-');
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (int_part, frac_part) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if (int_part.is_empty() && frac_part.is_empty())
+        || !int_part
+            .bytes()
+            .chain(frac_part.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        bail!("Cannot convert numeric value `{text}` to a decimal");
+    }
+
+    let mut digits = int_part.to_string();
+    let mut round_up = false;
+    if scale >= 0 {
+        let scale = scale.unsigned_abs() as usize;
+        if frac_part.len() > scale {
+            digits.push_str(&frac_part[..scale]);
+            round_up = frac_part.as_bytes()[scale] >= b'5';
+        } else {
+            digits.push_str(frac_part);
+            digits.push_str(&"0".repeat(scale - frac_part.len()));
+        }
+    } else {
+        let drop = scale.unsigned_abs() as usize;
+        if digits.len() > drop {
+            round_up = digits.as_bytes()[digits.len() - drop] >= b'5';
+            digits.truncate(digits.len() - drop);
+        } else {
+            round_up = digits.len() == drop && digits.as_bytes()[0] >= b'5';
+            digits.clear();
+        }
+    }
+    if digits.is_empty() {
+        digits.push('0');
+    }
+    Ok((negative, digits, round_up))
+}
+
+/// Parses the text form of a Postgres `numeric` into an unscaled `Decimal128` value.
+///
+/// # Errors
+///
+/// Will return `Err` for `NaN`/`Infinity` or values that do not fit in 128 bits.
+pub fn parse_decimal128(text: &str, scale: i8) -> Result<i128> {
+    let (negative, digits, round_up) = unscaled_digits(text, scale)?;
+    let overflow = || anyhow::anyhow!("numeric value `{text}` does not fit in Decimal128");
+    let mut value: i128 = digits.parse().map_err(|_| overflow())?;
+    if round_up {
+        value = value.checked_add(1).ok_or_else(overflow)?;
+    }
+    Ok(if negative { -value } else { value })
+}
+
+/// Parses the text form of a Postgres `numeric` into an unscaled `Decimal256` value.
+///
+/// # Errors
+///
+/// Will return `Err` for `NaN`/`Infinity` or values that do not fit in 256 bits.
+pub fn parse_decimal256(text: &str, scale: i8) -> Result<i256> {
+    let (negative, digits, round_up) = unscaled_digits(text, scale)?;
+    let overflow = || anyhow::anyhow!("numeric value `{text}` does not fit in Decimal256");
+    let mut value = i256::from_string(&digits).ok_or_else(overflow)?;
+    if round_up {
+        value = value.checked_add(i256::ONE).ok_or_else(overflow)?;
+    }
+    Ok(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::text::text_to_array;
+    use arrow::array::AsArray;
+    use arrow::datatypes::Decimal256Type;
+
+    #[test]
+    fn test_scalar_types() {
+        assert_eq!(
+            pg_to_arrow_type("smallint", "int2", None, None),
+            DataType::Int16
+        );
+        assert_eq!(
+            pg_to_arrow_type("integer", "int4", None, None),
+            DataType::Int32
+        );
+        assert_eq!(
+            pg_to_arrow_type("bigint", "int8", None, None),
+            DataType::Int64
+        );
+        assert_eq!(
+            pg_to_arrow_type("real", "float4", None, None),
+            DataType::Float32
+        );
+        assert_eq!(
+            pg_to_arrow_type("double precision", "float8", None, None),
+            DataType::Float64
+        );
+        assert_eq!(
+            pg_to_arrow_type("boolean", "bool", None, None),
+            DataType::Boolean
+        );
+        assert_eq!(
+            pg_to_arrow_type("bytea", "bytea", None, None),
+            DataType::Binary
+        );
+        assert_eq!(
+            pg_to_arrow_type("date", "date", None, None),
+            DataType::Date32
+        );
+        assert_eq!(
+            pg_to_arrow_type("time without time zone", "time", None, None),
+            DataType::Time64(TimeUnit::Microsecond)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_types() {
+        assert_eq!(
+            pg_to_arrow_type("timestamp without time zone", "timestamp", None, None),
+            DataType::Timestamp(TimeUnit::Microsecond, None)
+        );
+        assert_eq!(
+            pg_to_arrow_type("timestamp with time zone", "timestamptz", None, None),
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        );
+    }
+
+    #[test]
+    fn test_text_like_types() {
+        for (data_type, udt) in [
+            ("text", "text"),
+            ("character varying", "varchar"),
+            ("character", "bpchar"),
+            ("uuid", "uuid"),
+            ("json", "json"),
+            ("jsonb", "jsonb"),
+            ("interval", "interval"),
+            ("USER-DEFINED", "mood"),
+        ] {
+            assert_eq!(pg_to_arrow_type(data_type, udt, None, None), DataType::Utf8);
+        }
+    }
+
+    #[test]
+    fn test_numeric_types() {
+        assert_eq!(
+            pg_to_arrow_type("numeric", "numeric", Some(10), Some(2)),
+            DataType::Decimal128(10, 2)
+        );
+        assert_eq!(
+            pg_to_arrow_type("numeric", "numeric", Some(50), Some(4)),
+            DataType::Decimal256(50, 4)
+        );
+        assert_eq!(
+            pg_to_arrow_type("numeric", "numeric", None, None),
+            DataType::Decimal256(UNCONSTRAINED_NUMERIC_PRECISION, UNCONSTRAINED_NUMERIC_SCALE)
+        );
+        assert_eq!(
+            pg_to_arrow_type("numeric", "numeric", Some(100), Some(0)),
+            DataType::Utf8
+        );
+
+        // values of 10^28 and more fit, keeping the first 20 fractional digits
+        let field = Field::new(
+            "n",
+            pg_to_arrow_type("numeric", "numeric", None, None),
+            true,
+        );
+        let large = "123456789012345678901234567890123.0123456789012345678912";
+        let array = text_to_array(&[Some(large)], &field).unwrap();
+        assert_eq!(
+            array.as_primitive::<Decimal256Type>().value_as_string(0),
+            "123456789012345678901234567890123.01234567890123456789"
+        );
+        let too_large = format!("1{}", "0".repeat(56));
+        assert!(text_to_array(&[Some(&too_large)], &field).is_err());
+    }
+
+    #[test]
+    fn test_array_types() {
+        let list_of = |dt| DataType::List(Arc::new(Field::new_list_field(dt, true)));
+        assert_eq!(
+            pg_to_arrow_type("ARRAY", "_int4", None, None),
+            list_of(DataType::Int32)
+        );
+        assert_eq!(
+            pg_to_arrow_type("ARRAY", "_text", None, None),
+            list_of(DataType::Utf8)
+        );
+        assert_eq!(
+            pg_to_arrow_type("ARRAY", "_uuid", None, None),
+            list_of(DataType::Utf8)
+        );
+        assert_eq!(
+            pg_to_arrow_type("ARRAY", "_bytea", None, None),
+            list_of(DataType::Binary)
+        );
+    }
+
+    #[test]
+    fn test_select_expr() {
+        assert_eq!(select_expr("id", &DataType::Int32), r#""id""#);
+        assert_eq!(
+            select_expr("Doc", &DataType::Utf8),
+            r#""Doc"::text AS "Doc""#
+        );
+        assert_eq!(
+            select_expr("price", &DataType::Decimal128(10, 2)),
+            r#""price"::text AS "price""#
+        );
+        let tags = pg_to_arrow_type("ARRAY", "_uuid", None, None);
+        assert_eq!(select_expr("tags", &tags), r#""tags"::text[] AS "tags""#);
+        let ids = pg_to_arrow_type("ARRAY", "_int8", None, None);
+        assert_eq!(select_expr("order", &ids), r#""order""#);
+    }
+
+    #[test]
+    fn test_parse_decimal128() {
+        assert_eq!(parse_decimal128("123.45", 2).unwrap(), 12345);
+        assert_eq!(parse_decimal128("-123.45", 2).unwrap(), -12345);
+        assert_eq!(parse_decimal128("7", 3).unwrap(), 7000);
+        assert_eq!(parse_decimal128("0.125", 2).unwrap(), 13);
+        assert_eq!(parse_decimal128("-0.125", 2).unwrap(), -13);
+        assert_eq!(parse_decimal128("0.124", 2).unwrap(), 12);
+        assert_eq!(parse_decimal128("1250", -2).unwrap(), 13);
+        assert!(parse_decimal128("NaN", 2).is_err());
+        assert!(parse_decimal128("Infinity", 2).is_err());
+        assert!(parse_decimal128(&"9".repeat(40), 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_decimal256() {
+        let big = format!("{}.5", "9".repeat(45));
+        let parsed = parse_decimal256(&big, 1).unwrap();
+        assert_eq!(parsed.to_string(), format!("{}5", "9".repeat(45)));
+        assert_eq!(
+            parse_decimal256("-1.005", 2).unwrap(),
+            i256::from_i128(-101)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+///
+/// High-water marks for incremental extraction
+///
+use anyhow::{Result, bail};
+use arrow::{
+    array::{Array, AsArray},
+    compute::max,
+    datatypes::{
+        DataType, Date32Type, Int16Type, Int32Type, Int64Type, TimeUnit, TimestampMicrosecondType,
+    },
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    Postgres, Row,
+    postgres::{PgArguments, PgRow},
+    query::Query,
+};
+
+/// The largest value seen so far in a monotonically increasing column (a serial id, an
+/// `updated_at` timestamp, ...).  The next incremental run only reads rows beyond it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Watermark {
+    Int(i64),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<Utc>),
+    Date(NaiveDate),
+}
+
+impl Watermark {
+    /// Whether values of `data_type` can be used as watermarks.
+    #[must_use]
+    pub const fn supports(data_type: &DataType) -> bool {
+        matches!(
+            data_type,
+            DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::Timestamp(TimeUnit::Microsecond, _)
+                | DataType::Date32
+        )
+    }
+
+    /// Returns the largest non-null value of `array`, or `None` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the column type cannot be used as a watermark.
+    pub fn max_in(array: &dyn Array) -> Result<Option<Self>> {
+        Ok(match array.data_type() {
+            DataType::Int16 => max(array.as_primitive::<Int16Type>()).map(|v| Self::Int(v.into())),
+            DataType::Int32 => max(array.as_primitive::<Int32Type>()).map(|v| Self::Int(v.into())),
+            DataType::Int64 => max(array.as_primitive::<Int64Type>()).map(Self::Int),
+            DataType::Timestamp(TimeUnit::Microsecond, tz) => {
+                let Some(micros) = max(array.as_primitive::<TimestampMicrosecondType>()) else {
+                    return Ok(None);
+                };
+                let Some(instant) = DateTime::from_timestamp_micros(micros) else {
+                    bail!("Timestamp {micros} is out of range for a watermark");
+                };
+                Some(if tz.is_some() {
+                    Self::TimestampTz(instant)
+                } else {
+                    Self::Timestamp(instant.naive_utc())
+                })
+            }
+            DataType::Date32 => max(array.as_primitive::<Date32Type>())
+                .and_then(Date32Type::to_naive_date_opt)
+                .map(Self::Date),
+            other => bail!(
+                "Unsupported watermark column type {other}; use an integer, date or timestamp column"
+            ),
+        })
+    }
+
+    /// Reads column `name` of `row`, which holds values of `data_type`.
+    pub(crate) fn decode(row: &PgRow, name: &str, data_type: &DataType) -> Result<Option<Self>> {
+        Ok(match data_type {
+            DataType::Int16 => row
+                .try_get::<Option<i16>, _>(name)?
+                .map(|v| Self::Int(v.into())),
+            DataType::Int32 => row
+                .try_get::<Option<i32>, _>(name)?
+                .map(|v| Self::Int(v.into())),
+            DataType::Int64 => row.try_get::<Option<i64>, _>(name)?.map(Self::Int),
+            DataType::Timestamp(TimeUnit::Microsecond, None) => row
+                .try_get::<Option<NaiveDateTime>, _>(name)?
+                .map(Self::Timestamp),
+            DataType::Timestamp(TimeUnit::Microsecond, Some(_)) => row
+                .try_get::<Option<DateTime<Utc>>, _>(name)?
+                .map(Self::TimestampTz),
+            DataType::Date32 => row.try_get::<Option<NaiveDate>, _>(name)?.map(Self::Date),
+            other => bail!("Unsupported watermark column type {other}"),
+        })
+    }
+
+    /// Splits `[lo, hi]` into `parts` ranges of roughly equal width and returns the boundaries
+    /// between them, in increasing order and without duplicates.
+    pub(crate) fn split(lo: &Self, hi: &Self, parts: usize) -> Vec<Self> {
+        let (lo_ordinal, hi_ordinal) = (i128::from(lo.ordinal()), i128::from(hi.ordinal()));
+        let Ok(parts_wide) = i128::try_from(parts) else {
+            return Vec::new();
+        };
+        let mut boundaries: Vec<Self> = Vec::new();
+        for i in 1..parts_wide {
+            let ordinal = lo_ordinal + (hi_ordinal - lo_ordinal) * i / parts_wide;
+            let Some(boundary) = i64::try_from(ordinal)
+                .ok()
+                .and_then(|ordinal| lo.with_ordinal(ordinal))
+            else {
+                continue;
+            };
+            if boundary > *lo && boundaries.last().is_none_or(|last| boundary > *last) {
+                boundaries.push(boundary);
+            }
+        }
+        boundaries
+    }
+
+    /// The value as a number: integers as is, timestamps in microseconds and dates in days.
+    fn ordinal(&self) -> i64 {
+        match self {
+            Self::Int(v) => *v,
+            Self::Timestamp(v) => v.and_utc().timestamp_micros(),
+            Self::TimestampTz(v) => v.timestamp_micros(),
+            Self::Date(v) => Date32Type::from_naive_date(*v).into(),
+        }
+    }
+
+    /// A value of the same kind as `self` from its ordinal.
+    fn with_ordinal(&self, ordinal: i64) -> Option<Self> {
+        Some(match self {
+            Self::Int(_) => Self::Int(ordinal),
+            Self::Timestamp(_) => {
+                Self::Timestamp(DateTime::from_timestamp_micros(ordinal)?.naive_utc())
+            }
+            Self::TimestampTz(_) => Self::TimestampTz(DateTime::from_timestamp_micros(ordinal)?),
+            Self::Date(_) => {
+                Self::Date(Date32Type::to_naive_date_opt(i32::try_from(ordinal).ok()?)?)
+            }
+        })
+    }
+
+    /// Binds the watermark as the next query parameter, with its native Postgres type.
+    pub(crate) fn bind<'q>(
+        &self,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        match self {
+            Self::Int(v) => query.bind(*v),
+            Self::Timestamp(v) => query.bind(*v),
+            Self::TimestampTz(v) => query.bind(*v),
+            Self::Date(v) => query.bind(*v),
+        }
+    }
+
+    /// Writes the watermark as a typed SQL literal, for statements such as `COPY` that cannot
+    /// take parameters.
+    pub(crate) fn literal(&self) -> String {
+        match self {
+            Self::Int(v) => v.to_string(),
+            Self::Timestamp(v) => format!("'{}'::timestamp", v.format("%Y-%m-%d %H:%M:%S%.6f")),
+            Self::TimestampTz(v) => {
+                format!("'{}'::timestamptz", v.format("%Y-%m-%d %H:%M:%S%.6f+00"))
+            }
+            Self::Date(v) => format!("'{}'::date", v.format("%Y-%m-%d")),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use arrow::array::{Date32Array, Int32Array, StringArray, TimestampMicrosecondArray};
+
+    #[test]
+    fn test_max_in_integers() {
+        let array = Int32Array::from(vec![Some(3), None, Some(9), Some(1)]);
+        assert_eq!(Watermark::max_in(&array).unwrap(), Some(Watermark::Int(9)));
+        let empty = Int32Array::from(vec![None, None]);
+        assert_eq!(Watermark::max_in(&empty).unwrap(), None);
+    }
+
+    #[test]
+    fn test_max_in_timestamps() {
+        let naive = TimestampMicrosecondArray::from(vec![1_000_000, 5_000_000]);
+        assert_eq!(
+            Watermark::max_in(&naive).unwrap(),
+            Some(Watermark::Timestamp(
+                DateTime::from_timestamp(5, 0).unwrap().naive_utc()
+            ))
+        );
+        let aware = naive.with_timezone("UTC");
+        assert_eq!(
+            Watermark::max_in(&aware).unwrap(),
+            Some(Watermark::TimestampTz(
+                DateTime::from_timestamp(5, 0).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_max_in_dates() {
+        let array = Date32Array::from(vec![0, 31]);
+        assert_eq!(
+            Watermark::max_in(&array).unwrap(),
+            Some(Watermark::Date(
+                NaiveDate::from_ymd_opt(1970, 2, 1).unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_max_in_unsupported() {
+        let array = StringArray::from(vec!["a"]);
+        assert!(Watermark::max_in(&array).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let bounds = Watermark::split(&Watermark::Int(0), &Watermark::Int(100), 4);
+        assert_eq!(
+            bounds,
+            [Watermark::Int(25), Watermark::Int(50), Watermark::Int(75)]
+        );
+        // a narrow range yields fewer, distinct boundaries
+        let bounds = Watermark::split(&Watermark::Int(1), &Watermark::Int(3), 8);
+        assert_eq!(bounds, [Watermark::Int(2)]);
+        assert!(Watermark::split(&Watermark::Int(5), &Watermark::Int(5), 4).is_empty());
+        // no overflow across the whole i64 range
+        let bounds = Watermark::split(&Watermark::Int(i64::MIN), &Watermark::Int(i64::MAX), 2);
+        assert_eq!(bounds, [Watermark::Int(-1)]);
+
+        let day = |d| Watermark::Date(NaiveDate::from_ymd_opt(2026, 1, d).unwrap());
+        assert_eq!(Watermark::split(&day(1), &day(11), 2), [day(6)]);
+    }
+
+    #[test]
+    fn test_supports() {
+        assert!(Watermark::supports(&DataType::Int64));
+        assert!(Watermark::supports(&DataType::Timestamp(
+            TimeUnit::Microsecond,
+            Some("UTC".into())
+        )));
+        assert!(!Watermark::supports(&DataType::Float64));
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        for watermark in [
+            Watermark::Int(42),
+            Watermark::Timestamp(DateTime::from_timestamp(7, 0).unwrap().naive_utc()),
+            Watermark::TimestampTz(DateTime::from_timestamp(7, 0).unwrap()),
+            Watermark::Date(NaiveDate::from_ymd_opt(2026, 10, 1).unwrap()),
+        ] {
+            let json = serde_json::to_string(&watermark).unwrap();
+            assert_eq!(serde_json::from_str::<Watermark>(&json).unwrap(), watermark);
+        }
+        assert_eq!(
+            serde_json::to_string(&Watermark::Int(42)).unwrap(),
+            r#"{"type":"int","value":42}"#
+        );
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(Watermark::Int(-3).literal(), "-3");
+        assert_eq!(
+            Watermark::Timestamp(DateTime::from_timestamp(7, 5_000).unwrap().naive_utc()).literal(),
+            "'1970-01-01 00:00:07.000005'::timestamp"
+        );
+        assert_eq!(
+            Watermark::TimestampTz(DateTime::from_timestamp(7, 0).unwrap()).literal(),
+            "'1970-01-01 00:00:07.000000+00'::timestamptz"
+        );
+        assert_eq!(
+            Watermark::Date(NaiveDate::from_ymd_opt(2026, 10, 1).unwrap()).literal(),
+            "'2026-10-01'::date"
+        );
+    }
+}